@@ -1,4 +1,6 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
+
+use crate::symbol::Symbol;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Error {
@@ -25,12 +27,229 @@ impl Display for Error {
         )
     }
 }
-pub(crate) mod scanner;
 pub(crate) mod parser;
+pub(crate) mod reachability;
+pub(crate) mod scanner;
+mod unicode;
+
+/// Where a node is in the source: the bytes from `start` up to `end`, and
+/// the line it starts on, which errors about the node are reported at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) line: usize,
+}
+impl Span {
+    /// From the start of this span to the end of `other`.
+    pub(crate) fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+        }
+    }
+}
+
+/// An expression or statement in an [`Ast`], for later passes to key side
+/// tables by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct NodeId(u32);
+impl NodeId {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+pub(crate) enum Node {
+    Expression(Expression),
+    Statement(Statement),
+}
+impl From<Expression> for Node {
+    fn from(value: Expression) -> Self {
+        Self::Expression(value)
+    }
+}
+impl From<Statement> for Node {
+    fn from(value: Statement) -> Self {
+        Self::Statement(value)
+    }
+}
+
+/// Every node of a parsed source, each with its span. Children are added
+/// before their parents and nothing is ever removed, so ids stay valid as
+/// long as the tree is around.
+#[derive(Default)]
+pub(crate) struct Ast {
+    nodes: Vec<Node>,
+    spans: Vec<Span>,
+}
+impl Ast {
+    fn push(&mut self, node: Node, span: Span) -> NodeId {
+        self.nodes.push(node);
+        self.spans.push(span);
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+    pub(crate) fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.index()]
+    }
+    pub(crate) fn expression(&self, id: NodeId) -> &Expression {
+        match self.node(id) {
+            Node::Expression(expression) => expression,
+            Node::Statement(_) => panic!("{id:?} is a statement"),
+        }
+    }
+    pub(crate) fn statement(&self, id: NodeId) -> &Statement {
+        match self.node(id) {
+            Node::Statement(statement) => statement,
+            Node::Expression(_) => panic!("{id:?} is an expression"),
+        }
+    }
+    pub(crate) fn span(&self, id: NodeId) -> Span {
+        self.spans[id.index()]
+    }
+    /// Prints the node as an S-expression.
+    pub(crate) fn display(&self, id: NodeId) -> impl Display + '_ {
+        Shown { ast: self, id }
+    }
+}
+
+struct Shown<'a> {
+    ast: &'a Ast,
+    id: NodeId,
+}
+impl Shown<'_> {
+    fn fmt_function(
+        &self,
+        function: &FunctionDecl,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(f, "(fun {} (", function.name)?;
+        for (i, param) in function.params.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{separator}{param}")?;
+        }
+        write!(f, ")")?;
+        for statement in &function.body {
+            write!(f, " {}", self.ast.display(*statement))?;
+        }
+        write!(f, ")")
+    }
+}
+impl Display for Shown<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ast = self.ast;
+        let show = |id: &NodeId| ast.display(*id);
+        match ast.node(self.id) {
+            Node::Expression(expression) => match expression {
+                Expression::Literal(literal) => literal.fmt(f),
+                Expression::Unary(un) => write!(f, "({} {})", un.operator, show(&un.expression)),
+                Expression::Binary(bin) => {
+                    write!(
+                        f,
+                        "({} {} {})",
+                        bin.operator,
+                        show(&bin.lhs),
+                        show(&bin.rhs)
+                    )
+                }
+                Expression::Grouping(group) => write!(f, "(group {})", show(group)),
+                Expression::ToString(inner) => write!(f, "(str {})", show(inner)),
+                Expression::Variable(variable) => write!(f, "{}", variable.name),
+                Expression::Assign(assign) => {
+                    write!(f, "(= {} {})", assign.name, show(&assign.value))
+                }
+                Expression::Logical(logical) => {
+                    write!(
+                        f,
+                        "({} {} {})",
+                        logical.operator,
+                        show(&logical.lhs),
+                        show(&logical.rhs)
+                    )
+                }
+                Expression::Conditional(conditional) => write!(
+                    f,
+                    "(?: {} {} {})",
+                    show(&conditional.condition),
+                    show(&conditional.then),
+                    show(&conditional.otherwise)
+                ),
+                Expression::Call(call) => {
+                    write!(f, "(call {}", show(&call.callee))?;
+                    for argument in &call.arguments {
+                        write!(f, " {}", show(argument))?;
+                    }
+                    write!(f, ")")
+                }
+                Expression::Get(get) => write!(f, "(. {} {})", show(&get.object), get.name),
+                Expression::Set(set) => {
+                    write!(
+                        f,
+                        "(= (. {} {}) {})",
+                        show(&set.object),
+                        set.name,
+                        show(&set.value)
+                    )
+                }
+                Expression::This => "this".fmt(f),
+                Expression::Super(sup) => write!(f, "(super {})", sup.method),
+            },
+            Node::Statement(statement) => match statement {
+                Statement::Expression(expression) => write!(f, "(; {})", show(expression)),
+                Statement::Print(expression) => write!(f, "(print {})", show(expression)),
+                Statement::Var(VarStmt {
+                    name,
+                    initializer: Some(initializer),
+                }) => write!(f, "(var {name} {})", show(initializer)),
+                Statement::Var(var) => write!(f, "(var {})", var.name),
+                Statement::Block(statements) => {
+                    write!(f, "(block")?;
+                    for statement in statements {
+                        write!(f, " {}", show(statement))?;
+                    }
+                    write!(f, ")")
+                }
+                Statement::If(IfStmt {
+                    condition,
+                    then,
+                    otherwise: Some(otherwise),
+                }) => write!(
+                    f,
+                    "(if {} {} {})",
+                    show(condition),
+                    show(then),
+                    show(otherwise)
+                ),
+                Statement::If(stmt) => {
+                    write!(f, "(if {} {})", show(&stmt.condition), show(&stmt.then))
+                }
+                Statement::While(stmt) => {
+                    write!(f, "(while {} {})", show(&stmt.condition), show(&stmt.body))
+                }
+                Statement::Function(function) => self.fmt_function(function, f),
+                Statement::Return(ReturnStmt {
+                    value: Some(value), ..
+                }) => write!(f, "(return {})", show(value)),
+                Statement::Return(_) => write!(f, "(return)"),
+                Statement::Class(class) => {
+                    write!(f, "(class {}", class.name)?;
+                    if let Some(superclass) = class.superclass {
+                        write!(f, " < {}", show(&superclass))?;
+                    }
+                    for method in &class.methods {
+                        write!(f, " {}", show(method))?;
+                    }
+                    write!(f, ")")
+                }
+            },
+        }
+    }
+}
 
 pub(crate) enum LiteralExpr {
-    Number(Box<str>),
-    String(Box<str>),
+    Number(f64),
+    String(Symbol),
     True,
     False,
     Nil,
@@ -47,66 +266,133 @@ impl Display for LiteralExpr {
     }
 }
 pub(crate) struct UnaryExpr {
-    operator: &'static str,
-    expression: Box<Expression>,
+    /// `-` or `!`.
+    pub(crate) operator: &'static str,
+    pub(crate) expression: NodeId,
 }
-impl Display for UnaryExpr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({} {})", self.operator, self.expression)
-    }
+pub(crate) struct BinaryExpr {
+    pub(crate) lhs: NodeId,
+    pub(crate) operator: &'static str,
+    pub(crate) rhs: NodeId,
 }
-impl UnaryExpr {
-    pub(crate) fn new<T: Into<Expression>>(operator: &'static str, expression: T) -> Self {
-        let expression = Box::new(expression.into());
-        Self { operator, expression }
-    }
+pub(crate) struct VariableExpr {
+    pub(crate) name: Symbol,
 }
-pub(crate) struct BinaryExpr {
-    lhs: Box<Expression>,
-    operator: &'static str,
-    rhs: Box<Expression>,
+pub(crate) struct AssignExpr {
+    pub(crate) name: Symbol,
+    pub(crate) value: NodeId,
 }
-impl Display for BinaryExpr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-write!(f, "({} {} {})", self.operator, self.lhs, self.rhs)
-    }
+/// `and` and `or`, which only evaluate `rhs` if `lhs` doesn't decide the
+/// result.
+pub(crate) struct LogicalExpr {
+    pub(crate) lhs: NodeId,
+    pub(crate) operator: &'static str,
+    pub(crate) rhs: NodeId,
 }
-impl<'a> BinaryExpr {
-    pub(crate) fn new<T: Into<Expression>, U: Into<Expression>>(lhs: T, operator: &'static str, rhs: U) -> Self {
-        let lhs = Box::new(lhs.into());
-        let rhs = Box::new(rhs.into());
-        Self { lhs, operator, rhs }
-    }
+/// `condition ? then : otherwise`, which only evaluates one of the
+/// branches.
+pub(crate) struct ConditionalExpr {
+    pub(crate) condition: NodeId,
+    pub(crate) then: NodeId,
+    pub(crate) otherwise: NodeId,
+}
+pub(crate) struct CallExpr {
+    pub(crate) callee: NodeId,
+    pub(crate) arguments: Vec<NodeId>,
+}
+pub(crate) struct GetExpr {
+    pub(crate) object: NodeId,
+    pub(crate) name: Symbol,
+}
+pub(crate) struct SetExpr {
+    pub(crate) object: NodeId,
+    pub(crate) name: Symbol,
+    pub(crate) value: NodeId,
+}
+/// `super.method`.
+pub(crate) struct SuperExpr {
+    pub(crate) method: Symbol,
 }
 pub(crate) enum Expression {
     Literal(LiteralExpr),
     Unary(UnaryExpr),
     Binary(BinaryExpr),
-    Grouping(Box<Expression>),
-}
-
-impl Display for Expression {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Literal(l) => l.fmt(f),
-            Self::Unary(un) => un.fmt(f),
-            Self::Binary(bin) => bin.fmt(f),
-            Self::Grouping(group) => write!(f, "(group {group})"),
-        }
-    }
+    Grouping(NodeId),
+    /// The string value of an expression embedded in a string.
+    ToString(NodeId),
+    Variable(VariableExpr),
+    Assign(AssignExpr),
+    Logical(LogicalExpr),
+    Conditional(ConditionalExpr),
+    Call(CallExpr),
+    Get(GetExpr),
+    Set(SetExpr),
+    This,
+    Super(SuperExpr),
 }
 impl From<LiteralExpr> for Expression {
     fn from(arg: LiteralExpr) -> Self {
         Self::Literal(arg)
     }
 }
-impl<'a> From<BinaryExpr> for Expression {
-    fn from(value: BinaryExpr) -> Self {
-        Self::Binary(value)
-    }
+
+pub(crate) struct VarStmt {
+    pub(crate) name: Symbol,
+    pub(crate) initializer: Option<NodeId>,
+}
+pub(crate) struct IfStmt {
+    pub(crate) condition: NodeId,
+    pub(crate) then: NodeId,
+    pub(crate) otherwise: Option<NodeId>,
+}
+/// `for` loops are parsed into these too.
+pub(crate) struct WhileStmt {
+    pub(crate) condition: NodeId,
+    pub(crate) body: NodeId,
+}
+/// A function or method. Shared, closures made from it outlive the tree.
+pub(crate) struct FunctionDecl {
+    pub(crate) name: Symbol,
+    pub(crate) params: Vec<Symbol>,
+    pub(crate) body: Vec<NodeId>,
 }
-impl<'a> From<UnaryExpr> for Expression {
-    fn from(value: UnaryExpr) -> Self {
-        Self::Unary(value)
+pub(crate) struct ReturnStmt {
+    pub(crate) value: Option<NodeId>,
+}
+pub(crate) struct ClassDecl {
+    pub(crate) name: Symbol,
+    /// A [`VariableExpr`].
+    pub(crate) superclass: Option<NodeId>,
+    /// [`Statement::Function`]s.
+    pub(crate) methods: Vec<NodeId>,
+}
+pub(crate) enum Statement {
+    Expression(NodeId),
+    Print(NodeId),
+    Var(VarStmt),
+    Block(Vec<NodeId>),
+    If(IfStmt),
+    While(WhileStmt),
+    Function(Rc<FunctionDecl>),
+    Return(ReturnStmt),
+    Class(ClassDecl),
+}
+
+/// A whole script or REPL entry, with the tree its statements are in.
+pub(crate) struct Program {
+    pub(crate) ast: Rc<Ast>,
+    pub(crate) statements: Vec<NodeId>,
+}
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for statement in &self.statements {
+            writeln!(f, "{}", self.ast.display(*statement))?;
+        }
+        Ok(())
     }
 }
+/// A lone expression, for the REPL to print the value of.
+pub(crate) struct ExpressionTree {
+    pub(crate) ast: Rc<Ast>,
+    pub(crate) root: NodeId,
+}
@@ -1,196 +1,1008 @@
-use std::str::FromStr;
+use std::{collections::HashMap, rc::Rc, str::FromStr};
+
+use crate::symbol::Symbol;
 
 use super::{
-    scanner::{Scanner, Token, TokenType},
-    BinaryExpr, Error, Expression, UnaryExpr,
+    scanner::{Token, TokenStream, TokenType},
+    AssignExpr, Ast, BinaryExpr, CallExpr, ClassDecl, ConditionalExpr, Error, Expression,
+    ExpressionTree, FunctionDecl, GetExpr, IfStmt, LiteralExpr, LogicalExpr, Node, NodeId, Program,
+    ReturnStmt, SetExpr, Span, Statement, SuperExpr, UnaryExpr, VarStmt, VariableExpr, WhileStmt,
 };
 
+/// Functions and calls take at most this many parameters and arguments.
+const MAX_ARGUMENTS: usize = 255;
+/// How deeply the parser's recursive functions can be nested in each
+/// other, so deeply nested input is an error instead of a stack overflow.
+const MAX_NESTING: usize = 256;
+
+/// How tightly an infix operator binds, loosest first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Assignment,
+    Conditional,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+impl Precedence {
+    /// The level binding one step more tightly.
+    fn tighter(self) -> Self {
+        match self {
+            Self::Assignment => Self::Conditional,
+            Self::Conditional => Self::Or,
+            Self::Or => Self::And,
+            Self::And => Self::Equality,
+            Self::Equality => Self::Comparison,
+            Self::Comparison => Self::Term,
+            Self::Term => Self::Factor,
+            Self::Factor => Self::Unary,
+            Self::Unary | Self::Call => Self::Call,
+        }
+    }
+}
+#[derive(Clone, Copy)]
+enum Associativity {
+    Left,
+    Right,
+}
+/// What an infix operator builds from the expression before it.
+#[derive(Clone, Copy)]
+enum Infix {
+    Binary(&'static str),
+    Logical(&'static str),
+    Assign,
+    /// `condition ? then : otherwise`
+    Conditional,
+    Call,
+    Property,
+}
+struct Rule {
+    token: TokenType,
+    precedence: Precedence,
+    associativity: Associativity,
+    infix: Infix,
+}
+const fn rule(
+    token: TokenType,
+    precedence: Precedence,
+    associativity: Associativity,
+    infix: Infix,
+) -> Rule {
+    Rule {
+        token,
+        precedence,
+        associativity,
+        infix,
+    }
+}
+/// Every infix operator.
+const INFIX: &[Rule] = {
+    use Associativity::{Left, Right};
+    use Precedence as P;
+    use TokenType as T;
+    &[
+        rule(T::Equal, P::Assignment, Right, Infix::Assign),
+        rule(T::Question, P::Conditional, Right, Infix::Conditional),
+        rule(T::Or, P::Or, Left, Infix::Logical("or")),
+        rule(T::And, P::And, Left, Infix::Logical("and")),
+        rule(T::EqualEqual, P::Equality, Left, Infix::Binary("==")),
+        rule(T::BangEqual, P::Equality, Left, Infix::Binary("!=")),
+        rule(T::Greater, P::Comparison, Left, Infix::Binary(">")),
+        rule(T::GreaterEqual, P::Comparison, Left, Infix::Binary(">=")),
+        rule(T::Less, P::Comparison, Left, Infix::Binary("<")),
+        rule(T::LessEqual, P::Comparison, Left, Infix::Binary("<=")),
+        rule(T::Plus, P::Term, Left, Infix::Binary("+")),
+        rule(T::Minus, P::Term, Left, Infix::Binary("-")),
+        rule(T::Star, P::Factor, Left, Infix::Binary("*")),
+        rule(T::Slash, P::Factor, Left, Infix::Binary("/")),
+        rule(T::Percent, P::Factor, Left, Infix::Binary("%")),
+        rule(T::LeftParen, P::Call, Left, Infix::Call),
+        rule(T::Dot, P::Call, Left, Infix::Property),
+    ]
+};
+
+/// What kind of function the parser is in, for checking `return`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+/// What kind of class the parser is in, for checking `this` and `super`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
 pub(crate) struct Parser<'a> {
-    scanner: Scanner<'a>,
+    tokens: TokenStream<'a>,
+    /// Where the last token consumed is, for the spans of the nodes ending
+    /// in it.
+    previous: Span,
+    ast: Ast,
+    /// The local scopes around the current token, innermost last, with
+    /// whether each variable's initializer has been parsed yet. Globals
+    /// aren't tracked, they can be used before they're declared.
+    scopes: Vec<HashMap<Symbol, bool>>,
+    function: FunctionKind,
+    class: ClassKind,
+    /// How many of the recursive functions are being parsed, see
+    /// [`MAX_NESTING`].
+    nesting: usize,
 }
 
 impl<'a> Parser<'a>
 where
     Self: 'a,
 {
-    pub(crate) fn new(source: &'a str) -> Result<Self, Error> {
-        Ok(Parser {
-            scanner: Scanner::new(source),
+    pub(crate) fn new(source: &'a str) -> Self {
+        Parser {
+            tokens: TokenStream::new(source),
+            previous: Span {
+                start: 0,
+                end: 0,
+                line: 1,
+            },
+            ast: Ast::default(),
+            scopes: Vec::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            nesting: 0,
+        }
+    }
+    /// The next token, without consuming it.
+    fn peek(&mut self) -> Result<Token<'a>, Error> {
+        self.tokens.peek().cloned()
+    }
+    /// Consumes the next token.
+    fn advance(&mut self) -> Result<Token<'a>, Error> {
+        let token = self.tokens.advance()?;
+        self.previous = token.span();
+        Ok(token)
+    }
+    fn check(&mut self, id: TokenType) -> Result<bool, Error> {
+        Ok(self.tokens.peek()?.id == id)
+    }
+    /// Consumes the next token if it's an `id`.
+    fn matches(&mut self, id: TokenType) -> Result<Option<Token<'a>>, Error> {
+        if self.check(id)? {
+            self.advance().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+    /// An error at the next token.
+    fn error(&mut self, message: &str) -> Error {
+        match self.tokens.peek() {
+            Ok(token) if token.id == TokenType::Eof => Error::new(message, "end", token.line),
+            Ok(token) => Error::new(message, &format!("'{}'", token.lexeme), token.line),
+            Err(err) => err,
+        }
+    }
+    /// Enters one more level of nesting. Errors end the parse, so only
+    /// successful parses [`leave`](Self::leave) again.
+    fn enter(&mut self) -> Result<(), Error> {
+        if self.nesting == MAX_NESTING {
+            return Err(self.error("Too much nesting."));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+    fn leave(&mut self) {
+        self.nesting -= 1;
+    }
+    /// Adds `node` to the tree, spanning from the start of `from` to the
+    /// last token consumed.
+    fn push(&mut self, node: impl Into<Node>, from: Span) -> NodeId {
+        let span = from.to(self.previous);
+        self.ast.push(node.into(), span)
+    }
+    fn program(&mut self) -> Result<Program, Error> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::Eof)? {
+            statements.push(self.declaration()?);
+        }
+        Ok(Program {
+            ast: Rc::new(std::mem::take(&mut self.ast)),
+            statements,
         })
     }
-    fn expression(&mut self) -> Result<Expression, Error> {
-        self.equality()
-    }
-    fn equality(&mut self) -> Result<Expression, Error> {
-        let mut expr = self.comparison()?;
-        while let Some(r) = self.scanner.clone().peekable().peek().map(|r| {
-            r.as_ref()
-                .map(|t| t.id == TokenType::BangEqual || t.id == TokenType::EqualEqual)
-        }) {
-            if match r {
-                Ok(b) => !b,
-                Err(err) => return Err(err.clone()),
-            } {
-                break;
+    fn declaration(&mut self) -> Result<NodeId, Error> {
+        let from = self.tokens.peek()?.span();
+        let declaration = if self.matches(TokenType::Class)?.is_some() {
+            self.class_declaration()?
+        } else if self.matches(TokenType::Fun)?.is_some() {
+            let function = self.function(FunctionKind::Function)?;
+            Statement::Function(function.into())
+        } else if self.matches(TokenType::Var)?.is_some() {
+            self.var_declaration()?
+        } else {
+            return self.statement();
+        };
+        Ok(self.push(declaration, from))
+    }
+    fn class_declaration(&mut self) -> Result<Statement, Error> {
+        let name = self.consume(TokenType::Identifier, "Expect class name.")?;
+        self.declare(&name)?;
+        self.define(&name);
+        let superclass = if self.matches(TokenType::Less)?.is_some() {
+            let superclass = self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            if superclass.symbol() == name.symbol() {
+                return Err(Error::new(
+                    "A class can't inherit from itself.",
+                    &format!("'{}'", superclass.lexeme),
+                    superclass.line,
+                ));
             }
-            let operator = match self.scanner.next().unwrap()?.id {
-                TokenType::EqualEqual => "==",
-                TokenType::BangEqual => "!=",
-                _ => unreachable!(),
+            let variable = Expression::Variable(VariableExpr {
+                name: superclass.symbol(),
+            });
+            Some(self.push(variable, superclass.span()))
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let enclosing = self.class;
+        self.class = if superclass.is_some() {
+            ClassKind::Subclass
+        } else {
+            ClassKind::Class
+        };
+        let mut methods = Vec::new();
+        while !self.check(TokenType::Eof)? && !self.check(TokenType::RightBrace)? {
+            let kind = if self.tokens.peek()?.symbol == Some(Symbol::INIT) {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
             };
-            let right = self.comparison()?;
-            expr = BinaryExpr::new(expr, operator, right).into();
+            let from = self.tokens.peek()?.span();
+            match self.function(kind) {
+                Ok(method) => methods.push(self.push(Statement::Function(method.into()), from)),
+                Err(err) => {
+                    self.class = enclosing;
+                    return Err(err);
+                }
+            }
         }
-        Ok(expr)
+        self.class = enclosing;
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Statement::Class(ClassDecl {
+            name: name.symbol(),
+            superclass,
+            methods,
+        }))
     }
-    fn comparison(&mut self) -> Result<Expression, Error> {
-        let mut expr = self.term()?;
-        while let Some(r) = self.scanner.clone().peekable().peek().map(|r| {
-            r.as_ref().map(|t| match t.id {
-                TokenType::Greater
-                | TokenType::GreaterEqual
-                | TokenType::Less
-                | TokenType::LessEqual => true,
-                _ => false,
-            })
-        }) {
-            if match r {
-                Ok(b) => !b,
-                Err(err) => return Err(err.clone()),
-            } {
-                break;
+    fn function(&mut self, kind: FunctionKind) -> Result<FunctionDecl, Error> {
+        let name = self.consume(TokenType::Identifier, "Expect function name.")?;
+        if kind == FunctionKind::Function {
+            self.declare(&name)?;
+            self.define(&name);
+        }
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
+        let enclosing = std::mem::replace(&mut self.function, kind);
+        self.scopes.push(HashMap::new());
+        let result = self.function_rest(name);
+        self.scopes.pop();
+        self.function = enclosing;
+        result
+    }
+    /// The parameters and body, in the function's scope.
+    fn function_rest(&mut self, name: Token<'a>) -> Result<FunctionDecl, Error> {
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen)? {
+            loop {
+                if params.len() == MAX_ARGUMENTS {
+                    return Err(self.error("Can't have more than 255 parameters."));
+                }
+                let param = self.consume(TokenType::Identifier, "Expect parameter name.")?;
+                self.declare(&param)?;
+                self.define(&param);
+                params.push(param.symbol());
+                if self.matches(TokenType::Comma)?.is_none() {
+                    break;
+                }
             }
-            let operator = match self.scanner.next().unwrap()?.id {
-                TokenType::GreaterEqual => ">=",
-                TokenType::Greater => ">",
-                TokenType::LessEqual => "<=",
-                TokenType::Less => "<",
-                _ => unreachable!(),
-            };
-            let right = self.term()?;
-            expr = BinaryExpr::new(expr, operator, right).into();
         }
-        Ok(expr)
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block_rest()?;
+        Ok(FunctionDecl {
+            name: name.symbol(),
+            params,
+            body,
+        })
     }
-
-    fn term(&mut self) -> Result<Expression, Error> {
-        let mut expr = self.factor()?;
-
-        while let Some(r) = self.scanner.clone().peekable().peek().map(|r| {
-            r.as_ref().map(|t| match t.id {
-                TokenType::Minus | TokenType::Plus => true,
-                _ => false,
-            })
-        }) {
-            if match r {
-                Ok(b) => !b,
-                Err(err) => return Err(err.clone()),
-            } {
-                break;
+    fn var_declaration(&mut self) -> Result<Statement, Error> {
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        self.declare(&name)?;
+        let initializer = if self.matches(TokenType::Equal)?.is_some() {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.define(&name);
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Statement::Var(VarStmt {
+            name: name.symbol(),
+            initializer,
+        }))
+    }
+    /// Adds a local variable to the innermost scope, not yet usable.
+    fn declare(&mut self, name: &Token<'a>) -> Result<(), Error> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.insert(name.symbol(), false).is_some() {
+                return Err(Error::new(
+                    "Already a variable with this name in this scope.",
+                    &format!("'{}'", name.lexeme),
+                    name.line,
+                ));
             }
-            let operator = match self.scanner.next().unwrap()?.id {
-                TokenType::Minus => "-",
-                TokenType::Plus => "+",
-                _ => unreachable!(),
+        }
+        Ok(())
+    }
+    fn define(&mut self, name: &Token<'a>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.symbol(), true);
+        }
+    }
+    fn statement(&mut self) -> Result<NodeId, Error> {
+        self.enter()?;
+        let from = self.tokens.peek()?.span();
+        let statement = if self.matches(TokenType::For)?.is_some() {
+            self.for_statement(from)?
+        } else if self.matches(TokenType::If)?.is_some() {
+            self.if_statement()?
+        } else if self.matches(TokenType::Print)?.is_some() {
+            let value = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+            Statement::Print(value)
+        } else if let Some(keyword) = self.matches(TokenType::Return)? {
+            self.return_statement(keyword)?
+        } else if self.matches(TokenType::While)?.is_some() {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+            let condition = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+            let body = self.statement()?;
+            Statement::While(WhileStmt { condition, body })
+        } else if self.matches(TokenType::LeftBrace)?.is_some() {
+            self.block()?
+        } else {
+            let expression = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+            Statement::Expression(expression)
+        };
+        self.leave();
+        Ok(self.push(statement, from))
+    }
+    /// Parses `for (initializer; condition; increment) body` into a while
+    /// loop in a block, all spanning from the `for`.
+    fn for_statement(&mut self, from: Span) -> Result<Statement, Error> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        self.scopes.push(HashMap::new());
+        let result = self.for_rest(from);
+        self.scopes.pop();
+        result
+    }
+    fn for_rest(&mut self, from: Span) -> Result<Statement, Error> {
+        let initializer = if self.matches(TokenType::Semicolon)?.is_some() {
+            None
+        } else if let Some(var) = self.matches(TokenType::Var)? {
+            let initializer = self.var_declaration()?;
+            Some(self.push(initializer, var.span()))
+        } else {
+            let expression = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after loop initializer.")?;
+            let from = self.ast.span(expression);
+            Some(self.push(Statement::Expression(expression), from))
+        };
+        let condition = if self.check(TokenType::Semicolon)? {
+            // Nothing to span, so an empty span before the `;`.
+            let semicolon = self.tokens.peek()?.span();
+            let span = Span {
+                end: semicolon.start,
+                ..semicolon
             };
-            let right = self.factor()?;
-            expr = BinaryExpr::new(expr, operator, right).into();
+            self.ast
+                .push(Expression::from(LiteralExpr::True).into(), span)
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        let increment = if self.check(TokenType::RightParen)? {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.statement()?;
+        if let Some(increment) = increment {
+            let span = self.ast.span(increment);
+            let increment = self.ast.push(Statement::Expression(increment).into(), span);
+            body = self.push(Statement::Block(vec![body, increment]), from);
         }
-        Ok(expr)
+        let mut statements: Vec<_> = initializer.into_iter().collect();
+        statements.push(self.push(Statement::While(WhileStmt { condition, body }), from));
+        Ok(Statement::Block(statements))
     }
-    fn factor(&mut self) -> Result<Expression, Error> {
-        let mut expr = self.unary()?;
-        while let Some(r) = self.scanner.clone().peekable().peek().map(|r| {
-            r.as_ref().map(|t| match t.id {
-                TokenType::Slash | TokenType::Star => true,
-                _ => false,
-            })
-        }) {
-            if match r {
-                Ok(r) => !r,
-                Err(err) => return Err(err.clone()),
-            } {
+    fn if_statement(&mut self) -> Result<Statement, Error> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        let then = self.statement()?;
+        let otherwise = if self.matches(TokenType::Else)?.is_some() {
+            Some(self.statement()?)
+        } else {
+            None
+        };
+        Ok(Statement::If(IfStmt {
+            condition,
+            then,
+            otherwise,
+        }))
+    }
+    fn return_statement(&mut self, keyword: Token<'a>) -> Result<Statement, Error> {
+        let location = format!("'{}'", keyword.lexeme);
+        if self.function == FunctionKind::None {
+            return Err(Error::new(
+                "Can't return from top-level code.",
+                &location,
+                keyword.line,
+            ));
+        }
+        let value = if self.check(TokenType::Semicolon)? {
+            None
+        } else if self.function == FunctionKind::Initializer {
+            return Err(Error::new(
+                "Can't return a value from an initializer.",
+                &location,
+                keyword.line,
+            ));
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Statement::Return(ReturnStmt { value }))
+    }
+    fn block(&mut self) -> Result<Statement, Error> {
+        self.enter()?;
+        self.scopes.push(HashMap::new());
+        let result = self.block_rest();
+        self.scopes.pop();
+        let block = Statement::Block(result?);
+        self.leave();
+        Ok(block)
+    }
+    /// The statements up to and including the closing brace.
+    fn block_rest(&mut self) -> Result<Vec<NodeId>, Error> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::Eof)? && !self.check(TokenType::RightBrace)? {
+            statements.push(self.declaration()?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+    fn expression(&mut self) -> Result<NodeId, Error> {
+        self.precedence(Precedence::Assignment)
+    }
+    /// An expression whose operators bind at least as tightly as `min`.
+    fn precedence(&mut self, min: Precedence) -> Result<NodeId, Error> {
+        self.enter()?;
+        let mut expr = self.prefix()?;
+        loop {
+            let token = self.peek()?;
+            let Some(rule) = INFIX.iter().find(|rule| rule.token == token.id) else {
+                break;
+            };
+            if rule.precedence < min {
                 break;
             }
-            let operator = match self.scanner.next().unwrap()?.id {
-                TokenType::Slash => "/",
-                TokenType::Star => "*",
-                _ => unreachable!(),
+            self.advance()?;
+            // The right operand of a left associative operator only takes
+            // operators that bind more tightly.
+            let rhs = match rule.associativity {
+                Associativity::Left => rule.precedence.tighter(),
+                Associativity::Right => rule.precedence,
+            };
+            let from = self.ast.span(expr);
+            let infix = match rule.infix {
+                Infix::Binary(operator) => {
+                    let right = self.precedence(rhs)?;
+                    Expression::Binary(BinaryExpr {
+                        lhs: expr,
+                        operator,
+                        rhs: right,
+                    })
+                }
+                Infix::Logical(operator) => Expression::Logical(LogicalExpr {
+                    lhs: expr,
+                    operator,
+                    rhs: self.precedence(rhs)?,
+                }),
+                Infix::Assign => self.assign(expr, &token, rhs)?,
+                Infix::Conditional => {
+                    let then = self.expression()?;
+                    self.consume(
+                        TokenType::Colon,
+                        "Expect ':' after then branch of conditional.",
+                    )?;
+                    Expression::Conditional(ConditionalExpr {
+                        condition: expr,
+                        then,
+                        otherwise: self.precedence(rhs)?,
+                    })
+                }
+                Infix::Call => self.call(expr)?,
+                Infix::Property => {
+                    let name =
+                        self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                    Expression::Get(GetExpr {
+                        object: expr,
+                        name: name.symbol(),
+                    })
+                }
             };
-            let right = self.unary()?;
-            expr = BinaryExpr::new(expr, operator, right).into();
+            expr = self.push(infix, from);
         }
+        self.leave();
         Ok(expr)
     }
-    fn unary(&mut self) -> Result<Expression, Error> {
-        if let Some(r) = self.scanner.clone().peekable().peek().map(|r| {
-            r.as_ref().map(|t| match t.id {
-                TokenType::Bang | TokenType::Minus => true,
-                _ => false,
-            })
-        }) {
-            if match r {
-                Ok(r) => r,
-                Err(err) => return Err(err.clone()),
-            } {
-                let operator = match self.scanner.next().unwrap()?.id {
-                    TokenType::Bang => "!",
-                    TokenType::Minus => "-",
-                    _ => unreachable!(),
-                };
-                let right = self.unary()?;
-                return Ok(UnaryExpr::new(operator, right).into());
+    /// Unary operators, which bind more tightly than any infix one but
+    /// calls and property accesses, or a primary expression.
+    fn prefix(&mut self) -> Result<NodeId, Error> {
+        self.enter()?;
+        let operator = match self.tokens.peek()?.id {
+            TokenType::Bang => Some("!"),
+            TokenType::Minus => Some("-"),
+            _ => None,
+        };
+        let prefix = match operator {
+            Some(operator) => {
+                let token = self.advance()?;
+                let right = self.precedence(Precedence::Unary)?;
+                let unary = Expression::Unary(UnaryExpr {
+                    operator,
+                    expression: right,
+                });
+                self.push(unary, token.span())
             }
+            None => self.primary()?,
+        };
+        self.leave();
+        Ok(prefix)
+    }
+    /// An assignment to `target`, after the `=`.
+    fn assign(
+        &mut self,
+        target: NodeId,
+        equals: &Token<'a>,
+        rhs: Precedence,
+    ) -> Result<Expression, Error> {
+        let value = self.precedence(rhs)?;
+        match self.ast.expression(target) {
+            Expression::Variable(variable) => Ok(Expression::Assign(AssignExpr {
+                name: variable.name,
+                value,
+            })),
+            Expression::Get(get) => Ok(Expression::Set(SetExpr {
+                object: get.object,
+                name: get.name,
+                value,
+            })),
+            _ => Err(Error::new("Invalid assignment target.", "'='", equals.line)),
         }
-        self.primary()
-    }
-    fn primary(&mut self) -> Result<Expression, Error> {
-        use super::LiteralExpr;
-        let token = self.scanner.next().unwrap()?;
-        match &token.id {
-            TokenType::False => return Ok(LiteralExpr::False.into()),
-            TokenType::True => return Ok(LiteralExpr::True.into()),
-            TokenType::Nil => return Ok(LiteralExpr::Nil.into()),
-            TokenType::Number => return Ok(LiteralExpr::Number(token.lexeme.into()).into()),
-            TokenType::String => {
-                return Ok(
-                    LiteralExpr::String((&token.lexeme[1..token.lexeme.len() - 1]).into()).into(),
-                )
+    }
+    /// A call of `callee`, after the `(`.
+    fn call(&mut self, callee: NodeId) -> Result<Expression, Error> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen)? {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS {
+                    return Err(self.error("Can't have more than 255 arguments."));
+                }
+                arguments.push(self.expression()?);
+                if self.matches(TokenType::Comma)?.is_none() {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        Ok(Expression::Call(CallExpr { callee, arguments }))
+    }
+    fn primary(&mut self) -> Result<NodeId, Error> {
+        self.enter()?;
+        let token = self.peek()?;
+        let location = format!("'{}'", token.lexeme);
+        let primary = match token.id {
+            TokenType::Identifier => {
+                if self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(&token.symbol()))
+                    == Some(&false)
+                {
+                    return Err(Error::new(
+                        "Can't read local variable in its own initializer.",
+                        &location,
+                        token.line,
+                    ));
+                }
+                self.advance()?;
+                let variable = Expression::Variable(VariableExpr {
+                    name: token.symbol(),
+                });
+                Ok(self.push(variable, token.span()))
+            }
+            TokenType::This if self.class == ClassKind::None => Err(Error::new(
+                "Can't use 'this' outside of a class.",
+                &location,
+                token.line,
+            )),
+            TokenType::This => {
+                self.advance()?;
+                Ok(self.push(Expression::This, token.span()))
+            }
+            TokenType::Super => {
+                let message = match self.class {
+                    ClassKind::None => Some("Can't use 'super' outside of a class."),
+                    ClassKind::Class => Some("Can't use 'super' in a class with no superclass."),
+                    ClassKind::Subclass => None,
+                };
+                if let Some(message) = message {
+                    return Err(Error::new(message, &location, token.line));
+                }
+                self.advance()?;
+                self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+                let method =
+                    self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+                let sup = Expression::Super(SuperExpr {
+                    method: method.symbol(),
+                });
+                Ok(self.push(sup, token.span()))
+            }
+            // The rest of a string after an embedded expression, which
+            // starts at the `}` ending it.
+            TokenType::String | TokenType::Interpolation if token.lexeme.starts_with('}') => {
+                Err(Error::new("Expect expression.", "'}'", token.line))
+            }
+            TokenType::False => self.literal(LiteralExpr::False),
+            TokenType::True => self.literal(LiteralExpr::True),
+            TokenType::Nil => self.literal(LiteralExpr::Nil),
+            TokenType::Number => self.literal(LiteralExpr::Number(
+                token.number.expect("number tokens hold their value"),
+            )),
+            TokenType::String => self.literal(LiteralExpr::String(token.symbol())),
+            TokenType::Interpolation => {
+                self.advance()?;
+                self.interpolation(token)
             }
             TokenType::LeftParen => {
+                self.advance()?;
                 let expr = self.expression()?;
                 self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-                return Ok(Expression::Grouping(expr.into()));
+                Ok(self.push(Expression::Grouping(expr), token.span()))
             }
-            _ => unreachable!(),
-        }
-    }
-    fn consume(&mut self, id: TokenType, err_message: &str) -> Result<Token, Error> {
-        let mut peek = self.scanner.clone().peekable();
-        let token = peek.peek();
-        if let Some(r) = token.as_ref().map(|r| r.as_ref().map(|t| t.id == id)) {
-            if match r {
-                Ok(r) => r,
-                Err(err) => return Err(err.clone()),
-            } {
-                return self.scanner.next().unwrap();
-            } else {
-                return Err(Error::new(
-                    err_message,
-                    token.unwrap().as_ref().unwrap().lexeme,
-                    token.as_ref().unwrap().as_ref().unwrap().line,
-                ));
+            _ => Err(self.error("Expect expression.")),
+        };
+        self.leave();
+        primary
+    }
+    /// Consumes the token of `literal`.
+    fn literal(&mut self, literal: LiteralExpr) -> Result<NodeId, Error> {
+        let token = self.advance()?;
+        Ok(self.push(Expression::Literal(literal), token.span()))
+    }
+    /// The rest of a string with embedded expressions, after the part
+    /// before the first one: the parts concatenated with the string values
+    /// of the expressions.
+    fn interpolation(&mut self, first: Token<'a>) -> Result<NodeId, Error> {
+        let from = first.span();
+        let mut segment = first;
+        let mut string = None;
+        loop {
+            let text = segment.symbol();
+            if text != Symbol::EMPTY {
+                let text = self.push(
+                    Expression::Literal(LiteralExpr::String(text)),
+                    segment.span(),
+                );
+                string = Some(self.concatenate(string, text, from));
             }
-        } else {
-            return Err(Error::new(err_message, " at end", self.scanner.line));
+            if segment.id == TokenType::String {
+                return Ok(match string {
+                    Some(string) => string,
+                    None => self.push(
+                        Expression::Literal(LiteralExpr::String(Symbol::EMPTY)),
+                        from,
+                    ),
+                });
+            }
+            let value = self.expression()?;
+            let span = self.ast.span(value);
+            let value = self.push(Expression::ToString(value), span);
+            string = Some(self.concatenate(string, value, from));
+            segment = match self.matches(TokenType::Interpolation)? {
+                Some(segment) => segment,
+                None => {
+                    self.consume(TokenType::String, "Expect '}' after expression in string.")?
+                }
+            };
+        }
+    }
+    /// `string + value`, or just `value` if there's no string yet.
+    fn concatenate(&mut self, string: Option<NodeId>, value: NodeId, from: Span) -> NodeId {
+        let Some(string) = string else {
+            return value;
+        };
+        let binary = Expression::Binary(BinaryExpr {
+            lhs: string,
+            operator: "+",
+            rhs: value,
+        });
+        self.push(binary, from)
+    }
+    fn consume(&mut self, id: TokenType, err_message: &str) -> Result<Token<'a>, Error> {
+        match self.matches(id)? {
+            Some(token) => Ok(token),
+            None => Err(self.error(err_message)),
         }
     }
 }
 
-impl FromStr for Expression {
+/// A single expression, taking up all of `s`.
+impl FromStr for ExpressionTree {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parser = Parser::new(s)?;
-        parser.expression()
+        let mut parser = Parser::new(s);
+        let root = parser.expression()?;
+        if !parser.check(TokenType::Eof)? {
+            return Err(parser.error("Expect end of expression."));
+        }
+        Ok(ExpressionTree {
+            ast: Rc::new(parser.ast),
+            root,
+        })
+    }
+}
+impl FromStr for Program {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::new(s).program()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use super::{ExpressionTree, Program, Statement};
+    use crate::ast::scanner::Scanner;
+
+    /// A xorshift generator, so a failing seed can be run again.
+    struct Random(u64);
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Pieces of Lox, so that random input gets past the first token.
+    const FRAGMENTS: &[&str] = &[
+        "(", ")", "{", "}", ",", ".", "-", "+", ";", "/", "*", "%", "?", ":", "!", "!=", "=", "==",
+        ">", ">=", "<", "<=", "and", "class", "else", "false", "fun", "for", "if", "nil", "or",
+        "print", "return", "super", "this", "true", "var", "while", "init", "a", "b", "1", "0x1f",
+        "0b", "1_0", "2e", ".5", "\"s", "\"", "${", "\\", "//", "/*", "*/", "\n", " ", "é", "€",
+        "\0",
+    ];
+
+    /// Random bytes, and random runs of Lox fragments.
+    fn random_input(random: &mut Random) -> String {
+        let len = random.below(64);
+        if random.below(2) == 0 {
+            let bytes: Vec<u8> = (0..len).map(|_| random.next() as u8).collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        } else {
+            (0..len)
+                .map(|_| FRAGMENTS[random.below(FRAGMENTS.len())])
+                .collect()
+        }
+    }
+
+    /// Scans and parses `cases` random inputs, failing with the first one
+    /// that panics.
+    fn fuzz(seed: u64, cases: usize) {
+        let mut random = Random(seed);
+        for _ in 0..cases {
+            let input = random_input(&mut random);
+            let result = panic::catch_unwind(|| {
+                Scanner::new(&input).for_each(drop);
+                let _ = input.parse::<ExpressionTree>();
+                let _ = input.parse::<Program>();
+            });
+            assert!(result.is_ok(), "panicked on {input:?} (seed {seed})");
+        }
+    }
+
+    #[test]
+    fn random_input_is_an_error_not_a_panic() {
+        fuzz(0x9e37_79b9_7f4a_7c15, 20_000);
+    }
+
+    #[test]
+    fn missing_expressions_are_errors() {
+        for input in [
+            "", "\n", ")", "}", ";", "=", "print", "1 +", "(", "-", "a.", "f(1,",
+        ] {
+            let err = input.parse::<ExpressionTree>().err().unwrap();
+            assert!(err.message.starts_with("Expect"), "{input:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        for (open, close) in [
+            ("(", ")"),
+            ("-", ""),
+            ("!", ""),
+            ("{", "}"),
+            ("if (1) ", ""),
+        ] {
+            let source = format!("{}1;{}", open.repeat(100_000), close.repeat(100_000));
+            let err = source.parse::<Program>().err().unwrap();
+            assert_eq!(err.message, "Too much nesting.", "{open:?}");
+        }
+        let err = "(".repeat(100_000).parse::<ExpressionTree>().err().unwrap();
+        assert_eq!(err.to_string(), "[line 1] Error at '(': Too much nesting.");
+        // Nesting that isn't that deep still parses.
+        let source = format!("print {}1{};", "(".repeat(50), ")".repeat(50));
+        assert!(source.parse::<Program>().is_ok());
+        let source = format!("{}print -1;{}", "{".repeat(100), "}".repeat(100));
+        assert!(source.parse::<Program>().is_ok());
+    }
+
+    #[test]
+    fn nodes_span_their_source() {
+        let source = "var a = (1 + 2) * b;\nfor (;;) print a.c = \"${a}!\";";
+        let program: Program = source.parse().unwrap();
+        let ast = &program.ast;
+        let text = |id| {
+            let span = ast.span(id);
+            (&source[span.start..span.end], span.line)
+        };
+        let [var, for_loop] = program.statements[..] else {
+            panic!("expected two statements");
+        };
+        assert_eq!(text(var), ("var a = (1 + 2) * b;", 1));
+        assert_eq!(
+            ast.display(var).to_string(),
+            "(var a (* (group (+ 1 2)) b))"
+        );
+        let Statement::Var(var) = ast.statement(var) else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(text(var.initializer.unwrap()), ("(1 + 2) * b", 1));
+        assert_eq!(text(for_loop), ("for (;;) print a.c = \"${a}!\";", 2));
+        let Statement::Block(block) = ast.statement(for_loop) else {
+            panic!("expected a block");
+        };
+        let Statement::While(body) = ast.statement(block[0]) else {
+            panic!("expected a loop");
+        };
+        assert_eq!(text(body.body), ("print a.c = \"${a}!\";", 2));
+        assert_eq!(
+            ast.display(body.body).to_string(),
+            "(print (= (. a c) (+ (str a) !)))"
+        );
+    }
+
+    #[test]
+    fn classes_span_their_superclass_and_methods() {
+        let source = "class A < B {\n  init(a) {}\n  get() { return 1; }\n}";
+        let program: Program = source.parse().unwrap();
+        let ast = &program.ast;
+        let text = |id| {
+            let span = ast.span(id);
+            (&source[span.start..span.end], span.line)
+        };
+        let class = program.statements[0];
+        assert_eq!(
+            ast.display(class).to_string(),
+            "(class A < B (fun init (a)) (fun get () (return 1)))"
+        );
+        let Statement::Class(class) = ast.statement(class) else {
+            panic!("expected a class");
+        };
+        assert_eq!(text(class.superclass.unwrap()), ("B", 1));
+        let methods: Vec<_> = class.methods.iter().map(|method| text(*method)).collect();
+        assert_eq!(methods, [("init(a) {}", 2), ("get() { return 1; }", 3)]);
+    }
+
+    /// Run with `cargo test --release -- --ignored fuzz_for_a_while`.
+    #[test]
+    #[ignore = "fuzzer"]
+    fn fuzz_for_a_while() {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+            | 1;
+        fuzz(seed, 10_000_000);
+    }
+
+    /// A script `copies` times as long as one with a bit of everything.
+    fn script(copies: usize) -> String {
+        let mut source = String::new();
+        for i in 0..copies {
+            source += &format!(
+                "class A{i} < Base {{
+    init(a, b) {{ this.a = a; this.b = -b * (a + 2) / 3 % 4; }}
+    sum() {{ return this.a + this.b; }}
+}}
+/* a {i} /* nested */ comment */
+fun f{i}(x) {{
+    var y = x > 1 ? x - 1 : 0;
+    for (var j = 0; j < y and !false or nil == j; j = j + 1) {{
+        if (j >= 0x10) print \"${{j}} of ${{y}}\"; else y = f{i}(j).sum;
+    }}
+    while (y != 0) y = A{i}(y, 1_000.5e-1).sum();
+    return y;
+}}
+"
+            );
+        }
+        source
+    }
+    /// The fastest of a few parses of `source`.
+    fn parse_time(source: &str) -> Duration {
+        (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                source.parse::<Program>().unwrap();
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn parsing_takes_linear_time() {
+        let base = parse_time(&script(500)).as_secs_f64() / 500.0;
+        for copies in [500, 2000, 8000, 32000] {
+            let source = script(copies);
+            let time = parse_time(&source);
+            let per_copy = time.as_secs_f64() / copies as f64;
+            println!(
+                "{:>9} bytes {:>9.2} ms {:>6.1} ns/byte",
+                source.len(),
+                time.as_secs_f64() * 1e3,
+                time.as_secs_f64() * 1e9 / source.len() as f64
+            );
+            assert!(
+                per_copy < base * 3.0,
+                "{copies} copies parse slower per copy"
+            );
+        }
     }
 }
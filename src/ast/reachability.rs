@@ -0,0 +1,198 @@
+//! Finds statements that can never run, because every path before them
+//! returns or because they're under a condition that's always false.
+use super::{
+    Ast, ClassDecl, Expression, FunctionDecl, IfStmt, LiteralExpr, NodeId, Program, Statement,
+    WhileStmt,
+};
+use std::fmt::Display;
+
+/// Not an error, the program still runs.
+pub(crate) struct Warning {
+    line: usize,
+    message: String,
+}
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Warning: {}", self.line, self.message)
+    }
+}
+
+/// A warning for every block with statements after an unconditional
+/// `return`, at the line of that return, and for every branch or loop body
+/// a literal condition keeps from running, at its first line.
+pub(crate) fn warnings(program: &Program) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    statements(&program.ast, &program.statements, &mut warnings);
+    warnings
+}
+
+/// The line of the `return` that always ends `statements`, if one does.
+fn statements(ast: &Ast, statements: &[NodeId], warnings: &mut Vec<Warning>) -> Option<usize> {
+    for (i, statement) in statements.iter().enumerate() {
+        if let Some(line) = self::statement(ast, *statement, warnings) {
+            if i + 1 < statements.len() {
+                warnings.push(Warning {
+                    line,
+                    message: "Code after 'return' is never run.".to_string(),
+                });
+            }
+            return Some(line);
+        }
+    }
+    None
+}
+
+fn statement(ast: &Ast, statement: NodeId, warnings: &mut Vec<Warning>) -> Option<usize> {
+    match ast.statement(statement) {
+        Statement::Return(_) => Some(ast.span(statement).line),
+        Statement::Block(block) => statements(ast, block, warnings),
+        Statement::If(IfStmt {
+            condition,
+            then,
+            otherwise,
+        }) => match truthiness(ast, *condition) {
+            Some(true) => {
+                if let Some(otherwise) = otherwise {
+                    never_run(ast, *otherwise, ALWAYS_TRUE, warnings);
+                }
+                self::statement(ast, *then, warnings)
+            }
+            Some(false) => {
+                never_run(ast, *then, ALWAYS_FALSE, warnings);
+                otherwise.and_then(|otherwise| self::statement(ast, otherwise, warnings))
+            }
+            None => {
+                let then = self::statement(ast, *then, warnings);
+                let otherwise =
+                    otherwise.and_then(|otherwise| self::statement(ast, otherwise, warnings));
+                then.zip(otherwise)
+                    .map(|(then, otherwise)| then.max(otherwise))
+            }
+        },
+        Statement::While(WhileStmt { condition, body }) => {
+            if truthiness(ast, *condition) == Some(false) {
+                never_run(ast, *body, ALWAYS_FALSE, warnings);
+            } else {
+                self::statement(ast, *body, warnings);
+            }
+            // The body might not run at all.
+            None
+        }
+        Statement::Function(function) => {
+            self::function(ast, function, warnings);
+            None
+        }
+        Statement::Class(ClassDecl { methods, .. }) => {
+            for method in methods {
+                self::statement(ast, *method, warnings);
+            }
+            None
+        }
+        Statement::Expression(_) | Statement::Print(_) | Statement::Var(_) => None,
+    }
+}
+
+fn function(ast: &Ast, function: &FunctionDecl, warnings: &mut Vec<Warning>) {
+    statements(ast, &function.body, warnings);
+}
+
+/// Whether `condition` is a literal, possibly in parentheses, that's always
+/// truthy or always falsey.
+fn truthiness(ast: &Ast, condition: NodeId) -> Option<bool> {
+    match ast.expression(condition) {
+        Expression::Literal(LiteralExpr::Nil | LiteralExpr::False) => Some(false),
+        Expression::Literal(_) => Some(true),
+        Expression::Grouping(inner) => truthiness(ast, *inner),
+        _ => None,
+    }
+}
+
+const ALWAYS_FALSE: &str = "Code under a condition that's always false is never run.";
+const ALWAYS_TRUE: &str = "'else' after a condition that's always true is never run.";
+
+fn never_run(ast: &Ast, statement: NodeId, message: &str, warnings: &mut Vec<Warning>) {
+    warnings.push(Warning {
+        line: ast.span(statement).line,
+        message: message.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings_for(source: &str) -> Vec<String> {
+        let program: Program = source.parse().unwrap();
+        warnings(&program).iter().map(Warning::to_string).collect()
+    }
+
+    #[test]
+    fn code_after_return() {
+        assert_eq!(
+            warnings_for("fun f() {\n  return 1;\n  print 2;\n}"),
+            ["[line 2] Warning: Code after 'return' is never run."]
+        );
+        assert_eq!(
+            warnings_for("fun f() {\n  print 1;\n  return 2;\n}"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn returns_on_every_branch() {
+        assert_eq!(
+            warnings_for(
+                "fun f(x) {\n  if (x) return 1;\n  else {\n    return 2;\n  }\n  print 3;\n}"
+            ),
+            ["[line 4] Warning: Code after 'return' is never run."]
+        );
+        assert_eq!(
+            warnings_for("fun f(x) {\n  if (x) return 1;\n  print 2;\n}"),
+            Vec::<String>::new()
+        );
+        // The body of a loop might not run.
+        assert_eq!(
+            warnings_for("fun f(x) {\n  while (x) return 1;\n  print 2;\n}"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(
+            warnings_for("class A {\n  m() {\n    return;\n    print 1;\n  }\n}"),
+            ["[line 3] Warning: Code after 'return' is never run."]
+        );
+    }
+
+    #[test]
+    fn conditions_that_are_always_false() {
+        assert_eq!(
+            warnings_for("if (false)\n  print 1;\nwhile ((nil)) print 2;\nif (x) print 3;"),
+            [
+                "[line 2] Warning: Code under a condition that's always false is never run.",
+                "[line 3] Warning: Code under a condition that's always false is never run.",
+            ]
+        );
+        // Only literals count.
+        assert_eq!(
+            warnings_for("if (!true) print 1;\nwhile (1 > 2) print 2;"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn conditions_that_are_always_true() {
+        assert_eq!(
+            warnings_for("fun f() {\n  if (0) return 1;\n  else print 2;\n  print 3;\n}"),
+            [
+                "[line 3] Warning: 'else' after a condition that's always true is never run.",
+                "[line 2] Warning: Code after 'return' is never run.",
+            ]
+        );
+        assert_eq!(
+            warnings_for("if (\"\") print 1; else print 2;"),
+            ["[line 1] Warning: 'else' after a condition that's always true is never run."]
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use super::Error;
-use std::{fmt::Display, ops::RangeInclusive, str::CharIndices};
-#[derive(Debug, Clone, Copy,PartialEq, Eq, PartialOrd, Ord)]
+use super::{unicode, Error, Span};
+use crate::symbol::Symbol;
+use std::{collections::VecDeque, fmt::Display, str::CharIndices};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) enum TokenType {
     // Single-character tokens.
     LeftParen,
@@ -14,6 +15,9 @@ pub(crate) enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Question,
+    Colon,
 
     // One or two character tokens.
     Bang,
@@ -28,6 +32,8 @@ pub(crate) enum TokenType {
     // Literals.
     Identifier,
     String,
+    /// The part of a string before an embedded `${`.
+    Interpolation,
     Number,
 
     // Keywords.
@@ -47,17 +53,40 @@ pub(crate) enum TokenType {
     True,
     Var,
     While,
+
+    /// After the last token, however often the parser looks.
+    Eof,
 }
 impl Display for TokenType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self:?}")
     }
 }
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub(crate) struct Token<'a> {
     pub(crate) id: TokenType,
     pub(crate) lexeme: &'a str,
+    /// Where the lexeme starts in the source, in bytes.
+    pub(crate) start: usize,
     pub(crate) line: usize,
+    /// An identifier's name, or what a string literal holds with its escape
+    /// sequences decoded.
+    pub(crate) symbol: Option<Symbol>,
+    /// What a number literal stands for.
+    pub(crate) number: Option<f64>,
+}
+impl Token<'_> {
+    /// The symbol of an identifier or string token.
+    pub(crate) fn symbol(&self) -> Symbol {
+        self.symbol.expect("identifiers and strings are interned")
+    }
+    pub(crate) fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.start + self.lexeme.len(),
+            line: self.line,
+        }
+    }
 }
 impl<'a> Display for Token<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -75,8 +104,8 @@ impl Pos {
         let (start, current) = &mut self.0;
         *start = *current;
     }
-    fn cur_lexm(&self) -> RangeInclusive<usize> {
-        self.0 .0..=self.0 .1
+    fn start(&self) -> usize {
+        self.0 .0
     }
     fn cur_pos(&self) -> usize {
         self.0 .1
@@ -88,6 +117,8 @@ pub(crate) struct Scanner<'a> {
     source: &'a str,
     pub(super) line: usize,
     chars: CharIndices<'a>,
+    /// The braces opened in each `${...}` being scanned, innermost last.
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Scanner<'a> {
@@ -97,71 +128,262 @@ impl<'a> Scanner<'a> {
             source,
             line: 1,
             chars: source.char_indices(),
+            interpolations: Vec::new(),
         }
     }
     fn is_at_end(&self) -> bool {
-        self.location.cur_pos() >= self.source.len()
+        self.chars.offset() >= self.source.len()
     }
     fn advance(&mut self) -> Option<char> {
         let (indc, char) = self.chars.next()?;
         self.location.0 .1 = indc;
         Some(char)
     }
+    /// The next character, without consuming it.
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next().map(|t| t.1)
+    }
+    /// The character after the next one.
+    fn peek_next(&self) -> Option<char> {
+        self.chars.clone().nth(1).map(|t| t.1)
+    }
+    /// Skips whitespace and comments, however many follow each other.
+    fn skip_whitespace(&mut self) -> Result<(), Error> {
+        loop {
+            match self.peek() {
+                Some('\n') => self.line += 1,
+                Some(c) if c.is_ascii_whitespace() => (),
+                Some('/') if self.peek_next() == Some('/') => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.advance();
+                    }
+                    continue;
+                }
+                Some('/') if self.peek_next() == Some('*') => {
+                    self.advance();
+                    self.advance();
+                    self.block_comment()?;
+                    continue;
+                }
+                _ => return Ok(()),
+            }
+            self.advance();
+        }
+    }
+    /// The source of the token being scanned, up to the last character
+    /// consumed, however many bytes it takes.
+    fn lexeme(&self) -> &'a str {
+        &self.source[self.location.start()..self.chars.offset()]
+    }
+    /// The token after the last one, on the last line.
+    fn eof(&self) -> Token<'a> {
+        Token {
+            id: TokenType::Eof,
+            lexeme: "",
+            start: self.source.len(),
+            line: self.line,
+            symbol: None,
+            number: None,
+        }
+    }
     fn make_token(&self, id: TokenType) -> Token<'a> {
         Token {
             id,
-            lexeme: &self.source[self.location.cur_lexm()],
+            lexeme: self.lexeme(),
+            start: self.location.start(),
             line: self.line,
+            symbol: None,
+            number: None,
         }
     }
+    /// A string, or the part of one up to an embedded expression, which
+    /// is scanned as tokens of its own until the `}` that closes it.
     fn string(&mut self) -> Option<Result<Token<'a>, Error>> {
-        let mut chars = self.chars.clone().peekable();
-        while chars.peek().map(|t| t.1) != Some('"') {
-            if chars.next().map(|t| t.1) == Some('\n') {
-                self.line += 1;
+        // The `"` or `}` it starts at.
+        let open = &self.source[self.location.start()..self.chars.offset()];
+        let line = self.line;
+        let mut string = String::new();
+        let mut id = TokenType::String;
+        loop {
+            match self.advance() {
+                Some('"') => break,
+                Some('$') if self.peek() == Some('{') => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    id = TokenType::Interpolation;
+                    break;
+                }
+                Some('\\') if self.peek().is_some() => match self.escape() {
+                    Ok(c) => string.push(c),
+                    Err(err) => return Some(Err(err)),
+                },
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    string.push(c);
+                }
+                None => {
+                    let location = format!("'{open}'");
+                    return Some(Err(Error::new("Unterminated string", &location, line)));
+                }
             }
-            self.advance();
         }
-
-        if self.is_at_end() {
-            return Some(Err(Error::new("Unterminated string", "", self.line)));
+        let mut token = self.make_token(id);
+        token.symbol = Some(Symbol::intern(&string));
+        Some(Ok(token))
+    }
+    /// The character the escape sequence after a backslash stands for:
+    /// `\n`, `\t`, `\"`, `\\`, `\$` or `\u{...}` with up to six hex digits.
+    fn escape(&mut self) -> Result<char, Error> {
+        let start = self.location.cur_pos();
+        // At the sequence read so far.
+        let error = |scanner: &Self, message: &str| {
+            let sequence = &scanner.source[start..scanner.chars.offset()];
+            Error::new(message, &format!("'{sequence}'"), scanner.line)
+        };
+        match self.advance() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('$') => Ok('$'),
+            Some('u') => {
+                if self.advance() != Some('{') {
+                    return Err(error(self, "Invalid Unicode escape."));
+                }
+                let mut digits = String::new();
+                loop {
+                    match self.advance() {
+                        Some('}') if !digits.is_empty() => break,
+                        Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+                        _ => return Err(error(self, "Invalid Unicode escape.")),
+                    }
+                }
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error(self, "Unicode escape isn't a valid character."))
+            }
+            _ => Err(error(self, "Invalid escape sequence.")),
         }
-
-        // the closing '"'
-        self.advance();
-
-        Some(Ok(self.make_token(TokenType::String)))
-    }
-    fn number(&mut self) -> Option<Result<Token<'a>, Error>> {
-        let mut chars = self.chars.clone().peekable();
-        while match chars.peek() {
-            Some(c) if c.1.is_ascii_digit() => true,
-            _ => false,
-        } {
-            self.advance();
-            chars.next();
+    }
+    /// Skips a block comment after its `/*`, with the ones nested in it.
+    fn block_comment(&mut self) -> Result<(), Error> {
+        let line = self.line;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some('/') if self.peek() == Some('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some('*') if self.peek() == Some('/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                Some('\n') => self.line += 1,
+                Some(_) => (),
+                None => return Err(Error::new("Unterminated block comment", "'/*'", line)),
+            }
         }
-
-        if Some(true) == chars.peek().map(|c| c.1 == '.')
-            && Some(true) == {
-                let mut chars = chars.clone();
-                chars.next();
-                chars.peek().map(|c| c.1.is_ascii_digit())
+        Ok(())
+    }
+    /// A number: decimal with an optional fraction and exponent, or hex
+    /// after `0x` or binary after `0b`. Digits can be separated by `_`.
+    fn number(&mut self, first: char) -> Option<Result<Token<'a>, Error>> {
+        let radix = match (first, self.peek()) {
+            ('0', Some('x')) => 16,
+            ('0', Some('b')) => 2,
+            _ => 10,
+        };
+        let number = if radix == 10 {
+            self.decimal()
+        } else {
+            // consume the 'x' or 'b'
+            self.advance();
+            self.integer(radix)
+        };
+        let number = number.and_then(|number| match self.peek() {
+            Some(c) if unicode::is_xid_continue(c) => {
+                self.identifier();
+                Err(self.number_error("Invalid character in number."))
             }
+            _ => Ok(number),
+        });
+        let mut token = self.make_token(TokenType::Number);
+        token.number = Some(match number {
+            Ok(number) => number,
+            Err(err) => return Some(Err(err)),
+        });
+        Some(Ok(token))
+    }
+    fn decimal(&mut self) -> Result<f64, Error> {
+        self.digits(10)?;
+        let mut chars = self.chars.clone();
+        if chars.next().map(|t| t.1) == Some('.')
+            && chars.next().is_some_and(|t| t.1.is_ascii_digit())
         {
             // consume the '.'
-            chars.next();
             self.advance();
-
-            while match chars.peek() {
-                Some(c) if c.1.is_ascii_digit() => true,
-                _ => false,
-            } {
+            self.advance();
+            self.digits(10)?;
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.advance();
+            if matches!(self.peek(), Some('+' | '-')) {
                 self.advance();
-                chars.next();
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.number_error("Expect digits in exponent."));
+            }
+            self.advance();
+            self.digits(10)?;
+        }
+        let digits = self.lexeme().replace('_', "");
+        Ok(digits.parse().expect("the digits make a valid float"))
+    }
+    /// The hex or binary number after its prefix.
+    fn integer(&mut self, radix: u32) -> Result<f64, Error> {
+        if !self.peek().is_some_and(|c| c.is_digit(radix)) {
+            let prefix = if radix == 16 { "0x" } else { "0b" };
+            return Err(self.number_error(&format!("Expect digits after '{prefix}'.")));
+        }
+        self.advance();
+        self.digits(radix)?;
+        let digits = self.lexeme()[2..].replace('_', "");
+        match u128::from_str_radix(&digits, radix) {
+            Ok(number) => Ok(number as f64),
+            Err(_) => Err(self.number_error("Number is too large.")),
+        }
+    }
+    /// Consumes the digits after the first one, and the `_` between them.
+    fn digits(&mut self, radix: u32) -> Result<(), Error> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => {
+                    self.advance();
+                }
+                Some('_') => {
+                    self.advance();
+                    if !self.peek().is_some_and(|c| c.is_digit(radix)) {
+                        return Err(self.number_error("Expect a digit after '_'."));
+                    }
+                }
+                _ => return Ok(()),
             }
         }
-        Some(Ok(self.make_token(TokenType::Number)))
+    }
+    /// An error at the number scanned so far.
+    fn number_error(&self, message: &str) -> Error {
+        let number = self.lexeme();
+        Error::new(message, &format!("'{number}'"), self.line)
+    }
+    fn identifier(&mut self) -> &'a str {
+        while self.peek().is_some_and(unicode::is_xid_continue) {
+            self.advance();
+        }
+        self.lexeme()
     }
 }
 impl<'a> Iterator for Scanner<'a>
@@ -173,29 +395,40 @@ where
         if self.is_at_end() {
             return None;
         }
-        while match self.chars.clone().peekable().peek().map(|t| t.1) {
-            Some('\n') => {
-                self.line += 1;
-                true
-            }
-            Some(c) if c.is_ascii_whitespace() => true,
-            _ => false,
-        } {
-            self.advance();
+        if let Err(err) = self.skip_whitespace() {
+            return Some(Err(err));
         }
         let c = self.advance()?;
         self.location.sync();
         match c {
             '(' => Some(Ok(self.make_token(TokenType::LeftParen))),
             ')' => Some(Ok(self.make_token(TokenType::RightParen))),
-            '{' => Some(Ok(self.make_token(TokenType::LeftBrace))),
-            '}' => Some(Ok(self.make_token(TokenType::RightBrace))),
+            '{' => {
+                if let Some(open) = self.interpolations.last_mut() {
+                    *open += 1;
+                }
+                Some(Ok(self.make_token(TokenType::LeftBrace)))
+            }
+            '}' if self.interpolations.last() == Some(&0) => {
+                // The end of an embedded expression, the string goes on.
+                self.interpolations.pop();
+                self.string()
+            }
+            '}' => {
+                if let Some(open) = self.interpolations.last_mut() {
+                    *open -= 1;
+                }
+                Some(Ok(self.make_token(TokenType::RightBrace)))
+            }
             ',' => Some(Ok(self.make_token(TokenType::Comma))),
             '.' => Some(Ok(self.make_token(TokenType::Dot))),
             '-' => Some(Ok(self.make_token(TokenType::Minus))),
             '+' => Some(Ok(self.make_token(TokenType::Plus))),
             ';' => Some(Ok(self.make_token(TokenType::Semicolon))),
             '*' => Some(Ok(self.make_token(TokenType::Star))),
+            '%' => Some(Ok(self.make_token(TokenType::Percent))),
+            '?' => Some(Ok(self.make_token(TokenType::Question))),
+            ':' => Some(Ok(self.make_token(TokenType::Colon))),
             '!' if self.chars.clone().peekable().peek().map(|tup| tup.1) == Some('=') => {
                 // consume '!'
                 self.advance();
@@ -220,33 +453,10 @@ where
                 Some(Ok(self.make_token(TokenType::GreaterEqual)))
             }
             '>' => Some(Ok(self.make_token(TokenType::Greater))),
-            '/' if self.chars.clone().peekable().peek().map(|tup| tup.1) == Some('/') => {
-                let mut peek = self.chars.clone().peekable();
-                // consume the second '/'
-                self.advance();
-                peek.next();
-                while peek.peek().map(|tup| tup.1) != Some('\n') && !self.is_at_end() {
-                    peek.next();
-                    self.advance();
-                }
-                self.next()
-            }
             '/' => Some(Ok(self.make_token(TokenType::Slash))),
             '"' => self.string(),
-            _ if c.is_ascii_digit() => self.number(),
-            _ if c.is_alphabetic() => match {
-                while self
-                    .chars
-                    .clone()
-                    .peekable()
-                    .peek()
-                    .map(|t| t.1.is_alphanumeric())
-                    == Some(true)
-                {
-                    self.advance();
-                }
-                &self.source[self.location.cur_lexm()]
-            } {
+            _ if c.is_ascii_digit() => self.number(c),
+            _ if unicode::is_xid_start(c) || c == '_' => match self.identifier() {
                 "and" => Some(Ok(self.make_token(TokenType::And))),
                 "class" => Some(Ok(self.make_token(TokenType::Class))),
                 "else" => Some(Ok(self.make_token(TokenType::Else))),
@@ -263,7 +473,11 @@ where
                 "true" => Some(Ok(self.make_token(TokenType::True))),
                 "var" => Some(Ok(self.make_token(TokenType::Var))),
                 "while" => Some(Ok(self.make_token(TokenType::While))),
-                _ => Some(Ok(self.make_token(TokenType::Identifier))),
+                name => {
+                    let mut token = self.make_token(TokenType::Identifier);
+                    token.symbol = Some(Symbol::intern(name));
+                    Some(Ok(token))
+                }
             },
             _ => Some(Err(Error::new(
                 format!("Unexpected token: {} ", c),
@@ -273,3 +487,151 @@ where
         }
     }
 }
+
+/// The tokens of a source, each scanned once however far ahead the parser
+/// looks. The scanner's errors are tokens like any other, and once the
+/// source runs out it's [`TokenType::Eof`] from then on.
+pub(crate) struct TokenStream<'a> {
+    scanner: Scanner<'a>,
+    /// Scanned but not yet consumed, next first.
+    buffer: VecDeque<Result<Token<'a>, Error>>,
+}
+impl<'a> TokenStream<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        Self {
+            scanner: Scanner::new(source),
+            buffer: VecDeque::new(),
+        }
+    }
+    fn scan(&mut self) -> Result<Token<'a>, Error> {
+        self.scanner
+            .next()
+            .unwrap_or_else(|| Ok(self.scanner.eof()))
+    }
+    /// The next token, without consuming it.
+    pub(crate) fn peek(&mut self) -> Result<&Token<'a>, Error> {
+        self.peek_nth(0)
+    }
+    /// The token `n` after the next one, without consuming any.
+    pub(crate) fn peek_nth(&mut self, n: usize) -> Result<&Token<'a>, Error> {
+        while self.buffer.len() <= n {
+            let token = self.scan();
+            self.buffer.push_back(token);
+        }
+        self.buffer[n].as_ref().map_err(Clone::clone)
+    }
+    /// Consumes the next token.
+    pub(crate) fn advance(&mut self) -> Result<Token<'a>, Error> {
+        self.buffer.pop_front().unwrap_or_else(|| self.scan())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scanner, TokenStream, TokenType};
+
+    fn lexemes(source: &str) -> Vec<(TokenType, &str)> {
+        Scanner::new(source)
+            .map(|token| token.map(|token| (token.id, token.lexeme)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn identifiers_end_in_multibyte_characters() {
+        assert_eq!(
+            lexemes("café naïve é 变量"),
+            vec![
+                (TokenType::Identifier, "café"),
+                (TokenType::Identifier, "naïve"),
+                (TokenType::Identifier, "é"),
+                (TokenType::Identifier, "变量"),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers_follow_xid() {
+        assert_eq!(
+            lexemes("_ü Ωμέγα a̐ x٣ _9"),
+            vec![
+                (TokenType::Identifier, "_ü"),
+                (TokenType::Identifier, "Ωμέγα"),
+                (TokenType::Identifier, "a̐"),
+                (TokenType::Identifier, "x٣"),
+                (TokenType::Identifier, "_9"),
+            ]
+        );
+        // Combining marks and digits continue identifiers but don't start
+        // them.
+        assert!(Scanner::new("\u{310}a").next().unwrap().is_err());
+        assert!(Scanner::new("x²").nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn strings_and_comments_hold_any_text() {
+        assert_eq!(
+            lexemes("\"🎉\" /* ✓ */ é // ✓"),
+            vec![(TokenType::String, "\"🎉\""), (TokenType::Identifier, "é")]
+        );
+    }
+
+    #[test]
+    fn runs_of_comments_are_skipped_without_recursion() {
+        let source = "// a\n/* b */ ".repeat(500_000) + "c /**/ // d";
+        let tokens: Vec<_> = Scanner::new(&source).map(Result::unwrap).collect();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!((tokens[0].lexeme, tokens[0].line), ("c", 500_001));
+        assert_eq!(
+            lexemes("a//\n/**/b/"),
+            vec![
+                (TokenType::Identifier, "a"),
+                (TokenType::Identifier, "b"),
+                (TokenType::Slash, "/"),
+            ]
+        );
+        let err = Scanner::new("a\n/* b").nth(1).unwrap().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "[line 2] Error at '/*': Unterminated block comment"
+        );
+    }
+
+    #[test]
+    fn unterminated_strings_point_at_their_start() {
+        for (source, expected) in [
+            ("a \"b\nc", "[line 1] Error at '\"': Unterminated string"),
+            ("\n\"b\\", "[line 2] Error at '\"': Unterminated string"),
+            ("\"${a}\nb", "[line 1] Error at '}': Unterminated string"),
+        ] {
+            let err = Scanner::new(source).find_map(Result::err).unwrap();
+            assert_eq!(err.to_string(), expected, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn operators_after_multibyte_characters() {
+        assert_eq!(
+            lexemes("ü+\"é\"=="),
+            vec![
+                (TokenType::Identifier, "ü"),
+                (TokenType::Plus, "+"),
+                (TokenType::String, "\"é\""),
+                (TokenType::EqualEqual, "=="),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_stream_looks_ahead_and_ends_in_eof() {
+        let mut tokens = TokenStream::new("a + b");
+        assert_eq!(tokens.peek_nth(2).unwrap().lexeme, "b");
+        assert_eq!(tokens.peek_nth(3).unwrap().id, TokenType::Eof);
+        assert_eq!(tokens.advance().unwrap().lexeme, "a");
+        assert_eq!(tokens.peek().unwrap().id, TokenType::Plus);
+        assert_eq!(tokens.advance().unwrap().id, TokenType::Plus);
+        assert_eq!(tokens.advance().unwrap().id, TokenType::Identifier);
+        assert_eq!(tokens.advance().unwrap().id, TokenType::Eof);
+        assert_eq!(tokens.advance().unwrap().id, TokenType::Eof);
+    }
+}
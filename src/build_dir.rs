@@ -0,0 +1,69 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, DirBuilder},
+    hash::{BuildHasher, Hasher},
+    io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many names to try before giving up on creating a build directory.
+const MAX_ATTEMPTS: u32 = 64;
+
+/// A private scratch directory for the intermediate files of one compilation.
+///
+/// The directory is created with mode `0700` under the system temp directory
+/// and removed together with its contents when the value is dropped, unless
+/// it was created with `keep` set.
+pub(crate) struct BuildDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl BuildDir {
+    pub(crate) fn new(keep: bool) -> io::Result<Self> {
+        let base = std::env::temp_dir();
+        for _ in 0..MAX_ATTEMPTS {
+            let path = base.join(format!("complox-{:016x}", random_suffix()));
+            // `create` fails if the path already exists, so an attacker can't
+            // pre-create (or symlink) the directory we are about to use.
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(Self { path, keep }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "could not create a unique build directory",
+        ))
+    }
+    pub(crate) fn join<P: AsRef<Path>>(&self, file: P) -> PathBuf {
+        self.path.join(file)
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        if self.keep {
+            eprintln!("keeping temporary files in {}", self.path.display());
+        } else if let Err(err) = fs::remove_dir_all(&self.path) {
+            eprintln!(
+                "failed to remove build directory {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// `RandomState` is seeded from the OS on creation, mixing in the pid and
+/// time keeps the names distinct even if the seeds were ever reused.
+fn random_suffix() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    if let Ok(time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.finish()
+}
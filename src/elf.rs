@@ -0,0 +1,763 @@
+//! Writes an [`ir::Program`](crate::ir::Program) as an ELF64 relocatable
+//! object or a static executable, without going through nasm and ld.
+use crate::ir::{Program, Section};
+use crate::x86_64::encoder::{self, Encoded, Encoder, Reference};
+use std::{collections::HashMap, fmt};
+
+/// Where the first byte of an executable gets mapped.
+const BASE_ADDRESS: u64 = 0x40_0000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+const SYM_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Error {
+    Encode(encoder::Error),
+    /// A label defined in more than one section.
+    DuplicateSymbol(String),
+    /// A label used by an executable but never defined.
+    UndefinedSymbol(String),
+    /// The program's entry point isn't defined.
+    NoEntry(String),
+    /// A `rel32` that can't reach its target.
+    RelocationOverflow(String),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Encode(err) => err.fmt(f),
+            Self::DuplicateSymbol(name) => write!(f, "symbol '{name}' is defined twice"),
+            Self::UndefinedSymbol(name) => write!(f, "undefined reference to '{name}'"),
+            Self::NoEntry(name) => write!(f, "no '{name}' symbol to use as the entry point"),
+            Self::RelocationOverflow(name) => {
+                write!(f, "relocation against '{name}' is out of range")
+            }
+        }
+    }
+}
+impl From<encoder::Error> for Error {
+    fn from(value: encoder::Error) -> Self {
+        Self::Encode(value)
+    }
+}
+
+/// The sections we emit, in section header order after the null section.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Kind {
+    Text,
+    Rodata,
+    Data,
+    Bss,
+}
+impl Kind {
+    const ALL: [Kind; 4] = [Kind::Text, Kind::Rodata, Kind::Data, Kind::Bss];
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Text => ".text",
+            Self::Rodata => ".rodata",
+            Self::Data => ".data",
+            Self::Bss => ".bss",
+        }
+    }
+    fn index(&self) -> u16 {
+        match self {
+            Self::Text => 1,
+            Self::Rodata => 2,
+            Self::Data => 3,
+            Self::Bss => 4,
+        }
+    }
+    fn flags(&self) -> u64 {
+        match self {
+            Self::Text => SHF_ALLOC | SHF_EXECINSTR,
+            Self::Rodata => SHF_ALLOC,
+            Self::Data | Self::Bss => SHF_ALLOC | SHF_WRITE,
+        }
+    }
+    fn align(&self) -> u64 {
+        match self {
+            Self::Text => 16,
+            _ => 8,
+        }
+    }
+}
+
+/// Every section of a program run through the [`Encoder`].
+struct Sections {
+    encoded: Vec<(Kind, Encoded)>,
+    /// Label name to its section and offset.
+    symbols: HashMap<String, (Kind, usize)>,
+    /// Labels in definition order, so the output is deterministic.
+    order: Vec<String>,
+}
+impl Sections {
+    fn new(program: &Program) -> Result<Self, Error> {
+        let mut encoded = Vec::new();
+        let mut text = Encoder::new();
+        for section in program.text() {
+            text.section(section)?;
+        }
+        encoded.push((Kind::Text, text.finish()));
+        for (kind, section) in [
+            (Kind::Rodata, program.rodata()),
+            (Kind::Data, program.data()),
+            (Kind::Bss, program.bss()),
+        ] {
+            encoded.push((kind, data(section)?));
+        }
+
+        let mut symbols = HashMap::new();
+        let mut order = Vec::new();
+        for (kind, encoded) in &encoded {
+            for symbol in &encoded.symbols {
+                if symbols
+                    .insert(symbol.name.clone(), (*kind, symbol.offset))
+                    .is_some()
+                {
+                    return Err(Error::DuplicateSymbol(symbol.name.clone()));
+                }
+                order.push(symbol.name.clone());
+            }
+        }
+        Ok(Self {
+            encoded,
+            symbols,
+            order,
+        })
+    }
+    fn get(&self, kind: Kind) -> &Encoded {
+        &self.encoded.iter().find(|(k, _)| *k == kind).unwrap().1
+    }
+    fn get_mut(&mut self, kind: Kind) -> &mut Encoded {
+        &mut self.encoded.iter_mut().find(|(k, _)| *k == kind).unwrap().1
+    }
+    fn size(&self, kind: Kind) -> u64 {
+        self.get(kind).code.len() as u64
+    }
+}
+fn data(section: Option<&Section>) -> Result<Encoded, Error> {
+    let mut encoder = Encoder::new();
+    if let Some(section) = section {
+        encoder.body(section)?;
+    }
+    Ok(encoder.finish())
+}
+
+/// Writes `program` as an ELF64 relocatable object (`ET_REL`).
+///
+/// Branches to labels the program doesn't define become `R_X86_64_PLT32`
+/// relocations against undefined global symbols, so the object can be linked
+/// against shared libraries and into position independent executables.
+pub(crate) fn object(program: &Program) -> Result<Vec<u8>, Error> {
+    let sections = Sections::new(program)?;
+
+    // Local labels first, the symbol table's `sh_info` is the index of the
+    // first global one.
+    let mut strtab = StringTable::new();
+    let mut symtab = Vec::new();
+    let mut indices = HashMap::new();
+    write_symbol(&mut symtab, 0, 0, SHN_UNDEF, 0);
+    let (globals, locals): (Vec<_>, Vec<_>) = sections
+        .order
+        .iter()
+        .partition(|name| name.as_str() == program.entry());
+    for name in &locals {
+        let (kind, offset) = sections.symbols[name.as_str()];
+        indices.insert(name.to_string(), symtab.len() as u64 / SYM_SIZE);
+        let info = (STB_LOCAL << 4) | STT_NOTYPE;
+        write_symbol(
+            &mut symtab,
+            strtab.add(name),
+            info,
+            kind.index(),
+            offset as u64,
+        );
+    }
+    let first_global = symtab.len() as u64 / SYM_SIZE;
+    for name in &globals {
+        let (kind, offset) = sections.symbols[name.as_str()];
+        indices.insert(name.to_string(), symtab.len() as u64 / SYM_SIZE);
+        let info = (STB_GLOBAL << 4) | STT_FUNC;
+        write_symbol(
+            &mut symtab,
+            strtab.add(name),
+            info,
+            kind.index(),
+            offset as u64,
+        );
+    }
+
+    let mut rela = Vec::new();
+    for relocation in &sections.get(Kind::Text).relocations {
+        let symbol = match indices.get(&relocation.symbol) {
+            Some(&index) => index,
+            None => {
+                let index = symtab.len() as u64 / SYM_SIZE;
+                let info = (STB_GLOBAL << 4) | STT_NOTYPE;
+                write_symbol(
+                    &mut symtab,
+                    strtab.add(&relocation.symbol),
+                    info,
+                    SHN_UNDEF,
+                    0,
+                );
+                indices.insert(relocation.symbol.clone(), index);
+                index
+            }
+        };
+        put_u64(&mut rela, relocation.offset as u64);
+        let kind = match relocation.reference {
+            Reference::Branch => R_X86_64_PLT32,
+            Reference::Data => R_X86_64_PC32,
+        };
+        put_u64(&mut rela, (symbol << 32) | kind as u64);
+        put_u64(&mut rela, relocation.addend as u64);
+    }
+
+    let mut out = vec![0; EHDR_SIZE as usize];
+    let mut headers = SectionHeaders::new();
+    for kind in Kind::ALL {
+        let encoded = sections.get(kind);
+        let offset = align_to(&mut out, kind.align());
+        let size = encoded.code.len() as u64;
+        let kind_type = if kind == Kind::Bss {
+            SHT_NOBITS
+        } else {
+            out.extend(&encoded.code);
+            SHT_PROGBITS
+        };
+        headers.add(Header {
+            name: kind.name(),
+            kind: kind_type,
+            flags: kind.flags(),
+            offset,
+            size,
+            align: kind.align(),
+            ..Header::default()
+        });
+    }
+    // An empty .note.GNU-stack marks the stack as non executable.
+    headers.add(Header {
+        name: ".note.GNU-stack",
+        kind: SHT_PROGBITS,
+        offset: out.len() as u64,
+        align: 1,
+        ..Header::default()
+    });
+    let symtab_index = headers.len() + 1;
+    let offset = align_to(&mut out, 8);
+    out.extend(&rela);
+    headers.add(Header {
+        name: ".rela.text",
+        kind: SHT_RELA,
+        flags: SHF_INFO_LINK,
+        offset,
+        size: rela.len() as u64,
+        link: symtab_index,
+        info: Kind::Text.index() as u32,
+        align: 8,
+        entsize: RELA_SIZE,
+        ..Header::default()
+    });
+    headers.symbols(&mut out, &symtab, &strtab, first_global as u32);
+    let shoff = headers.write(&mut out);
+
+    write_ehdr(
+        &mut out,
+        ElfHeader {
+            kind: ET_REL,
+            entry: 0,
+            phoff: 0,
+            phnum: 0,
+            shoff,
+            shnum: headers.len() as u16,
+        },
+    );
+    Ok(out)
+}
+
+/// Writes `program` as a statically linked executable (`ET_EXEC`) that
+/// starts at the program's entry point.
+///
+/// Every branch has to target a label of the program itself, there is
+/// nothing to link against.
+pub(crate) fn executable(program: &Program) -> Result<Vec<u8>, Error> {
+    let mut sections = Sections::new(program)?;
+    let writable = sections.size(Kind::Data) + sections.size(Kind::Bss) > 0;
+    let phnum = if writable { 2 } else { 1 };
+
+    // Headers, .text and .rodata share the first (r-x) segment; .data and
+    // .bss start on a fresh page in the second (rw-) one.
+    let mut offsets = HashMap::new();
+    let mut offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    for kind in [Kind::Text, Kind::Rodata] {
+        offset = offset.next_multiple_of(kind.align());
+        offsets.insert(kind, offset);
+        offset += sections.size(kind);
+    }
+    let text_end = offset;
+    offset = offset.next_multiple_of(PAGE_SIZE);
+    let data_start = offset;
+    offsets.insert(Kind::Data, offset);
+    offset += sections.size(Kind::Data);
+    let data_end = offset;
+    offset = offset.next_multiple_of(Kind::Bss.align());
+    offsets.insert(Kind::Bss, offset);
+    let bss_end = offset + sections.size(Kind::Bss);
+    let address = |kind: Kind, offset: usize| BASE_ADDRESS + offsets[&kind] + offset as u64;
+
+    let entry = match sections.symbols.get(program.entry()) {
+        Some(&(kind, offset)) => address(kind, offset),
+        None => return Err(Error::NoEntry(program.entry().to_string())),
+    };
+    let mut patches = Vec::new();
+    for relocation in &sections.get(Kind::Text).relocations {
+        let Some(&(kind, target)) = sections.symbols.get(&relocation.symbol) else {
+            return Err(Error::UndefinedSymbol(relocation.symbol.clone()));
+        };
+        let place = address(Kind::Text, relocation.offset) as i64;
+        let value = address(kind, target) as i64 + relocation.addend - place;
+        let value = i32::try_from(value)
+            .map_err(|_| Error::RelocationOverflow(relocation.symbol.clone()))?;
+        patches.push((relocation.offset, value));
+    }
+    let text = &mut sections.get_mut(Kind::Text).code;
+    for (offset, value) in patches {
+        text[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    let mut out = vec![0; (EHDR_SIZE + phnum * PHDR_SIZE) as usize];
+    let mut headers = SectionHeaders::new();
+    for kind in Kind::ALL {
+        let code = &sections.get(kind).code;
+        let kind_type = if kind == Kind::Bss {
+            SHT_NOBITS
+        } else {
+            out.resize(offsets[&kind] as usize, 0);
+            out.extend(code);
+            SHT_PROGBITS
+        };
+        headers.add(Header {
+            name: kind.name(),
+            kind: kind_type,
+            flags: kind.flags(),
+            address: BASE_ADDRESS + offsets[&kind],
+            offset: offsets[&kind],
+            size: code.len() as u64,
+            align: kind.align(),
+            ..Header::default()
+        });
+    }
+    let mut strtab = StringTable::new();
+    let mut symtab = Vec::new();
+    write_symbol(&mut symtab, 0, 0, SHN_UNDEF, 0);
+    let mut first_global = 0;
+    for global in [false, true] {
+        if global {
+            first_global = symtab.len() as u64 / SYM_SIZE;
+        }
+        for name in &sections.order {
+            if (name == program.entry()) != global {
+                continue;
+            }
+            let (kind, offset) = sections.symbols[name];
+            let info = if global {
+                (STB_GLOBAL << 4) | STT_FUNC
+            } else {
+                (STB_LOCAL << 4) | STT_NOTYPE
+            };
+            let value = address(kind, offset);
+            write_symbol(&mut symtab, strtab.add(name), info, kind.index(), value);
+        }
+    }
+    headers.symbols(&mut out, &symtab, &strtab, first_global as u32);
+    let shoff = headers.write(&mut out);
+
+    let mut phdrs = Vec::new();
+    write_phdr(&mut phdrs, PF_R | PF_X, 0, text_end, text_end);
+    if writable {
+        write_phdr(
+            &mut phdrs,
+            PF_R | PF_W,
+            data_start,
+            data_end - data_start,
+            bss_end - data_start,
+        );
+    }
+    out[EHDR_SIZE as usize..EHDR_SIZE as usize + phdrs.len()].copy_from_slice(&phdrs);
+    write_ehdr(
+        &mut out,
+        ElfHeader {
+            kind: ET_EXEC,
+            entry,
+            phoff: EHDR_SIZE,
+            phnum: phnum as u16,
+            shoff,
+            shnum: headers.len() as u16,
+        },
+    );
+    Ok(out)
+}
+
+struct ElfHeader {
+    kind: u16,
+    entry: u64,
+    phoff: u64,
+    phnum: u16,
+    shoff: u64,
+    shnum: u16,
+}
+/// Fills in the ELF header at the start of `out`.
+fn write_ehdr(out: &mut [u8], header: ElfHeader) {
+    let mut ehdr = Vec::with_capacity(EHDR_SIZE as usize);
+    // ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_SYSV
+    ehdr.extend(b"\x7fELF\x02\x01\x01\x00");
+    ehdr.extend([0; 8]);
+    put_u16(&mut ehdr, header.kind);
+    put_u16(&mut ehdr, EM_X86_64);
+    put_u32(&mut ehdr, 1);
+    put_u64(&mut ehdr, header.entry);
+    put_u64(&mut ehdr, header.phoff);
+    put_u64(&mut ehdr, header.shoff);
+    put_u32(&mut ehdr, 0);
+    put_u16(&mut ehdr, EHDR_SIZE as u16);
+    put_u16(&mut ehdr, PHDR_SIZE as u16);
+    put_u16(&mut ehdr, header.phnum);
+    put_u16(&mut ehdr, SHDR_SIZE as u16);
+    put_u16(&mut ehdr, header.shnum);
+    // .shstrtab is always the last section.
+    put_u16(&mut ehdr, header.shnum - 1);
+    out[..EHDR_SIZE as usize].copy_from_slice(&ehdr);
+}
+fn write_phdr(out: &mut Vec<u8>, flags: u32, offset: u64, filesz: u64, memsz: u64) {
+    put_u32(out, PT_LOAD);
+    put_u32(out, flags);
+    put_u64(out, offset);
+    put_u64(out, BASE_ADDRESS + offset);
+    put_u64(out, BASE_ADDRESS + offset);
+    put_u64(out, filesz);
+    put_u64(out, memsz);
+    put_u64(out, PAGE_SIZE);
+}
+fn write_symbol(out: &mut Vec<u8>, name: u32, info: u8, section: u16, value: u64) {
+    put_u32(out, name);
+    out.push(info);
+    out.push(0);
+    put_u16(out, section);
+    put_u64(out, value);
+    put_u64(out, 0);
+}
+
+#[derive(Default)]
+struct Header {
+    name: &'static str,
+    kind: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+/// The section header table, starting with the null section.
+struct SectionHeaders {
+    headers: Vec<Header>,
+}
+impl SectionHeaders {
+    fn new() -> Self {
+        Self {
+            headers: vec![Header::default()],
+        }
+    }
+    fn len(&self) -> u32 {
+        self.headers.len() as u32
+    }
+    fn add(&mut self, header: Header) {
+        self.headers.push(header);
+    }
+    /// Appends `.symtab`, `.strtab` and `.shstrtab` to `out` along with
+    /// their headers.
+    fn symbols(
+        &mut self,
+        out: &mut Vec<u8>,
+        symtab: &[u8],
+        strtab: &StringTable,
+        first_global: u32,
+    ) {
+        let offset = align_to(out, 8);
+        out.extend(symtab);
+        self.add(Header {
+            name: ".symtab",
+            kind: SHT_SYMTAB,
+            offset,
+            size: symtab.len() as u64,
+            link: self.len() + 1,
+            info: first_global,
+            align: 8,
+            entsize: SYM_SIZE,
+            ..Header::default()
+        });
+        let offset = out.len() as u64;
+        out.extend(&strtab.bytes);
+        self.add(Header {
+            name: ".strtab",
+            kind: SHT_STRTAB,
+            offset,
+            size: strtab.bytes.len() as u64,
+            align: 1,
+            ..Header::default()
+        });
+    }
+    /// Appends `.shstrtab` and the header table itself, returning the
+    /// table's offset.
+    fn write(&mut self, out: &mut Vec<u8>) -> u64 {
+        let mut shstrtab = StringTable::new();
+        let names = self
+            .headers
+            .iter()
+            .map(|header| shstrtab.add(header.name))
+            .collect::<Vec<_>>();
+        let name = shstrtab.add(".shstrtab");
+        let offset = out.len() as u64;
+        out.extend(&shstrtab.bytes);
+        self.add(Header {
+            name: ".shstrtab",
+            kind: SHT_STRTAB,
+            offset,
+            size: shstrtab.bytes.len() as u64,
+            align: 1,
+            ..Header::default()
+        });
+
+        let shoff = align_to(out, 8);
+        for (header, name) in self.headers.iter().zip(names.into_iter().chain([name])) {
+            put_u32(out, if header.kind == 0 { 0 } else { name });
+            put_u32(out, header.kind);
+            put_u64(out, header.flags);
+            put_u64(out, header.address);
+            put_u64(out, header.offset);
+            put_u64(out, header.size);
+            put_u32(out, header.link);
+            put_u32(out, header.info);
+            put_u64(out, header.align);
+            put_u64(out, header.entsize);
+        }
+        shoff
+    }
+}
+
+/// A `.strtab`/`.shstrtab`, nul separated and starting with an empty name.
+struct StringTable {
+    bytes: Vec<u8>,
+}
+impl StringTable {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// Pads `out` with zeros up to a multiple of `align`, returning the new length.
+fn align_to(out: &mut Vec<u8>, align: u64) -> u64 {
+    let len = (out.len() as u64).next_multiple_of(align);
+    out.resize(len as usize, 0);
+    len
+}
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend(value.to_le_bytes());
+}
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_le_bytes());
+}
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend(value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Blob;
+    use crate::x86_64::{OpCode, Reg};
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+    fn section(name: &str, code: Vec<OpCode>) -> Section {
+        Section::new(name, vec![Blob::from(code)])
+    }
+    /// `_start` comes after another function, loads the address of a
+    /// variable in .data and has a buffer in .bss.
+    fn program(entry: &str) -> Program {
+        let data = section(
+            "data",
+            vec![OpCode::Label("counter".to_string()), OpCode::Dq(7)],
+        );
+        let text = vec![
+            section("helper", vec![OpCode::Ret]),
+            section(
+                entry,
+                vec![
+                    OpCode::Mov(Reg::Rax.into(), 60u32.into()),
+                    OpCode::Lea(Reg::Rdi, "counter".to_string()),
+                    OpCode::Syscall,
+                ],
+            ),
+        ];
+        let bss = section(
+            "bss",
+            vec![OpCode::Label("buffer".to_string()), OpCode::Resb(16)],
+        );
+        Program::new(Some(data), text).with_bss(bss)
+    }
+
+    #[test]
+    fn executable_headers() {
+        let out = executable(&program("_start")).unwrap();
+        assert_eq!(&out[..8], b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(u16_at(&out, 16), ET_EXEC);
+        assert_eq!(u16_at(&out, 18), EM_X86_64);
+        // The headers take 64 + 2 * 56 bytes, .text starts right after
+        // them and `_start` right after `helper`'s `ret`.
+        let text = EHDR_SIZE + 2 * PHDR_SIZE;
+        assert_eq!(u64_at(&out, 24), BASE_ADDRESS + text + 1);
+        assert_eq!(u64_at(&out, 32), EHDR_SIZE);
+        assert_eq!(u16_at(&out, 54), PHDR_SIZE as u16);
+        assert_eq!(u16_at(&out, 56), 2);
+
+        let shoff = u64_at(&out, 40);
+        let shnum = u16_at(&out, 60);
+        assert_eq!(u16_at(&out, 58), SHDR_SIZE as u16);
+        assert_eq!(shoff % 8, 0);
+        assert_eq!(shoff + shnum as u64 * SHDR_SIZE, out.len() as u64);
+        assert_eq!(u16_at(&out, 62), shnum - 1);
+    }
+
+    #[test]
+    fn executable_segments() {
+        let out = executable(&program("_start")).unwrap();
+        // (type, flags, offset, address, file size, memory size, alignment)
+        let phdr = |i: usize| {
+            let at = (EHDR_SIZE + i as u64 * PHDR_SIZE) as usize;
+            assert_eq!(u64_at(&out, at + 16), u64_at(&out, at + 24));
+            (
+                u32_at(&out, at),
+                u32_at(&out, at + 4),
+                u64_at(&out, at + 8),
+                u64_at(&out, at + 16),
+                u64_at(&out, at + 32),
+                u64_at(&out, at + 40),
+                u64_at(&out, at + 48),
+            )
+        };
+        // Headers and 15 bytes of code, padded to .rodata's alignment.
+        assert_eq!(
+            phdr(0),
+            (PT_LOAD, PF_R | PF_X, 0, BASE_ADDRESS, 192, 192, PAGE_SIZE)
+        );
+        // 8 bytes of .data on the next page, then 16 of .bss.
+        assert_eq!(
+            phdr(1),
+            (
+                PT_LOAD,
+                PF_R | PF_W,
+                PAGE_SIZE,
+                BASE_ADDRESS + PAGE_SIZE,
+                8,
+                24,
+                PAGE_SIZE
+            )
+        );
+        assert_eq!(u64_at(&out, PAGE_SIZE as usize), 7);
+    }
+
+    #[test]
+    fn executable_code_is_relocated() {
+        let out = executable(&program("_start")).unwrap();
+        let entry = (u64_at(&out, 24) - BASE_ADDRESS) as usize;
+        assert_eq!(out[entry - 1], 0xC3);
+        assert_eq!(out[entry..entry + 5], [0xB8, 0x3C, 0x00, 0x00, 0x00]);
+        assert_eq!(out[entry + 5..entry + 8], [0x48, 0x8D, 0x3D]);
+        // `counter` is at the start of .data, relative to the end of the
+        // `lea`.
+        let end = BASE_ADDRESS + entry as u64 + 12;
+        let rel = BASE_ADDRESS + PAGE_SIZE - end;
+        assert_eq!(u32_at(&out, entry + 8), rel as u32);
+        assert_eq!(out[entry + 12..entry + 14], [0x0F, 0x05]);
+    }
+
+    #[test]
+    fn executable_errors() {
+        assert_eq!(
+            executable(&program("start")).unwrap_err(),
+            Error::NoEntry("_start".to_string())
+        );
+        let program = Program::new(
+            None,
+            vec![section("_start", vec![OpCode::Call("exit".to_string())])],
+        );
+        assert_eq!(
+            executable(&program).unwrap_err(),
+            Error::UndefinedSymbol("exit".to_string())
+        );
+    }
+
+    #[test]
+    fn object_headers() {
+        let out = object(&program("_start")).unwrap();
+        assert_eq!(&out[..8], b"\x7fELF\x02\x01\x01\x00");
+        assert_eq!(u16_at(&out, 16), ET_REL);
+        assert_eq!(u64_at(&out, 24), 0);
+        assert_eq!(u64_at(&out, 32), 0);
+        assert_eq!(u16_at(&out, 56), 0);
+        let shoff = u64_at(&out, 40);
+        assert_eq!(
+            shoff + u16_at(&out, 60) as u64 * SHDR_SIZE,
+            out.len() as u64
+        );
+    }
+}
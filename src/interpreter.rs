@@ -0,0 +1,800 @@
+//! Evaluates the AST directly, for the REPL and quick scripts that
+//! shouldn't wait for an assembler and linker.
+//!
+//! Local variables live in a linked list of bindings, one per declaration,
+//! so a closure sees exactly the variables declared before it. Globals are
+//! looked up by name when they're used.
+use crate::{
+    ast::{
+        Ast, ClassDecl, Expression, ExpressionTree, FunctionDecl, IfStmt, LiteralExpr, NodeId,
+        Program, ReturnStmt, Statement, VarStmt, WhileStmt,
+    },
+    symbol::Symbol,
+    value::{RuntimeError, Value, MAX_DEPTH},
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, io,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Runtime(RuntimeError),
+    Io(io::Error),
+}
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Self::Runtime(err)
+    }
+}
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Runtime(err) => err.fmt(f),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Why evaluation stopped early.
+enum Unwind {
+    Error(Error),
+    Return(Value),
+}
+impl<T: Into<Error>> From<T> for Unwind {
+    fn from(err: T) -> Self {
+        Self::Error(err.into())
+    }
+}
+
+/// One local variable, and the ones declared before it.
+struct Binding {
+    name: Symbol,
+    value: RefCell<Value>,
+    parent: Scope,
+}
+type Scope = Option<Rc<Binding>>;
+
+/// A function or method with the variables it closes over.
+pub(crate) struct Function {
+    declaration: Rc<FunctionDecl>,
+    /// The tree the body is in.
+    ast: Rc<Ast>,
+    closure: Scope,
+    /// `init` methods always return `this`.
+    is_initializer: bool,
+}
+impl Function {
+    pub(crate) fn name(&self) -> &str {
+        self.declaration.name.as_str()
+    }
+    /// This method with `this` bound to `instance`.
+    fn bind(&self, instance: Rc<Instance>) -> Function {
+        Function {
+            declaration: self.declaration.clone(),
+            ast: self.ast.clone(),
+            closure: Some(Rc::new(Binding {
+                name: Symbol::THIS,
+                value: RefCell::new(Value::Instance(instance)),
+                parent: self.closure.clone(),
+            })),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+/// A function implemented in Rust.
+pub(crate) struct Native {
+    arity: usize,
+    function: fn(&[Value]) -> Value,
+}
+
+pub(crate) struct Class {
+    name: Symbol,
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<Symbol, Rc<Function>>,
+}
+impl Class {
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    fn method(&self, name: Symbol) -> Option<&Rc<Function>> {
+        self.methods.get(&name).or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.method(name))
+        })
+    }
+    fn arity(&self) -> usize {
+        self.method(Symbol::INIT)
+            .map_or(0, |init| init.declaration.params.len())
+    }
+}
+
+pub(crate) struct Instance {
+    class: Rc<Class>,
+    fields: RefCell<HashMap<Symbol, Value>>,
+}
+impl Instance {
+    pub(crate) fn class(&self) -> &Class {
+        &self.class
+    }
+}
+
+pub(crate) struct Interpreter<W: io::Write> {
+    globals: HashMap<Symbol, Value>,
+    scope: Scope,
+    /// The tree of the code running.
+    ast: Rc<Ast>,
+    out: W,
+    /// How many blocks and function bodies we're in, variables declared
+    /// outside any are global.
+    nesting: usize,
+    /// How many calls haven't returned yet.
+    depth: usize,
+}
+impl<W: io::Write> Interpreter<W> {
+    /// An interpreter printing to `out`, with the native functions defined.
+    pub(crate) fn new(out: W) -> Self {
+        let mut globals = HashMap::new();
+        globals.insert(
+            Symbol::CLOCK,
+            Value::Native(Rc::new(Native {
+                arity: 0,
+                function: |_| {
+                    let time = SystemTime::now().duration_since(UNIX_EPOCH);
+                    Value::Number(time.map_or(0.0, |time| time.as_secs_f64()))
+                },
+            })),
+        );
+        Self {
+            globals,
+            scope: None,
+            ast: Rc::default(),
+            out,
+            nesting: 0,
+            depth: 0,
+        }
+    }
+    /// Runs `program`, the globals it defines stay for the next one.
+    pub(crate) fn run(&mut self, program: &Program) -> Result<(), Error> {
+        self.ast = program.ast.clone();
+        let result = program
+            .statements
+            .iter()
+            .try_for_each(|statement| self.execute(*statement));
+        self.reset();
+        self.out.flush()?;
+        match result {
+            Ok(()) => Ok(()),
+            Err(Unwind::Error(err)) => Err(err),
+            Err(Unwind::Return(_)) => unreachable!("the parser rejects top-level returns"),
+        }
+    }
+    /// Evaluates one expression, for the REPL to print.
+    pub(crate) fn evaluate(&mut self, expression: &ExpressionTree) -> Result<Value, Error> {
+        self.ast = expression.ast.clone();
+        let result = self.expression(expression.root);
+        self.reset();
+        self.out.flush()?;
+        match result {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+            Err(Unwind::Return(_)) => unreachable!("the parser rejects top-level returns"),
+        }
+    }
+    /// Runs a line of the REPL, printing the value if it's an expression.
+    /// Gives the syntax or runtime error it stopped at.
+    pub(crate) fn run_line(&mut self, input: &str) -> Result<(), String> {
+        let result = match input.parse::<ExpressionTree>() {
+            Ok(expression) => self.evaluate(&expression).and_then(|value| {
+                writeln!(self.out, "{value}")?;
+                Ok(self.out.flush()?)
+            }),
+            Err(_) => {
+                let program = input.parse::<Program>().map_err(|err| err.to_string())?;
+                self.run(&program)
+            }
+        };
+        result.map_err(|err| err.to_string())
+    }
+    /// Back at the top level, a runtime error can leave us anywhere.
+    fn reset(&mut self) {
+        self.scope = None;
+        self.nesting = 0;
+        self.depth = 0;
+    }
+    /// Declares a variable in the current scope, global if there's no
+    /// local one.
+    fn declare(&mut self, name: Symbol, value: Value) {
+        if self.nesting > 0 {
+            self.scope = Some(Rc::new(Binding {
+                name,
+                value: RefCell::new(value),
+                parent: self.scope.take(),
+            }));
+        } else {
+            self.globals.insert(name, value);
+        }
+    }
+    fn binding(&self, name: Symbol) -> Option<&Rc<Binding>> {
+        let mut scope = self.scope.as_ref();
+        while let Some(binding) = scope {
+            if binding.name == name {
+                return Some(binding);
+            }
+            scope = binding.parent.as_ref();
+        }
+        None
+    }
+    fn lookup(&self, name: Symbol, line: usize) -> Result<Value, RuntimeError> {
+        match self.binding(name) {
+            Some(binding) => Ok(binding.value.borrow().clone()),
+            None => self
+                .globals
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::new(format!("Undefined variable '{name}'."), line)),
+        }
+    }
+    fn assign(&mut self, name: Symbol, value: Value, line: usize) -> Result<(), RuntimeError> {
+        if let Some(binding) = self.binding(name) {
+            *binding.value.borrow_mut() = value;
+            return Ok(());
+        }
+        match self.globals.get_mut(&name) {
+            Some(global) => {
+                *global = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::new(
+                format!("Undefined variable '{name}'."),
+                line,
+            )),
+        }
+    }
+    fn execute(&mut self, statement: NodeId) -> Result<(), Unwind> {
+        let ast = self.ast.clone();
+        let line = ast.span(statement).line;
+        match ast.statement(statement) {
+            Statement::Expression(expression) => {
+                self.expression(*expression)?;
+            }
+            Statement::Print(expression) => {
+                let value = self.expression(*expression)?;
+                writeln!(self.out, "{value}")?;
+            }
+            Statement::Var(VarStmt {
+                name, initializer, ..
+            }) => {
+                let value = match initializer {
+                    Some(initializer) => self.expression(*initializer)?,
+                    None => Value::Nil,
+                };
+                self.declare(*name, value);
+            }
+            Statement::Block(statements) => {
+                let scope = self.scope.clone();
+                self.nesting += 1;
+                let result = statements
+                    .iter()
+                    .try_for_each(|statement| self.execute(*statement));
+                self.nesting -= 1;
+                self.scope = scope;
+                result?;
+            }
+            Statement::If(IfStmt {
+                condition,
+                then,
+                otherwise,
+            }) => {
+                if self.expression(*condition)?.is_truthy() {
+                    self.execute(*then)?;
+                } else if let Some(otherwise) = otherwise {
+                    self.execute(*otherwise)?;
+                }
+            }
+            Statement::While(WhileStmt { condition, body }) => {
+                while self.expression(*condition)?.is_truthy() {
+                    self.execute(*body)?;
+                }
+            }
+            Statement::Function(declaration) => {
+                // Declared first so the function can call itself.
+                self.declare(declaration.name, Value::Nil);
+                let function = Value::Function(Rc::new(Function {
+                    declaration: declaration.clone(),
+                    ast: ast.clone(),
+                    closure: self.scope.clone(),
+                    is_initializer: false,
+                }));
+                self.assign(declaration.name, function, line)?;
+            }
+            Statement::Return(ReturnStmt { value, .. }) => {
+                let value = match value {
+                    Some(value) => self.expression(*value)?,
+                    None => Value::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Statement::Class(class) => self.class(class, line)?,
+        }
+        Ok(())
+    }
+    fn class(&mut self, class: &ClassDecl, line: usize) -> Result<(), Unwind> {
+        let superclass = match class.superclass {
+            Some(superclass) => match self.expression(superclass)? {
+                Value::Class(superclass) => Some(superclass),
+                _ => {
+                    let line = self.ast.span(superclass).line;
+                    return Err(RuntimeError::new("Superclass must be a class.", line).into());
+                }
+            },
+            None => None,
+        };
+        self.declare(class.name, Value::Nil);
+        let mut closure = self.scope.clone();
+        if let Some(superclass) = &superclass {
+            closure = Some(Rc::new(Binding {
+                name: Symbol::SUPER,
+                value: RefCell::new(Value::Class(superclass.clone())),
+                parent: closure,
+            }));
+        }
+        let methods = class
+            .methods
+            .iter()
+            .map(|method| {
+                let Statement::Function(method) = self.ast.statement(*method) else {
+                    unreachable!("methods are function declarations")
+                };
+                let function = Function {
+                    declaration: method.clone(),
+                    ast: self.ast.clone(),
+                    closure: closure.clone(),
+                    is_initializer: method.name == Symbol::INIT,
+                };
+                (method.name, Rc::new(function))
+            })
+            .collect();
+        let value = Value::Class(Rc::new(Class {
+            name: class.name,
+            superclass,
+            methods,
+        }));
+        self.assign(class.name, value, line)?;
+        Ok(())
+    }
+    fn expression(&mut self, expression: NodeId) -> Result<Value, Unwind> {
+        let ast = self.ast.clone();
+        let line = ast.span(expression).line;
+        Ok(match ast.expression(expression) {
+            Expression::Literal(literal) => match literal {
+                LiteralExpr::Nil => Value::Nil,
+                LiteralExpr::True => Value::Bool(true),
+                LiteralExpr::False => Value::Bool(false),
+                LiteralExpr::Number(n) => Value::Number(*n),
+                LiteralExpr::String(s) => Value::String(s.as_str().into()),
+            },
+            Expression::Grouping(inner) => self.expression(*inner)?,
+            Expression::ToString(inner) => {
+                Value::String(self.expression(*inner)?.to_string().into())
+            }
+            Expression::Unary(unary) => {
+                let value = self.expression(unary.expression)?;
+                match unary.operator {
+                    "-" => value.negate(line)?,
+                    "!" => Value::Bool(!value.is_truthy()),
+                    operator => unreachable!("unknown unary operator {operator}"),
+                }
+            }
+            Expression::Binary(binary) => {
+                let lhs = self.expression(binary.lhs)?;
+                let rhs = self.expression(binary.rhs)?;
+                match binary.operator {
+                    "+" => lhs.add(&rhs, line)?,
+                    "==" => Value::Bool(lhs == rhs),
+                    "!=" => Value::Bool(lhs != rhs),
+                    operator => {
+                        let (a, b) = lhs.numbers(&rhs, line)?;
+                        match operator {
+                            "-" => Value::Number(a - b),
+                            "*" => Value::Number(a * b),
+                            "/" => Value::Number(a / b),
+                            "%" => Value::Number(a % b),
+                            "<" => Value::Bool(a < b),
+                            "<=" => Value::Bool(a <= b),
+                            ">" => Value::Bool(a > b),
+                            ">=" => Value::Bool(a >= b),
+                            operator => unreachable!("unknown binary operator {operator}"),
+                        }
+                    }
+                }
+            }
+            Expression::Variable(variable) => self.lookup(variable.name, line)?,
+            Expression::Assign(assign) => {
+                let value = self.expression(assign.value)?;
+                self.assign(assign.name, value.clone(), line)?;
+                value
+            }
+            Expression::Logical(logical) => {
+                let lhs = self.expression(logical.lhs)?;
+                let decided = match logical.operator {
+                    "or" => lhs.is_truthy(),
+                    _ => !lhs.is_truthy(),
+                };
+                if decided {
+                    lhs
+                } else {
+                    self.expression(logical.rhs)?
+                }
+            }
+            Expression::Conditional(conditional) => {
+                if self.expression(conditional.condition)?.is_truthy() {
+                    self.expression(conditional.then)?
+                } else {
+                    self.expression(conditional.otherwise)?
+                }
+            }
+            Expression::Call(call) => {
+                let callee = self.expression(call.callee)?;
+                let arguments = call
+                    .arguments
+                    .iter()
+                    .map(|argument| self.expression(*argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(callee, arguments, line)?
+            }
+            Expression::Get(get) => match self.expression(get.object)? {
+                Value::Instance(instance) => {
+                    let field = instance.fields.borrow().get(&get.name).cloned();
+                    match field {
+                        Some(value) => value,
+                        None => match instance.class.method(get.name) {
+                            Some(method) => Value::Function(Rc::new(method.bind(instance.clone()))),
+                            None => {
+                                return Err(RuntimeError::new(
+                                    format!("Undefined property '{}'.", get.name),
+                                    line,
+                                )
+                                .into())
+                            }
+                        },
+                    }
+                }
+                _ => return Err(RuntimeError::new("Only instances have properties.", line).into()),
+            },
+            Expression::Set(set) => {
+                let Value::Instance(instance) = self.expression(set.object)? else {
+                    return Err(RuntimeError::new("Only instances have fields.", line).into());
+                };
+                let value = self.expression(set.value)?;
+                instance.fields.borrow_mut().insert(set.name, value.clone());
+                value
+            }
+            Expression::This => self.lookup(Symbol::THIS, line)?,
+            Expression::Super(sup) => {
+                let Value::Class(superclass) = self.lookup(Symbol::SUPER, line)? else {
+                    unreachable!("'super' is always bound to a class")
+                };
+                let Value::Instance(instance) = self.lookup(Symbol::THIS, line)? else {
+                    unreachable!("'this' is always bound to an instance")
+                };
+                match superclass.method(sup.method) {
+                    Some(method) => Value::Function(Rc::new(method.bind(instance))),
+                    None => {
+                        return Err(RuntimeError::new(
+                            format!("Undefined property '{}'.", sup.method),
+                            line,
+                        )
+                        .into())
+                    }
+                }
+            }
+        })
+    }
+    fn call(&mut self, callee: Value, arguments: Vec<Value>, line: usize) -> Result<Value, Unwind> {
+        let arity = match &callee {
+            Value::Function(function) => function.declaration.params.len(),
+            Value::Native(native) => native.arity,
+            Value::Class(class) => class.arity(),
+            _ => {
+                return Err(RuntimeError::new("Can only call functions and classes.", line).into())
+            }
+        };
+        if arguments.len() != arity {
+            return Err(RuntimeError::new(
+                format!("Expected {arity} arguments but got {}.", arguments.len()),
+                line,
+            )
+            .into());
+        }
+        if self.depth == MAX_DEPTH {
+            return Err(RuntimeError::new("Stack overflow.", line).into());
+        }
+        match callee {
+            Value::Function(function) => self.call_function(&function, arguments),
+            Value::Native(native) => Ok((native.function)(&arguments)),
+            Value::Class(class) => {
+                let instance = Rc::new(Instance {
+                    class: class.clone(),
+                    fields: RefCell::new(HashMap::new()),
+                });
+                if let Some(init) = class.method(Symbol::INIT) {
+                    self.call_function(&init.bind(instance.clone()), arguments)?;
+                }
+                Ok(Value::Instance(instance))
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn call_function(
+        &mut self,
+        function: &Function,
+        arguments: Vec<Value>,
+    ) -> Result<Value, Unwind> {
+        let mut scope = function.closure.clone();
+        for (param, argument) in function.declaration.params.iter().zip(arguments) {
+            scope = Some(Rc::new(Binding {
+                name: *param,
+                value: RefCell::new(argument),
+                parent: scope,
+            }));
+        }
+        let caller = std::mem::replace(&mut self.scope, scope);
+        let caller_ast = std::mem::replace(&mut self.ast, function.ast.clone());
+        self.nesting += 1;
+        self.depth += 1;
+        let result = function
+            .declaration
+            .body
+            .iter()
+            .try_for_each(|statement| self.execute(*statement));
+        self.depth -= 1;
+        self.nesting -= 1;
+        self.ast = caller_ast;
+        self.scope = caller;
+        let value = match result {
+            Ok(()) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(err) => return Err(err),
+        };
+        if function.is_initializer {
+            return Ok(function
+                .closure
+                .as_ref()
+                .expect("methods have 'this' bound")
+                .value
+                .borrow()
+                .clone());
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `source` prints, then the error it stops with, if any.
+    fn run(source: &str) -> String {
+        let mut interpreter = Interpreter::new(Vec::new());
+        let result = interpreter.run(&source.parse().unwrap());
+        let mut output = String::from_utf8(interpreter.out).unwrap();
+        if let Err(err) = result {
+            output += &format!("error: {err}\n");
+        }
+        output
+    }
+
+    #[test]
+    fn closures_capture_their_variables() {
+        let source = "
+fun counter() {
+  var count = 0;
+  fun increment() {
+    count = count + 1;
+    return count;
+  }
+  return increment;
+}
+var a = counter();
+var b = counter();
+print a();
+print a();
+print b();
+print a;
+";
+        assert_eq!(run(source), "1\n2\n1\n<fn increment>\n");
+    }
+
+    #[test]
+    fn closures_see_the_variables_declared_before_them() {
+        let source = "
+var a = \"global\";
+{
+  fun show() { print a; }
+  show();
+  var a = \"block\";
+  show();
+  print a;
+}
+";
+        assert_eq!(run(source), "global\nglobal\nblock\n");
+    }
+
+    #[test]
+    fn classes() {
+        let source = "
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  }
+  sum() { return this.x + this.y; }
+}
+var p = Point(1, 2);
+print p.sum();
+var sum = p.sum;
+p.x = 10;
+print sum();
+print p;
+print Point;
+p.sum = \"field\";
+print p.sum;
+";
+        assert_eq!(run(source), "3\n12\nPoint instance\nPoint\nfield\n");
+    }
+
+    #[test]
+    fn init_returns_this() {
+        let source = "
+class Foo {
+  init(early) {
+    this.value = 1;
+    if (early) return;
+    this.value = 2;
+  }
+}
+var foo = Foo(true);
+print foo.value;
+print foo.init(false) == foo;
+print foo.value;
+";
+        assert_eq!(run(source), "1\ntrue\n2\n");
+    }
+
+    #[test]
+    fn inheritance_and_super() {
+        let source = "
+class A {
+  name() { return \"A\"; }
+  greet() { return \"hi from \" + this.name(); }
+}
+class B < A {
+  name() { return \"B\"; }
+  greet() { return super.greet() + \" via \" + super.name(); }
+}
+class C < B {}
+print C().greet();
+print C().name();
+";
+        assert_eq!(run(source), "hi from B via A\nB\n");
+    }
+
+    #[test]
+    fn clock() {
+        assert_eq!(run("print clock() > 1e9;"), "true\n");
+        assert_eq!(run("print clock;"), "<native fn>\n");
+        assert_eq!(
+            run("clock(1);"),
+            "error: Expected 0 arguments but got 1.\n[line 1]\n"
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        for (source, expected) in [
+            ("print x;", "Undefined variable 'x'.\n[line 1]"),
+            ("x = 1;", "Undefined variable 'x'.\n[line 1]"),
+            ("print -\"a\";", "Operand must be a number."),
+            ("print 1 < \"a\";", "Operands must be numbers."),
+            (
+                "print 1 + nil;",
+                "Operands must be two numbers or two strings.",
+            ),
+            ("\"a\"();", "Can only call functions and classes."),
+            (
+                "fun f(a) {}\nf();",
+                "Expected 1 arguments but got 0.\n[line 2]",
+            ),
+            (
+                "class A {}\nA(1);",
+                "Expected 0 arguments but got 1.\n[line 2]",
+            ),
+            ("print 1 .x;", "Only instances have properties."),
+            (
+                "var a = 1;\na.x = 2;",
+                "Only instances have fields.\n[line 2]",
+            ),
+            (
+                "class A {}\nprint A().x;",
+                "Undefined property 'x'.\n[line 2]",
+            ),
+            (
+                "var A = 1;\nclass B < A {}",
+                "Superclass must be a class.\n[line 2]",
+            ),
+            (
+                "class A {}\nclass B < A { f() { return super.f; } }\nB().f();",
+                "Undefined property 'f'.\n[line 2]",
+            ),
+        ] {
+            let output = run(source);
+            assert!(
+                output.starts_with(&format!("error: {expected}")),
+                "{source:?} gave {output:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn deep_recursion_is_a_runtime_error() {
+        // Test threads get less stack than the main thread the interpreter
+        // normally runs on.
+        let output = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| run("fun f() { f(); }\nf();"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(output, "error: Stack overflow.\n[line 1]\n");
+    }
+
+    #[test]
+    fn output_before_an_error_is_kept() {
+        assert_eq!(
+            run("print 1;\nprint nil + 1;\nprint 2;"),
+            "1\nerror: Operands must be two numbers or two strings.\n[line 2]\n"
+        );
+    }
+
+    /// The REPL runs each line with the same interpreter.
+    #[test]
+    fn repl_lines_share_globals_and_functions() {
+        let mut interpreter = Interpreter::new(Vec::new());
+        for line in [
+            "var a = 1;",
+            "fun f(b) { return a + b; }",
+            "f(2)",
+            "a = 10",
+            "f(2)",
+        ] {
+            interpreter.run_line(line).unwrap();
+        }
+        // An error part way through a block leaves the interpreter at the
+        // top level again.
+        assert_eq!(
+            interpreter.run_line("{ var b = 1; print b; c; }"),
+            Err("Undefined variable 'c'.\n[line 1]".to_string())
+        );
+        interpreter.run_line("var b = 3;").unwrap();
+        interpreter.run_line("b").unwrap();
+        assert_eq!(
+            interpreter.run_line("print"),
+            Err("[line 1] Error at end: Expect expression.".to_string())
+        );
+        assert_eq!(
+            String::from_utf8(interpreter.out).unwrap(),
+            "3\n10\n12\n1\n3\n"
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use crate::x86_64::OpCode;
-use std::fmt;
+use crate::x86_64::{
+    abi::Target,
+    printer::{Nasm, Syntax},
+    OpCode,
+};
+use std::{collections::HashSet, fmt};
 #[repr(transparent)]
 pub(crate) struct Blob(Vec<OpCode>);
 impl<T: Into<Vec<OpCode>>> From<T> for Blob {
@@ -44,6 +48,22 @@ impl Section {
             code,
         }
     }
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+    pub(crate) fn code_mut(&mut self) -> &mut Vec<Blob> {
+        &mut self.code
+    }
+}
+impl<'a> IntoIterator for &'a Section
+where
+    Self: 'a,
+{
+    type Item = &'a Blob;
+    type IntoIter = std::slice::Iter<'a, Blob>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.code.iter()
+    }
 }
 impl fmt::Display for Section {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -57,27 +77,154 @@ impl fmt::Display for Section {
 }
 pub(crate) struct Program {
     data: Option<Section>,
+    rodata: Option<Section>,
+    bss: Option<Section>,
     text: Vec<Section>,
+    /// The global symbol execution starts at.
+    entry: &'static str,
 }
 impl Program {
     pub(crate) fn new(data: Option<Section>, text: Vec<Section>) -> Program {
-        Program { data, text }
+        Program {
+            data,
+            rodata: None,
+            bss: None,
+            text,
+            entry: Target::default().entry(),
+        }
+    }
+    /// Adds the entry point `target` starts the program at.
+    pub(crate) fn with_target(mut self, target: Target) -> Program {
+        self.entry = target.entry();
+        if let Some(section) = target.entry_section() {
+            self.text.insert(0, section);
+        }
+        self
+    }
+    pub(crate) fn entry(&self) -> &str {
+        self.entry
+    }
+    /// Labels the program branches to without defining them, i.e. the
+    /// functions it needs to be linked against.
+    pub(crate) fn externs(&self) -> Vec<&str> {
+        let mut defined = HashSet::new();
+        let mut used = Vec::new();
+        let sections = [&self.data, &self.rodata, &self.bss]
+            .into_iter()
+            .flatten()
+            .chain(&self.text);
+        for section in sections {
+            defined.insert(section.name.as_str());
+            for instruction in section.code.iter().flatten() {
+                match instruction {
+                    OpCode::Label(name) => {
+                        defined.insert(name.as_str());
+                    }
+                    OpCode::Call(name)
+                    | OpCode::Jmp(name)
+                    | OpCode::Jcc(_, name)
+                    | OpCode::Lea(_, name) => used.push(name.as_str()),
+                    _ => (),
+                }
+            }
+        }
+        let mut externs = Vec::new();
+        for name in used {
+            if !defined.contains(name) && !externs.contains(&name) {
+                externs.push(name);
+            }
+        }
+        externs
+    }
+    pub(crate) fn with_rodata(mut self, rodata: Section) -> Program {
+        self.rodata = Some(rodata);
+        self
+    }
+    pub(crate) fn with_bss(mut self, bss: Section) -> Program {
+        self.bss = Some(bss);
+        self
+    }
+    pub(crate) fn data(&self) -> Option<&Section> {
+        self.data.as_ref()
+    }
+    pub(crate) fn rodata(&self) -> Option<&Section> {
+        self.rodata.as_ref()
+    }
+    pub(crate) fn bss(&self) -> Option<&Section> {
+        self.bss.as_ref()
+    }
+    pub(crate) fn text(&self) -> &[Section] {
+        &self.text
+    }
+    pub(crate) fn text_mut(&mut self) -> &mut [Section] {
+        &mut self.text
     }
 }
 
+impl Program {
+    /// The program as assembly text in the given syntax.
+    pub(crate) fn listing<'a>(&'a self, syntax: &'a dyn Syntax) -> Listing<'a> {
+        Listing {
+            program: self,
+            syntax,
+        }
+    }
+}
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(section) = &self.data {
-            writeln!(f, "section .data")?;
-            for glob in &section.code {
-                writeln!(f, "{glob}")?;
+        self.listing(&Nasm).fmt(f)
+    }
+}
+
+pub(crate) struct Listing<'a> {
+    program: &'a Program,
+    syntax: &'a dyn Syntax,
+}
+impl Listing<'_> {
+    fn blob(&self, f: &mut fmt::Formatter<'_>, blob: &Blob, externs: &[&str]) -> fmt::Result {
+        for instruction in blob {
+            write!(f, "    ")?;
+            match instruction {
+                OpCode::Call(name) if externs.contains(&name.as_str()) => {
+                    self.syntax.call_external(f, name)?
+                }
+                _ => self.syntax.instruction(f, instruction)?,
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program;
+        let externs = program.externs();
+        self.syntax.prologue(f)?;
+        for name in &externs {
+            self.syntax.external(f, name)?;
+        }
+        for (name, section) in [
+            (".data", &program.data),
+            (".rodata", &program.rodata),
+            (".bss", &program.bss),
+        ] {
+            if let Some(section) = section {
+                self.syntax.section(f, name)?;
+                for glob in &section.code {
+                    self.blob(f, glob, &externs)?;
+                    writeln!(f)?;
+                }
             }
         }
-        writeln!(f, "section .text")?;
-        writeln!(f, "  global _start")?;
-        for glob in &self.text {
-            write!(f, "{glob}")?;
+        self.syntax.section(f, ".text")?;
+        self.syntax.global(f, program.entry)?;
+        for section in &program.text {
+            writeln!(f, "{}:", section.name)?;
+            for blob in &section.code {
+                self.blob(f, blob, &externs)?;
+                writeln!(f)?;
+            }
         }
-        Ok(())
+        self.syntax.epilogue(f)
     }
 }
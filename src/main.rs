@@ -1,90 +1,695 @@
 use std::{
     fs::{self, File},
-    io::{Read, Write},
+    io::{self, Read, Write},
+    os::unix::{fs::OpenOptionsExt, process::ExitStatusExt},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 mod ast;
+mod build_dir;
+mod elf;
+mod interpreter;
 mod ir;
+mod mir;
+mod passes;
+mod symbol;
+mod value;
 mod x86_64;
+use build_dir::BuildDir;
+use interpreter::Interpreter;
 use ir::Program;
-const LOX_COMP_BUF: &'static str = "/tmp/out";
+use passes::{Pass, Passes};
+use x86_64::{
+    abi::Target,
+    printer::{GasAtt, GasIntel, Nasm, Syntax},
+};
+
+const USAGE: &str = "Usage: lox [run [--interp | --tree-walk]] [--keep-temps] [--print-mir] \
+[-O0 | -O1 | -O2] [--passes=<pass,...>] [--disable-pass=<pass,...>] [--print-after=<pass,...>] \
+[--assembler=<nasm|gas|builtin>] [--syntax=<att|intel>] [--libc [--no-main]] [-S | -c] \
+[-o <output>] [file]";
+
+/// How the generated assembly becomes machine code.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+enum Assembler {
+    /// Assemble with nasm and link with ld.
+    #[default]
+    Nasm,
+    /// Assemble with GNU as and link with ld.
+    Gas,
+    /// Encode and write the ELF file ourselves, no external tools needed.
+    Builtin,
+}
+
+/// The GNU as dialect to print, see [`Assembler::Gas`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+enum GasSyntax {
+    #[default]
+    Att,
+    Intel,
+}
+
+/// How far to take the compilation.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+enum Stop {
+    /// Write the assembly listing, `-S`.
+    Assembly,
+    /// Write a relocatable object file, `-c`.
+    Object,
+    #[default]
+    Executable,
+}
 
-use ast::{scanner::Scanner, BinaryExpr, Expression, LiteralExpr, UnaryExpr};
-fn compile_program(program: Program) -> std::io::Result<()> {
-    let asm_path = format!("{LOX_COMP_BUF}.asm");
-    let obj_path = format!("{LOX_COMP_BUF}.o");
+/// Command line options for the `lox` binary.
+#[derive(Default)]
+struct Options {
+    /// Run the script after compiling it, `lox run`.
+    run: bool,
+    /// With `run`, interpret the mid-level IR as lowered instead of
+    /// compiling.
+    interp: bool,
+    /// With `run`, evaluate the AST instead of compiling.
+    tree_walk: bool,
+    /// Don't remove the build directory after compiling.
+    keep_temps: bool,
+    /// Print the mid-level IR before generating code.
+    print_mir: bool,
+    passes: Passes,
+    /// Print the IR, or the code once it's generated, after these passes.
+    print_after: Vec<Pass>,
+    assembler: Assembler,
+    syntax: GasSyntax,
+    /// Link against libc with `cc` instead of running freestanding.
+    libc: bool,
+    /// With `libc`, leave out `main` so the script can be linked into a C
+    /// program.
+    no_main: bool,
+    stop: Stop,
+    /// Where to write the executable (or listing/object with `-S`/`-c`).
+    output: Option<PathBuf>,
+    file: Option<String>,
+}
+impl Options {
+    fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        // Applied last, so they win over `-O` wherever they are.
+        let mut disabled = Vec::new();
+        // Options that can't be given twice, or would silently override.
+        let mut given = Vec::new();
+        let mut args = args.peekable();
+        if args.peek().map(String::as_str) == Some("run") {
+            args.next();
+            options.run = true;
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--interp" => options.interp = true,
+                "--tree-walk" => options.tree_walk = true,
+                "--keep-temps" => options.keep_temps = true,
+                "--print-mir" => options.print_mir = true,
+                "--libc" => options.libc = true,
+                "--no-main" => options.no_main = true,
+                "-S" => {
+                    once(&mut given, "-S")?;
+                    options.stop = Stop::Assembly;
+                }
+                "-c" => {
+                    once(&mut given, "-c")?;
+                    options.stop = Stop::Object;
+                }
+                "-o" => {
+                    once(&mut given, "-o")?;
+                    match args.next() {
+                        Some(output) => options.output = Some(output.into()),
+                        None => return Err("missing file name after '-o'".to_string()),
+                    }
+                }
+                _ if arg.starts_with("--assembler=") => {
+                    once(&mut given, "--assembler")?;
+                    options.assembler = match &arg["--assembler=".len()..] {
+                        "nasm" => Assembler::Nasm,
+                        "gas" => Assembler::Gas,
+                        "builtin" => Assembler::Builtin,
+                        other => return Err(format!("unknown assembler '{other}'")),
+                    };
+                }
+                _ if arg.starts_with("--syntax=") => {
+                    once(&mut given, "--syntax")?;
+                    options.syntax = match &arg["--syntax=".len()..] {
+                        "att" => GasSyntax::Att,
+                        "intel" => GasSyntax::Intel,
+                        other => return Err(format!("unknown syntax '{other}'")),
+                    };
+                }
+                _ if arg.starts_with("-O") => {
+                    options.passes = arg[2..]
+                        .parse()
+                        .ok()
+                        .and_then(Passes::level)
+                        .ok_or_else(|| format!("unknown optimization level '{arg}'"))?;
+                }
+                _ if arg.starts_with("--passes=") => {
+                    options.passes = Passes::list(&arg["--passes=".len()..])?
+                        .into_iter()
+                        .collect();
+                }
+                _ if arg.starts_with("--disable-pass=") => {
+                    disabled.extend(Passes::list(&arg["--disable-pass=".len()..])?);
+                }
+                _ if arg.starts_with("--print-after=") => {
+                    options
+                        .print_after
+                        .extend(Passes::list(&arg["--print-after=".len()..])?);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{arg}'")),
+                _ if options.file.is_none() => options.file = Some(arg),
+                _ => return Err(format!("unexpected argument '{arg}'")),
+            }
+        }
+        for pass in disabled {
+            options.passes.set(pass, false);
+        }
+        if let Some(pass) = options
+            .print_after
+            .iter()
+            .find(|pass| !options.passes.enabled(**pass))
+        {
+            return Err(format!(
+                "'--print-after={pass}' names a pass that doesn't run"
+            ));
+        }
+        if given.contains(&"-S") && given.contains(&"-c") {
+            return Err("'-S' and '-c' can't be used together".to_string());
+        }
+        if given.contains(&"--syntax") && options.assembler != Assembler::Gas {
+            return Err("'--syntax' needs '--assembler=gas'".to_string());
+        }
+        if options.no_main && !options.libc {
+            return Err("'--no-main' needs '--libc'".to_string());
+        }
+        if options.interp && !options.run {
+            return Err("'--interp' needs 'run'".to_string());
+        }
+        if options.tree_walk && !options.run {
+            return Err("'--tree-walk' needs 'run'".to_string());
+        }
+        if options.interp && !options.print_after.is_empty() {
+            return Err("'--interp' runs no passes to print after".to_string());
+        }
+        if options.interp && options.tree_walk {
+            return Err("'--interp' and '--tree-walk' can't be used together".to_string());
+        }
+        if options.run {
+            if options.file.is_none() {
+                return Err("'run' needs a file".to_string());
+            }
+            if options.stop != Stop::Executable || options.output.is_some() || options.no_main {
+                return Err("'run' only makes executables, without '-o'".to_string());
+            }
+        }
+        Ok(options)
+    }
+    /// The output path: `-o` if given, otherwise the source file's stem
+    /// (with an extension for `-S` and `-c`).
+    fn output_for(&self, file: &str) -> PathBuf {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => {
+                let stem = Path::new(file)
+                    .file_stem()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("a.out"));
+                match self.stop {
+                    Stop::Assembly if self.assembler == Assembler::Nasm => {
+                        stem.with_extension("asm")
+                    }
+                    Stop::Assembly => stem.with_extension("s"),
+                    Stop::Object => stem.with_extension("o"),
+                    Stop::Executable => stem,
+                }
+            }
+        }
+    }
+    fn target(&self) -> Target {
+        if self.libc {
+            Target::Libc {
+                main: !self.no_main,
+            }
+        } else {
+            Target::Freestanding
+        }
+    }
+    fn syntax(&self) -> &'static dyn Syntax {
+        match (self.assembler, self.syntax) {
+            (Assembler::Gas, GasSyntax::Att) => &GasAtt,
+            (Assembler::Gas, GasSyntax::Intel) => &GasIntel,
+            _ => &Nasm,
+        }
+    }
+}
+
+/// Records that `option` was given, failing if it already was.
+fn once(given: &mut Vec<&'static str>, option: &'static str) -> Result<(), String> {
+    if given.contains(&option) {
+        return Err(format!("'{option}' given more than once"));
+    }
+    given.push(option);
+    Ok(())
+}
+fn assemble(
+    program: &Program,
+    object: &Path,
+    options: &Options,
+    build_dir: &BuildDir,
+) -> io::Result<()> {
+    let asm_path = build_dir.join(match options.assembler {
+        Assembler::Nasm => "out.asm",
+        _ => "out.s",
+    });
     let mut file = File::create(&asm_path)?;
-    write!(file, "{program}")?;
-
-    Command::new("nasm")
-        .args(&["-f", "elf64", &asm_path])
-        .stderr(Stdio::inherit())
-        .output()?;
-    Command::new("ld")
-        .arg(&obj_path)
+    write!(file, "{}", program.listing(options.syntax()))?;
+
+    let mut assembler = match options.assembler {
+        Assembler::Gas => Command::new("as"),
+        _ => {
+            let mut nasm = Command::new("nasm");
+            nasm.args(["-f", "elf64"]);
+            nasm
+        }
+    };
+    assembler
+        .arg("-o")
+        .arg(object)
+        .arg(&asm_path)
+        .stderr(Stdio::inherit());
+    check_status(&mut assembler)
+}
+/// Links with ld, or through cc to pull in libc.
+fn link(object: &Path, output: &Path, target: Target) -> io::Result<()> {
+    let mut linker = match target {
+        Target::Freestanding => Command::new("ld"),
+        Target::Libc { .. } => Command::new("cc"),
+    };
+    linker
+        .arg("-o")
+        .arg(output)
+        .arg(object)
         .stdout(Stdio::inherit())
-        .output()?;
-    fs::remove_file(asm_path)?;
-    fs::remove_file(obj_path)?;
-    Ok(())
+        .stderr(Stdio::inherit());
+    check_status(&mut linker)
 }
-fn run<'a>(input: &'a str) -> Result<(), ast::Error> {
-    // Todo: add syncronization to parser
-    match input.parse::<Expression>() {
-        Ok(exp) => println!("{exp}"),
-        Err(err) => eprintln!("{err}"),
+fn check_status(command: &mut Command) -> io::Result<()> {
+    let status = command.status().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("could not run {:?}: {err}", command.get_program()),
+        )
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{:?} failed with {status}",
+            command.get_program()
+        )))
     }
-    Ok(())
 }
-fn run_file(file: &str) -> std::io::Result<Option<Program>> {
+/// Writes the ELF file directly, without an assembler or linker.
+fn write_elf(program: &Program, output: &Path, object_only: bool) -> io::Result<()> {
+    let bytes = if object_only {
+        elf::object(program)
+    } else {
+        elf::executable(program)
+    }
+    .map_err(|err| io::Error::other(err.to_string()))?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(if object_only { 0o644 } else { 0o755 })
+        .open(output)?;
+    file.write_all(&bytes)
+}
+/// Writes `program` to `output`, with any intermediate files in `build_dir`.
+fn compile_program(
+    program: Program,
+    output: &Path,
+    options: &Options,
+    build_dir: &BuildDir,
+) -> io::Result<()> {
+    let target = options.target();
+    let program = program.with_target(target);
+    match (options.stop, options.assembler, target) {
+        (Stop::Assembly, _, _) => {
+            let mut file = File::create(output)?;
+            return write!(file, "{}", program.listing(options.syntax()));
+        }
+        (Stop::Executable, Assembler::Builtin, Target::Freestanding) => {
+            return write_elf(&program, output, false)
+        }
+        _ => (),
+    }
+    let object = match options.stop {
+        Stop::Object => output.to_path_buf(),
+        _ => build_dir.join("out.o"),
+    };
+    match options.assembler {
+        Assembler::Builtin => write_elf(&program, &object, true)?,
+        _ => assemble(&program, &object, options, build_dir)?,
+    }
+    if options.stop == Stop::Object {
+        return Ok(());
+    }
+    link(&object, output, target)
+}
+/// Compiles to a temporary executable and runs it, giving its exit code.
+fn run_program(program: Program, options: &Options) -> io::Result<i32> {
+    let build_dir = BuildDir::new(options.keep_temps)?;
+    let executable = build_dir.join("a.out");
+    compile_program(program, &executable, options, &build_dir)?;
+    let status = Command::new(&executable).status()?;
+    // Like a shell, 128 plus the signal that killed it.
+    Ok(status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+/// Interprets the IR, giving the exit code the compiled script would have.
+fn interpret(module: &mir::Module) -> io::Result<i32> {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let result = mir::interp::run(module, &mut out);
+    out.flush()?;
+    match result {
+        Ok(()) => Ok(0),
+        Err(mir::interp::Error::Runtime(err)) => {
+            eprintln!("{err}");
+            Ok(70)
+        }
+        Err(mir::interp::Error::Io(err)) => Err(err),
+    }
+}
+/// Evaluates the AST, giving the exit code the compiled script would have.
+fn tree_walk(program: &ast::Program) -> io::Result<i32> {
+    let stdout = io::stdout();
+    let mut interpreter = Interpreter::new(io::BufWriter::new(stdout.lock()));
+    match interpreter.run(program) {
+        Ok(()) => Ok(0),
+        Err(interpreter::Error::Runtime(err)) => {
+            eprintln!("{err}");
+            Ok(70)
+        }
+        Err(interpreter::Error::Io(err)) => Err(err),
+    }
+}
+fn parse_file(file: &str) -> io::Result<ast::Program> {
     let mut file = File::open(file)?;
     let mut input = String::new();
     file.read_to_string(&mut input)?;
-    match run(&input) {
-        Ok(()) => (),
+    match input.parse::<ast::Program>() {
+        Ok(program) => {
+            for warning in ast::reachability::warnings(&program) {
+                eprintln!("{warning}");
+            }
+            Ok(program)
+        }
         Err(err) => {
             eprintln!("{err}");
             std::process::exit(65);
         }
     }
-    Ok(None)
 }
-fn run_repl() -> std::io::Result<()> {
+/// Lowers `program` to the mid-level IR, before any optimization.
+fn lower(program: &ast::Program, options: &Options) -> mir::Module {
+    let module = match mir::lower::lower(program) {
+        Ok(module) => module,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(65);
+        }
+    };
+    check(&module, mir::verify::verify(&module));
+    if options.print_mir {
+        print!("{module}");
+    }
+    module
+}
+/// Runs the enabled passes over `module` in SSA form.
+fn optimize(mut module: mir::Module, options: &Options) -> mir::Module {
+    module.functions.iter_mut().for_each(mir::ssa::to_ssa);
+    check(&module, mir::verify::verify_ssa(&module));
+    run_pass(&mut module, Pass::Inline, options, mir::inline::inline);
+    run_pass(&mut module, Pass::Fold, options, |module| {
+        module.functions.iter_mut().for_each(mir::fold::fold);
+    });
+    run_pass(&mut module, Pass::Dce, options, |module| {
+        mir::dce::remove_unused_functions(module);
+        module.functions.iter_mut().for_each(mir::dce::eliminate);
+    });
+    module.functions.iter_mut().for_each(mir::ssa::from_ssa);
+    check(&module, mir::verify::verify(&module));
+    module
+}
+/// Runs `pass` over `module` in SSA form, if it's enabled.
+fn run_pass(
+    module: &mut mir::Module,
+    pass: Pass,
+    options: &Options,
+    run: impl FnOnce(&mut mir::Module),
+) {
+    if !options.passes.enabled(pass) {
+        return;
+    }
+    run(module);
+    check(module, mir::verify::verify_ssa(module));
+    if options.print_after.contains(&pass) {
+        print!("{module}");
+    }
+}
+fn generate(module: &mir::Module, options: &Options) -> Program {
+    let mut program = x86_64::codegen::generate(
+        module,
+        options.target(),
+        options.passes.enabled(Pass::Regalloc),
+    );
+    if options.print_after.contains(&Pass::Regalloc) {
+        print!("{}", program.listing(options.syntax()));
+    }
+    if options.passes.enabled(Pass::Peephole) {
+        x86_64::peephole::optimize(&mut program);
+        if options.print_after.contains(&Pass::Peephole) {
+            print!("{}", program.listing(options.syntax()));
+        }
+    }
+    program
+}
+/// Exits if a compiler pass left `module` malformed.
+fn check(module: &mir::Module, result: Result<(), Vec<mir::verify::Error>>) {
+    if let Err(errors) = result {
+        for err in errors {
+            eprintln!("lox: internal error: {err}");
+        }
+        eprint!("{module}");
+        std::process::exit(70);
+    }
+}
+fn run_repl() -> io::Result<()> {
+    let mut interpreter = Interpreter::new(io::stdout());
     let mut input = String::new();
     loop {
         print!("> ");
-        std::io::stdout().flush()?;
-        if std::io::stdin().read_line(&mut input)? == 0 {
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut input)? == 0 {
             break Ok(());
         }
-        match run(&input) {
-            Ok(()) => {
-                input.clear();
-            }
-            Err(err) => {
-                eprintln!("{err}");
-                input.clear();
-            }
+        if let Err(err) = interpreter.run_line(&input) {
+            eprintln!("{err}");
         }
+        input.clear();
     }
 }
-fn main() -> std::io::Result<()> {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() > 1 {
-        println!("Usage: complox [file]");
-    } else if args.len() == 0 {
-        if let Some(program) = run_file(&args[1])? {
-            compile_program(program)?;
+fn main() -> io::Result<()> {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("lox: {err}");
+            eprintln!("{USAGE}");
+            std::process::exit(64);
+        }
+    };
+    if let Some(file) = &options.file {
+        let program = parse_file(file)?;
+        if options.tree_walk {
+            std::process::exit(tree_walk(&program)?);
+        }
+        let module = lower(&program, &options);
+        // Interpreted as lowered, so it checks the passes instead of
+        // sharing their mistakes.
+        if options.interp {
+            std::process::exit(interpret(&module)?);
+        }
+        let module = optimize(module, &options);
+        let program = generate(&module, &options);
+        let result = if options.run {
+            run_program(program, &options).map(|code| std::process::exit(code))
+        } else {
+            BuildDir::new(options.keep_temps).and_then(|build_dir| {
+                compile_program(program, &options.output_for(file), &options, &build_dir)
+            })
+        };
+        if let Err(err) = result {
+            eprintln!("lox: {err}");
+            std::process::exit(74);
         }
     } else {
         run_repl()?;
     }
-    //   let expr = BinaryExpr::new(
-    //       UnaryExpr::new("-",LiteralExpr::Number("123".to_string().into_boxed_str())),
-    //       "*",
-    //       Expression::Grouping(Box::new(Expression::Literal(LiteralExpr::Number("45.67".to_string().into_boxed_str())))),
-    //   );
-    //   println!("{expr}");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        match Options::parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(options) => options,
+            Err(err) => panic!("{args:?}: {err}"),
+        }
+    }
+    fn error(args: &[&str]) -> String {
+        match Options::parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(_) => panic!("{args:?} parsed"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&["a.lox"]);
+        assert_eq!(options.file.as_deref(), Some("a.lox"));
+        assert_eq!(options.passes, Passes::default());
+        assert_eq!(options.assembler, Assembler::Nasm);
+        assert_eq!(options.syntax, GasSyntax::Att);
+        assert_eq!(options.stop, Stop::Executable);
+        assert_eq!(options.output_for("dir/a.lox"), PathBuf::from("a"));
+    }
+
+    #[test]
+    fn optimization_levels() {
+        assert_eq!(parse(&["-O0", "a.lox"]).passes, Passes::none());
+        assert_eq!(parse(&["-O2", "a.lox"]).passes, Passes::default());
+        let passes = parse(&["-O1", "a.lox"]).passes;
+        assert!(passes.enabled(Pass::Fold) && !passes.enabled(Pass::Inline));
+        // The last level wins, but disabled passes stay off wherever they are.
+        assert_eq!(parse(&["-O0", "-O2", "a.lox"]).passes, Passes::default());
+        let passes = parse(&["--disable-pass=fold", "-O2", "a.lox"]).passes;
+        assert!(!passes.enabled(Pass::Fold) && passes.enabled(Pass::Dce));
+        assert_eq!(error(&["-O3"]), "unknown optimization level '-O3'");
+        assert_eq!(error(&["-Ofast"]), "unknown optimization level '-Ofast'");
+        assert_eq!(error(&["-O"]), "unknown optimization level '-O'");
+        assert_eq!(
+            error(&["-O0", "--print-after=fold"]),
+            "'--print-after=fold' names a pass that doesn't run"
+        );
+    }
+
+    #[test]
+    fn assemblers() {
+        assert_eq!(
+            parse(&["--assembler=gas", "a.lox"]).assembler,
+            Assembler::Gas
+        );
+        assert_eq!(
+            parse(&["--assembler=builtin", "a.lox"]).assembler,
+            Assembler::Builtin
+        );
+        assert_eq!(error(&["--assembler=yasm"]), "unknown assembler 'yasm'");
+        assert_eq!(error(&["--assembler="]), "unknown assembler ''");
+        assert_eq!(error(&["--assembler"]), "unknown option '--assembler'");
+        assert_eq!(
+            error(&["--assembler=gas", "--assembler=nasm"]),
+            "'--assembler' given more than once"
+        );
+    }
+
+    #[test]
+    fn syntaxes() {
+        let options = parse(&["--assembler=gas", "--syntax=intel", "-S", "a.lox"]);
+        assert_eq!(options.syntax, GasSyntax::Intel);
+        assert_eq!(options.output_for("a.lox"), PathBuf::from("a.s"));
+        assert_eq!(
+            parse(&["--syntax=att", "--assembler=gas", "a.lox"]).syntax,
+            GasSyntax::Att
+        );
+        assert_eq!(
+            error(&["--assembler=gas", "--syntax=masm"]),
+            "unknown syntax 'masm'"
+        );
+        assert_eq!(
+            error(&["--syntax=intel", "a.lox"]),
+            "'--syntax' needs '--assembler=gas'"
+        );
+        assert_eq!(
+            error(&["--assembler=nasm", "--syntax=intel", "a.lox"]),
+            "'--syntax' needs '--assembler=gas'"
+        );
+        assert_eq!(
+            error(&["--assembler=gas", "--syntax=att", "--syntax=intel"]),
+            "'--syntax' given more than once"
+        );
+    }
+
+    #[test]
+    fn outputs() {
+        let options = parse(&["-c", "-o", "out/x.o", "a.lox"]);
+        assert_eq!(options.stop, Stop::Object);
+        assert_eq!(options.output_for("a.lox"), PathBuf::from("out/x.o"));
+        assert_eq!(
+            parse(&["-S", "a.lox"]).output_for("a.lox"),
+            PathBuf::from("a.asm")
+        );
+        assert_eq!(error(&["-o"]), "missing file name after '-o'");
+        assert_eq!(error(&["-o", "x", "-o", "y"]), "'-o' given more than once");
+        assert_eq!(error(&["-S", "-S"]), "'-S' given more than once");
+        assert_eq!(
+            error(&["-S", "-c", "a.lox"]),
+            "'-S' and '-c' can't be used together"
+        );
+    }
+
+    #[test]
+    fn run_combinations() {
+        let options = parse(&["run", "--interp", "-O0", "a.lox"]);
+        assert!(options.run && options.interp);
+        assert!(parse(&["run", "--tree-walk", "a.lox"]).tree_walk);
+        assert_eq!(error(&["--interp", "a.lox"]), "'--interp' needs 'run'");
+        assert_eq!(
+            error(&["--tree-walk", "a.lox"]),
+            "'--tree-walk' needs 'run'"
+        );
+        assert_eq!(
+            error(&["run", "--interp", "--tree-walk", "a.lox"]),
+            "'--interp' and '--tree-walk' can't be used together"
+        );
+        assert_eq!(
+            error(&["run", "--interp", "--print-after=fold", "a.lox"]),
+            "'--interp' runs no passes to print after"
+        );
+        assert_eq!(error(&["run"]), "'run' needs a file");
+        assert_eq!(
+            error(&["run", "-c", "a.lox"]),
+            "'run' only makes executables, without '-o'"
+        );
+        assert_eq!(error(&["a.lox", "run"]), "unexpected argument 'run'");
+    }
+
+    #[test]
+    fn libc() {
+        let options = parse(&["--libc", "--no-main", "-c", "a.lox"]);
+        assert!(matches!(options.target(), Target::Libc { main: false }));
+        assert!(matches!(parse(&["a.lox"]).target(), Target::Freestanding));
+        assert_eq!(error(&["--no-main", "a.lox"]), "'--no-main' needs '--libc'");
+        assert_eq!(error(&["--frobnicate"]), "unknown option '--frobnicate'");
+    }
+}
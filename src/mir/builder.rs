@@ -0,0 +1,90 @@
+//! Builds a [`Function`] one instruction at a time.
+use super::{BinaryOp, BlockId, Constant, Function, Instruction, Terminator, Ty, UnaryOp, VReg};
+
+/// Appends to the current block, new blocks end in
+/// [`Terminator::Unreachable`] until they're terminated.
+pub(crate) struct Builder {
+    function: Function,
+    current: BlockId,
+}
+impl Builder {
+    pub(crate) fn new(name: &str) -> Self {
+        let mut builder = Self {
+            function: Function {
+                name: name.to_string(),
+                params: Vec::new(),
+                types: Vec::new(),
+                blocks: Vec::new(),
+            },
+            current: BlockId::ENTRY,
+        };
+        builder.block();
+        builder
+    }
+    pub(crate) fn param(&mut self, ty: Ty) -> VReg {
+        let vreg = self.vreg(ty);
+        self.function.params.push(vreg);
+        vreg
+    }
+    /// A fresh register.
+    pub(crate) fn vreg(&mut self, ty: Ty) -> VReg {
+        self.function.vreg(ty)
+    }
+    /// A new, empty block. It doesn't become the current one.
+    pub(crate) fn block(&mut self) -> BlockId {
+        self.function.add_block(Terminator::Unreachable)
+    }
+    pub(crate) fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+    pub(crate) fn push(&mut self, instruction: Instruction) {
+        self.function.blocks[self.current.index()]
+            .instructions
+            .push(instruction);
+    }
+    pub(crate) fn constant(&mut self, value: Constant) -> VReg {
+        let dst = self.vreg(value.ty());
+        self.push(Instruction::Const { dst, value });
+        dst
+    }
+    pub(crate) fn copy(&mut self, dst: VReg, src: VReg) {
+        self.push(Instruction::Copy { dst, src });
+    }
+    pub(crate) fn unary(&mut self, op: UnaryOp, src: VReg, line: usize) -> VReg {
+        let dst = self.vreg(op.result());
+        self.push(Instruction::Unary { dst, op, src, line });
+        dst
+    }
+    pub(crate) fn binary(&mut self, op: BinaryOp, lhs: VReg, rhs: VReg, line: usize) -> VReg {
+        let ty = op.result(self.function.ty(lhs), self.function.ty(rhs));
+        let dst = self.vreg(ty);
+        self.push(Instruction::Binary {
+            dst,
+            op,
+            lhs,
+            rhs,
+            line,
+        });
+        dst
+    }
+    pub(crate) fn print(&mut self, src: VReg) {
+        self.push(Instruction::Print { src });
+    }
+    pub(crate) fn call(&mut self, callee: &str, args: Vec<VReg>, line: usize) -> VReg {
+        let dst = self.vreg(Ty::Value);
+        self.push(Instruction::Call {
+            dst,
+            callee: callee.to_string(),
+            args,
+            line,
+        });
+        dst
+    }
+    /// Ends the current block.
+    pub(crate) fn terminate(&mut self, terminator: Terminator) {
+        self.function.blocks[self.current.index()].terminator = terminator;
+    }
+    pub(crate) fn finish(self) -> Function {
+        self.function
+    }
+}
@@ -0,0 +1,183 @@
+//! The control-flow graph of a [`Function`]: which blocks can follow each
+//! other, and an order to visit them in.
+use super::{BlockId, Function};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Cfg {
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    /// Reachable blocks only, the entry first.
+    reverse_postorder: Vec<BlockId>,
+    /// Each block's index in `reverse_postorder`, `None` if unreachable.
+    order: Vec<Option<usize>>,
+}
+impl Cfg {
+    /// Both ends of an edge have to be blocks of `function`.
+    pub(crate) fn new(function: &Function) -> Self {
+        let blocks = function.blocks.len();
+        let mut successors = Vec::with_capacity(blocks);
+        let mut predecessors = vec![Vec::new(); blocks];
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            let mut targets = block.terminator.successors();
+            // `branch %0, bb1, bb1` is a single edge.
+            targets.dedup();
+            for target in &targets {
+                predecessors[target.index()].push(id);
+            }
+            successors.push(targets);
+        }
+
+        let mut postorder = Vec::with_capacity(blocks);
+        let mut visited = vec![false; blocks];
+        if blocks > 0 {
+            // Each entry is a block and how many of its successors were
+            // visited so far.
+            let mut stack = vec![(BlockId::ENTRY, 0)];
+            visited[0] = true;
+            while let Some((id, next)) = stack.last_mut() {
+                let id = *id;
+                match successors[id.index()].get(*next) {
+                    Some(&target) => {
+                        *next += 1;
+                        if !visited[target.index()] {
+                            visited[target.index()] = true;
+                            stack.push((target, 0));
+                        }
+                    }
+                    None => {
+                        postorder.push(id);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        let reverse_postorder: Vec<_> = postorder.into_iter().rev().collect();
+        let mut order = vec![None; blocks];
+        for (i, id) in reverse_postorder.iter().enumerate() {
+            order[id.index()] = Some(i);
+        }
+        Self {
+            successors,
+            predecessors,
+            reverse_postorder,
+            order,
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.successors.len()
+    }
+    pub(crate) fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.successors[block.index()]
+    }
+    /// Includes unreachable predecessors, in block order.
+    pub(crate) fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.predecessors[block.index()]
+    }
+    pub(crate) fn is_reachable(&self, block: BlockId) -> bool {
+        self.order[block.index()].is_some()
+    }
+    /// Every block comes before its successors, except along back edges.
+    pub(crate) fn reverse_postorder(&self) -> &[BlockId] {
+        &self.reverse_postorder
+    }
+    /// Where `block` is in the reverse postorder.
+    pub(crate) fn order(&self, block: BlockId) -> Option<usize> {
+        self.order[block.index()]
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mir::{builder::Builder, Constant, Terminator};
+
+    pub(crate) const DIAMOND: &[&[u32]] = &[&[1, 2], &[3], &[3], &[]];
+    pub(crate) const LOOP: &[&[u32]] = &[&[1], &[2, 3], &[1], &[]];
+    /// bb1 heads the outer loop, bb2 the inner one.
+    pub(crate) const NESTED_LOOPS: &[&[u32]] = &[&[1], &[2, 5], &[3, 4], &[2], &[1], &[]];
+
+    /// A function where block `i` goes to the blocks in `successors[i]`,
+    /// branching on a register defined in the entry if there are two.
+    pub(crate) fn shaped(successors: &[&[u32]]) -> Function {
+        let mut builder = Builder::new("f");
+        let cond = builder.constant(Constant::Bool(true));
+        for _ in 1..successors.len() {
+            builder.block();
+        }
+        for (i, targets) in successors.iter().enumerate() {
+            builder.switch_to(BlockId(i as u32));
+            builder.terminate(match targets {
+                [] => Terminator::Return(None),
+                [target] => Terminator::Jump(BlockId(*target)),
+                [then, otherwise] => Terminator::Branch {
+                    cond,
+                    then: BlockId(*then),
+                    otherwise: BlockId(*otherwise),
+                },
+                _ => unreachable!("blocks have at most two successors"),
+            });
+        }
+        builder.finish()
+    }
+    fn ids(ids: &[u32]) -> Vec<BlockId> {
+        ids.iter().map(|id| BlockId(*id)).collect()
+    }
+    fn predecessors(cfg: &Cfg) -> Vec<Vec<BlockId>> {
+        (0..cfg.len() as u32)
+            .map(|id| cfg.predecessors(BlockId(id)).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn diamond() {
+        let cfg = Cfg::new(&shaped(DIAMOND));
+        assert_eq!(cfg.successors(BlockId(0)), ids(&[1, 2]));
+        assert_eq!(
+            predecessors(&cfg),
+            vec![ids(&[]), ids(&[0]), ids(&[0]), ids(&[1, 2])]
+        );
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 2, 1, 3]));
+    }
+
+    #[test]
+    fn loops() {
+        let cfg = Cfg::new(&shaped(LOOP));
+        assert_eq!(
+            predecessors(&cfg),
+            vec![ids(&[]), ids(&[0, 2]), ids(&[1]), ids(&[1])]
+        );
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 1, 3, 2]));
+
+        let cfg = Cfg::new(&shaped(NESTED_LOOPS));
+        assert_eq!(
+            predecessors(&cfg),
+            vec![
+                ids(&[]),
+                ids(&[0, 4]),
+                ids(&[1, 3]),
+                ids(&[2]),
+                ids(&[2]),
+                ids(&[1])
+            ]
+        );
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 1, 5, 2, 4, 3]));
+        assert_eq!(cfg.order(BlockId(4)), Some(4));
+    }
+
+    #[test]
+    fn unreachable_blocks() {
+        // bb2 can't be reached but still counts as bb1's predecessor.
+        let cfg = Cfg::new(&shaped(&[&[1], &[], &[1]]));
+        assert_eq!(cfg.predecessors(BlockId(1)), ids(&[0, 2]));
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 1]));
+        assert!(!cfg.is_reachable(BlockId(2)));
+        assert_eq!(cfg.order(BlockId(2)), None);
+    }
+
+    #[test]
+    fn branches_to_one_block_are_one_edge() {
+        let cfg = Cfg::new(&shaped(&[&[1, 1], &[]]));
+        assert_eq!(cfg.successors(BlockId(0)), ids(&[1]));
+        assert_eq!(cfg.predecessors(BlockId(1)), ids(&[0]));
+    }
+}
@@ -0,0 +1,238 @@
+//! Dead code elimination, on functions in SSA form.
+//!
+//! Branches on constants become jumps, a block only ever entered from the
+//! end of another is merged into it, blocks nothing jumps to any more are
+//! removed, and so are instructions whose results nobody uses, unless they
+//! could fail at runtime: a type error still has to be reported.
+use super::{
+    cfg::Cfg, BinaryOp, Block, BlockId, Constant, Function, Instruction, Module, Terminator, Ty,
+    UnaryOp, VReg, SCRIPT,
+};
+use std::collections::HashSet;
+
+/// Removes the functions the script never calls, directly or through other
+/// functions.
+pub(crate) fn remove_unused_functions(module: &mut Module) {
+    let mut used = HashSet::from([SCRIPT.to_string()]);
+    let mut worklist = vec![SCRIPT.to_string()];
+    while let Some(name) = worklist.pop() {
+        let Some(function) = module.function(&name) else {
+            continue;
+        };
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            if let Instruction::Call { callee, .. } = instruction {
+                if used.insert(callee.clone()) {
+                    worklist.push(callee.clone());
+                }
+            }
+        }
+    }
+    module
+        .functions
+        .retain(|function| used.contains(&function.name));
+}
+
+/// Removes the dead code in `function`, which has to be in SSA form.
+pub(crate) fn eliminate(function: &mut Function) {
+    fold_branches(function);
+    // Edges from unreachable blocks would keep blocks from being merged.
+    remove_unreachable(function);
+    merge_blocks(function);
+    remove_unreachable(function);
+    remove_unused(function);
+}
+
+/// Turns branches on constants, or to the same block twice, into jumps.
+fn fold_branches(function: &mut Function) {
+    let mut constants = vec![None; function.types.len()];
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Instruction::Const { dst, value } = instruction {
+            constants[dst.index()] = Some(!matches!(value, Constant::Nil | Constant::Bool(false)));
+        }
+    }
+    for id in function.block_ids() {
+        let Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        } = function.block(id).terminator
+        else {
+            continue;
+        };
+        let (taken, dropped) = match constants[cond.index()] {
+            _ if then == otherwise => (then, None),
+            Some(true) => (then, Some(otherwise)),
+            Some(false) => (otherwise, Some(then)),
+            None => continue,
+        };
+        function.blocks[id.index()].terminator = Terminator::Jump(taken);
+        if let Some(dropped) = dropped {
+            remove_phi_args(function, dropped, |block| block == id);
+        }
+    }
+}
+
+/// Appends every block whose only predecessor jumps to it to that
+/// predecessor, leaving it unreachable.
+fn merge_blocks(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    // Going in reverse postorder, a whole chain is merged into its first
+    // block.
+    for &id in cfg.reverse_postorder() {
+        while let Terminator::Jump(target) = function.block(id).terminator {
+            if target == id || cfg.predecessors(target).len() != 1 {
+                break;
+            }
+            let merged = std::mem::replace(
+                &mut function.blocks[target.index()],
+                Block {
+                    instructions: Vec::new(),
+                    terminator: Terminator::Unreachable,
+                },
+            );
+            for successor in merged.terminator.successors() {
+                for instruction in &mut function.blocks[successor.index()].instructions {
+                    let Instruction::Phi { args, .. } = instruction else {
+                        break;
+                    };
+                    for (pred, _) in args {
+                        if *pred == target {
+                            *pred = id;
+                        }
+                    }
+                }
+            }
+            let block = &mut function.blocks[id.index()];
+            // Phis with a single predecessor are copies.
+            block
+                .instructions
+                .extend(
+                    merged
+                        .instructions
+                        .into_iter()
+                        .map(|instruction| match instruction {
+                            Instruction::Phi { dst, args } => Instruction::Copy {
+                                dst,
+                                src: args[0].1,
+                            },
+                            instruction => instruction,
+                        }),
+                );
+            block.terminator = merged.terminator;
+        }
+    }
+}
+
+/// Removes the blocks control can't reach, renumbering the rest in order.
+fn remove_unreachable(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    let mut renumbered = vec![None; function.blocks.len()];
+    let mut next = 0;
+    for id in function.block_ids() {
+        if cfg.is_reachable(id) {
+            renumbered[id.index()] = Some(BlockId(next));
+            next += 1;
+        }
+    }
+    if next as usize == function.blocks.len() {
+        return;
+    }
+    for id in function.block_ids() {
+        remove_phi_args(function, id, |block| !cfg.is_reachable(block));
+    }
+    let blocks = std::mem::take(&mut function.blocks);
+    for (id, mut block) in (0..).map(BlockId).zip(blocks) {
+        if !cfg.is_reachable(id) {
+            continue;
+        }
+        for target in block.terminator.successors_mut() {
+            *target = renumbered[target.index()].unwrap();
+        }
+        for instruction in &mut block.instructions {
+            if let Instruction::Phi { args, .. } = instruction {
+                for (pred, _) in args {
+                    *pred = renumbered[pred.index()].unwrap();
+                }
+            }
+        }
+        function.blocks.push(block);
+    }
+}
+
+/// Removes `block`'s phi arguments for the predecessors `removed` is true
+/// for. A phi left with one argument becomes a copy of it.
+fn remove_phi_args(function: &mut Function, block: BlockId, removed: impl Fn(BlockId) -> bool) {
+    for instruction in &mut function.blocks[block.index()].instructions {
+        let Instruction::Phi { dst, args } = instruction else {
+            break;
+        };
+        args.retain(|(pred, _)| !removed(*pred));
+        if let [(_, src)] = args[..] {
+            *instruction = Instruction::Copy { dst: *dst, src };
+        }
+    }
+}
+
+/// Removes the instructions nothing depends on, marking from the ones with
+/// effects and the terminators back through their operands.
+fn remove_unused(function: &mut Function) {
+    let mut definitions = vec![None; function.types.len()];
+    let mut live = Vec::with_capacity(function.blocks.len());
+    let mut worklist = Vec::new();
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        live.push(vec![false; block.instructions.len()]);
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            if let Some(dst) = instruction.dst() {
+                definitions[dst.index()] = Some((id, i));
+            }
+            if has_effects(function, instruction) {
+                live[id.index()][i] = true;
+                worklist.extend(instruction.uses());
+            }
+        }
+        worklist.extend(block.terminator.uses());
+    }
+    while let Some(vreg) = worklist.pop() {
+        // Parameters have no definition.
+        let Some((id, i)) = definitions[vreg.index()] else {
+            continue;
+        };
+        if !live[id.index()][i] {
+            live[id.index()][i] = true;
+            worklist.extend(function.block(id).instructions[i].uses());
+        }
+    }
+    for (block, live) in function.blocks.iter_mut().zip(live) {
+        let mut live = live.into_iter();
+        block.instructions.retain(|_| live.next().unwrap());
+    }
+}
+
+/// Whether `instruction` does more than write its destination: prints, or
+/// could fail on the types of its operands.
+fn has_effects(function: &Function, instruction: &Instruction) -> bool {
+    let is = |vreg: VReg, ty: Ty| function.ty(vreg) == ty;
+    match instruction {
+        Instruction::Print { .. } | Instruction::Call { .. } => true,
+        Instruction::Unary {
+            op: UnaryOp::Negate,
+            src,
+            ..
+        } => !is(*src, Ty::Number),
+        Instruction::Binary { op, lhs, rhs, .. } => match op {
+            BinaryOp::Equal | BinaryOp::NotEqual => false,
+            BinaryOp::Add => {
+                !(is(*lhs, Ty::Number) && is(*rhs, Ty::Number)
+                    || is(*lhs, Ty::String) && is(*rhs, Ty::String))
+            }
+            _ => !(is(*lhs, Ty::Number) && is(*rhs, Ty::Number)),
+        },
+        Instruction::Const { .. }
+        | Instruction::Copy { .. }
+        | Instruction::Unary {
+            op: UnaryOp::Not | UnaryOp::ToString,
+            ..
+        }
+        | Instruction::Phi { .. } => false,
+    }
+}
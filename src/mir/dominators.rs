@@ -0,0 +1,152 @@
+//! The dominator tree and dominance frontiers of a [`Cfg`].
+//!
+//! Block `a` dominates `b` if every path from the entry to `b` goes through
+//! `a`. Immediate dominators are found with the iterative algorithm from
+//! Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm".
+use super::{cfg::Cfg, BlockId};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Dominators {
+    /// The entry is its own immediate dominator, unreachable blocks have
+    /// none.
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+}
+impl Dominators {
+    pub(crate) fn new(cfg: &Cfg) -> Self {
+        let mut idom = vec![None; cfg.len()];
+        let order = cfg.reverse_postorder();
+        if let Some(&entry) = order.first() {
+            idom[entry.index()] = Some(entry);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new = None;
+                for &predecessor in cfg.predecessors(block) {
+                    if idom[predecessor.index()].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => predecessor,
+                        Some(new) => intersect(cfg, &idom, predecessor, new),
+                    });
+                }
+                if new.is_some() && idom[block.index()] != new {
+                    idom[block.index()] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); cfg.len()];
+        for &block in order.iter().skip(1) {
+            let parent = idom[block.index()].expect("reachable blocks have a dominator");
+            children[parent.index()].push(block);
+        }
+        Self { idom, children }
+    }
+    /// The blocks `block` immediately dominates, in reverse postorder.
+    pub(crate) fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.index()]
+    }
+    /// For each block, where its dominance ends: the blocks it doesn't
+    /// strictly dominate that have a predecessor it does dominate.
+    pub(crate) fn frontiers(&self, cfg: &Cfg) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![Vec::new(); cfg.len()];
+        for &block in cfg.reverse_postorder() {
+            let predecessors = cfg.predecessors(block);
+            if predecessors.len() < 2 {
+                continue;
+            }
+            let idom = self.idom[block.index()].unwrap();
+            for &predecessor in predecessors {
+                let mut runner = predecessor;
+                // Unreachable predecessors don't take part.
+                if self.idom[runner.index()].is_none() {
+                    continue;
+                }
+                while runner != idom {
+                    let frontier: &mut Vec<BlockId> = &mut frontiers[runner.index()];
+                    if !frontier.contains(&block) {
+                        frontier.push(block);
+                    }
+                    runner = self.idom[runner.index()].unwrap();
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+/// The closest common dominator of `a` and `b`, walking up by reverse
+/// postorder number.
+fn intersect(cfg: &Cfg, idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId) -> BlockId {
+    let order = |block: BlockId| cfg.order(block).unwrap();
+    while a != b {
+        while order(a) > order(b) {
+            a = idom[a.index()].unwrap();
+        }
+        while order(b) > order(a) {
+            b = idom[b.index()].unwrap();
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::cfg::tests::{shaped, DIAMOND, LOOP, NESTED_LOOPS};
+
+    /// The children and frontier of every block.
+    fn tree(successors: &[&[u32]]) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+        let cfg = Cfg::new(&shaped(successors));
+        let dominators = Dominators::new(&cfg);
+        let numbers = |ids: &[BlockId]| ids.iter().map(|id| id.0).collect();
+        let children = (0..cfg.len() as u32)
+            .map(|id| numbers(dominators.children(BlockId(id))))
+            .collect();
+        let frontiers = dominators
+            .frontiers(&cfg)
+            .iter()
+            .map(|frontier| numbers(frontier))
+            .collect();
+        (children, frontiers)
+    }
+
+    #[test]
+    fn diamond() {
+        let (children, frontiers) = tree(DIAMOND);
+        assert_eq!(children, [vec![2, 1, 3], vec![], vec![], vec![]]);
+        assert_eq!(frontiers, [vec![], vec![3], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn loops() {
+        let (children, frontiers) = tree(LOOP);
+        assert_eq!(children, [vec![1], vec![3, 2], vec![], vec![]]);
+        assert_eq!(frontiers, [vec![], vec![1], vec![1], vec![]]);
+    }
+
+    #[test]
+    fn nested_loops() {
+        let (children, frontiers) = tree(NESTED_LOOPS);
+        assert_eq!(
+            children,
+            [vec![1], vec![5, 2], vec![4, 3], vec![], vec![], vec![]]
+        );
+        assert_eq!(
+            frontiers,
+            [vec![], vec![1], vec![1, 2], vec![2], vec![1], vec![]]
+        );
+    }
+
+    #[test]
+    fn unreachable_blocks_are_left_out() {
+        let (children, frontiers) = tree(&[&[1], &[], &[1]]);
+        assert_eq!(children, [vec![1], vec![], vec![]]);
+        assert_eq!(frontiers, [vec![], vec![], vec![]]);
+    }
+}
@@ -0,0 +1,307 @@
+//! Constant folding and algebraic simplification, on functions in SSA form.
+//!
+//! Operations on constants are evaluated the way [`interp`](super::interp)
+//! would at runtime. One that would fail, like `-"a"` or `1 + nil`, is left
+//! alone so the error still happens, on its line. Identities like `x * 1`
+//! only become copies when `x` is known to be a number, anything else could
+//! be a type error too. `x + 0` isn't one of them, it's `0` for `-0`.
+use super::{cfg::Cfg, interp, BinaryOp, Constant, Function, Instruction, Ty, VReg};
+use crate::{symbol::Symbol, value::Value};
+
+/// Folds what it can in `function`, which has to be in SSA form.
+pub(crate) fn fold(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    // The constant each register is known to hold. Definitions come before
+    // their uses in reverse postorder, except for phis on loops, so going
+    // around again only finds more when a loop's phi folded.
+    let mut constants = vec![None; function.types.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in cfg.reverse_postorder() {
+            for instruction in &mut function.blocks[id.index()].instructions {
+                let Some(dst) = instruction.dst() else {
+                    continue;
+                };
+                if constants[dst.index()].is_some() {
+                    continue;
+                }
+                if let Some(value) = constant(instruction, &constants) {
+                    *instruction = Instruction::Const {
+                        dst,
+                        value: value.clone(),
+                    };
+                    constants[dst.index()] = Some(value);
+                    changed = true;
+                } else if let Some(src) = identity(instruction, &function.types, &constants) {
+                    *instruction = Instruction::Copy { dst, src };
+                }
+            }
+        }
+    }
+}
+
+/// What `instruction` evaluates to, if it's the same every time.
+fn constant(instruction: &Instruction, constants: &[Option<Constant>]) -> Option<Constant> {
+    let value = |vreg: VReg| constants[vreg.index()].as_ref().map(interp::constant);
+    let result = match instruction {
+        Instruction::Const { value, .. } => return Some(value.clone()),
+        Instruction::Copy { src, .. } => return constants[src.index()].clone(),
+        Instruction::Unary { op, src, line, .. } => interp::unary(*op, &value(*src)?, *line),
+        Instruction::Binary {
+            op, lhs, rhs, line, ..
+        } => interp::binary(*op, &value(*lhs)?, &value(*rhs)?, *line),
+        Instruction::Phi { args, .. } => {
+            let (first, rest) = args.split_first()?;
+            let first = constants[first.1.index()].as_ref()?;
+            return rest
+                .iter()
+                .all(|(_, arg)| {
+                    constants[arg.index()]
+                        .as_ref()
+                        .is_some_and(|arg| same(arg, first))
+                })
+                .then(|| first.clone());
+        }
+        Instruction::Print { .. } | Instruction::Call { .. } => return None,
+    };
+    match result.ok()? {
+        Value::Nil => Some(Constant::Nil),
+        Value::Bool(b) => Some(Constant::Bool(b)),
+        Value::Number(n) => Some(Constant::Number(n)),
+        Value::String(s) => Some(Constant::String(Symbol::intern(&s))),
+        Value::Function(_) | Value::Native(_) | Value::Class(_) | Value::Instance(_) => {
+            unreachable!("operations on constants give constants")
+        }
+    }
+}
+
+/// Whether `a` and `b` are the same constant, unlike `==` telling `0` and
+/// `-0` apart.
+fn same(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+/// The operand `instruction` always gives back unchanged, if there is one.
+fn identity(
+    instruction: &Instruction,
+    types: &[Ty],
+    constants: &[Option<Constant>],
+) -> Option<VReg> {
+    let Instruction::Binary { op, lhs, rhs, .. } = instruction else {
+        return None;
+    };
+    let (lhs, rhs) = (*lhs, *rhs);
+    let is = |vreg: VReg, ty: Ty| types[vreg.index()] == ty;
+    let number = |vreg: VReg| match constants[vreg.index()] {
+        Some(Constant::Number(n)) => Some(n),
+        _ => None,
+    };
+    let empty = |vreg: VReg| {
+        matches!(
+            &constants[vreg.index()],
+            Some(Constant::String(Symbol::EMPTY))
+        )
+    };
+    let one = |vreg: VReg| number(vreg) == Some(1.0);
+    // Only `-0` leaves every number as it is when added.
+    let negative_zero = |vreg: VReg| number(vreg).is_some_and(|n| n == 0.0 && n.is_sign_negative());
+    let positive_zero = |vreg: VReg| number(vreg).is_some_and(|n| n == 0.0 && n.is_sign_positive());
+    match op {
+        BinaryOp::Multiply if is(lhs, Ty::Number) && one(rhs) => Some(lhs),
+        BinaryOp::Multiply if is(rhs, Ty::Number) && one(lhs) => Some(rhs),
+        BinaryOp::Divide if is(lhs, Ty::Number) && one(rhs) => Some(lhs),
+        BinaryOp::Subtract if is(lhs, Ty::Number) && positive_zero(rhs) => Some(lhs),
+        BinaryOp::Add if is(lhs, Ty::Number) && negative_zero(rhs) => Some(lhs),
+        BinaryOp::Add if is(rhs, Ty::Number) && negative_zero(lhs) => Some(rhs),
+        BinaryOp::Add if is(lhs, Ty::String) && empty(rhs) => Some(lhs),
+        BinaryOp::Add if is(rhs, Ty::String) && empty(lhs) => Some(rhs),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{builder::Builder, dce, BlockId, Terminator, UnaryOp};
+
+    /// The entry block's instructions once `builder`'s function is folded.
+    fn folded(mut builder: Builder) -> Vec<Instruction> {
+        builder.terminate(Terminator::Return(None));
+        let mut function = builder.finish();
+        fold(&mut function);
+        function.blocks.swap_remove(0).instructions
+    }
+    fn number(builder: &mut Builder, n: f64) -> VReg {
+        builder.constant(Constant::Number(n))
+    }
+    fn string(builder: &mut Builder, s: &str) -> VReg {
+        builder.constant(Constant::String(Symbol::intern(s)))
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let mut f = Builder::new("f");
+        let (one, two, three) = (
+            number(&mut f, 1.0),
+            number(&mut f, 2.0),
+            number(&mut f, 3.0),
+        );
+        let product = f.binary(BinaryOp::Multiply, two, three, 1);
+        let sum = f.binary(BinaryOp::Add, one, product, 1);
+        let yes = f.constant(Constant::Bool(true));
+        let no = f.unary(UnaryOp::Not, yes, 1);
+        let (a, b) = (string(&mut f, "a"), string(&mut f, "b"));
+        let ab = f.binary(BinaryOp::Add, a, b, 1);
+        let instructions = folded(f);
+        assert_eq!(
+            instructions[sum.index()],
+            Instruction::Const {
+                dst: sum,
+                value: Constant::Number(7.0)
+            }
+        );
+        assert_eq!(
+            instructions[no.index()],
+            Instruction::Const {
+                dst: no,
+                value: Constant::Bool(false)
+            }
+        );
+        assert_eq!(
+            instructions[ab.index()],
+            Instruction::Const {
+                dst: ab,
+                value: Constant::String(Symbol::intern("ab"))
+            }
+        );
+    }
+
+    #[test]
+    fn negative_zero_is_kept() {
+        let mut f = Builder::new("f");
+        let x = f.param(Ty::Number);
+        let zero = number(&mut f, 0.0);
+        let negated = f.unary(UnaryOp::Negate, zero, 1);
+        // `x + 0` is `0` for `x = -0`, `x + -0` is always `x`.
+        let plus_zero = f.binary(BinaryOp::Add, x, zero, 1);
+        let plus_negative_zero = f.binary(BinaryOp::Add, x, negated, 1);
+        let instructions = folded(f);
+        let Instruction::Const {
+            value: Constant::Number(n),
+            ..
+        } = instructions[1]
+        else {
+            panic!("-0 should fold");
+        };
+        assert_eq!(n.to_bits(), (-0.0f64).to_bits());
+        assert!(matches!(instructions[2], Instruction::Binary { dst, .. } if dst == plus_zero));
+        assert_eq!(
+            instructions[3],
+            Instruction::Copy {
+                dst: plus_negative_zero,
+                src: x
+            }
+        );
+    }
+
+    #[test]
+    fn phis_of_zero_and_negative_zero_stay() {
+        let mut f = Builder::new("f");
+        let cond = f.param(Ty::Bool);
+        let (then, otherwise, join) = (f.block(), f.block(), f.block());
+        let zero = number(&mut f, 0.0);
+        let negative_zero = number(&mut f, -0.0);
+        f.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        for block in [then, otherwise] {
+            f.switch_to(block);
+            f.terminate(Terminator::Jump(join));
+        }
+        f.switch_to(join);
+        let dst = f.vreg(Ty::Number);
+        let phi = Instruction::Phi {
+            dst,
+            args: vec![(then, zero), (otherwise, negative_zero)],
+        };
+        f.push(phi.clone());
+        f.terminate(Terminator::Return(Some(dst)));
+        let mut function = f.finish();
+        fold(&mut function);
+        assert_eq!(function.block(join).instructions, [phi]);
+    }
+
+    #[test]
+    fn type_errors_are_not_folded() {
+        let mut f = Builder::new("f");
+        let x = f.param(Ty::Value);
+        let one = number(&mut f, 1.0);
+        let a = string(&mut f, "a");
+        let sum = f.binary(BinaryOp::Add, one, a, 1);
+        let negated = f.unary(UnaryOp::Negate, a, 2);
+        // `x` might not be a number.
+        let product = f.binary(BinaryOp::Multiply, x, one, 3);
+        let instructions = folded(f);
+        assert!(matches!(instructions[2], Instruction::Binary { dst, .. } if dst == sum));
+        assert!(matches!(instructions[3], Instruction::Unary { dst, .. } if dst == negated));
+        assert!(matches!(instructions[4], Instruction::Binary { dst, .. } if dst == product));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let mut f = Builder::new("f");
+        let (one, zero) = (number(&mut f, 1.0), number(&mut f, 0.0));
+        f.binary(BinaryOp::Divide, one, zero, 1);
+        f.binary(BinaryOp::Divide, zero, zero, 1);
+        f.binary(BinaryOp::Modulo, one, zero, 1);
+        let numbers: Vec<_> = folded(f)[2..]
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::Const {
+                    value: Constant::Number(n),
+                    ..
+                } => *n,
+                _ => panic!("`{instruction}` should fold"),
+            })
+            .collect();
+        assert_eq!(numbers[0], f64::INFINITY);
+        assert!(numbers[1].is_nan());
+        assert!(numbers[2].is_nan());
+    }
+
+    #[test]
+    fn constant_conditions_turn_branches_into_jumps() {
+        let mut f = Builder::new("f");
+        let (one, two) = (number(&mut f, 1.0), number(&mut f, 2.0));
+        let cond = f.binary(BinaryOp::Less, one, two, 1);
+        let (then, otherwise) = (f.block(), f.block());
+        f.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        f.switch_to(then);
+        f.print(one);
+        f.terminate(Terminator::Return(None));
+        f.switch_to(otherwise);
+        f.print(two);
+        f.terminate(Terminator::Return(None));
+        let mut function = f.finish();
+        fold(&mut function);
+        dce::eliminate(&mut function);
+        // `then` is merged into the entry, `otherwise` is gone.
+        assert_eq!(function.blocks.len(), 1);
+        let entry = function.block(BlockId::ENTRY);
+        assert_eq!(
+            entry.instructions.last(),
+            Some(&Instruction::Print { src: one })
+        );
+        assert_eq!(entry.terminator, Terminator::Return(None));
+    }
+}
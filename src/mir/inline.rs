@@ -0,0 +1,310 @@
+//! Inlining, on modules in SSA form.
+//!
+//! A call to a small function that can't end up calling itself is replaced
+//! with a copy of the function's blocks. Callees are inlined into before
+//! their callers, so a function's size includes what was inlined into it.
+//! Methods aren't compiled yet, so only functions are inlined.
+//!
+//! Inlined calls don't count towards the depth that's reported as a stack
+//! overflow, only calls that are left do.
+use super::{Block, BlockId, Constant, Function, Instruction, Module, Terminator, VReg};
+
+/// Functions with at most this many instructions, terminators included,
+/// are inlined.
+const MAX_SIZE: usize = 40;
+
+pub(crate) fn inline(module: &mut Module) {
+    let calls = call_graph(module);
+    let recursive: Vec<bool> = (0..module.functions.len())
+        .map(|i| reaches(&calls, i, i))
+        .collect();
+    for caller in postorder(&calls) {
+        loop {
+            let function = &module.functions[caller];
+            // The first call worth inlining.
+            let site =
+                function.block_ids().find_map(|id| {
+                    function.block(id).instructions.iter().enumerate().find_map(
+                        |(i, instruction)| {
+                            let Instruction::Call { callee, .. } = instruction else {
+                                return None;
+                            };
+                            let callee = index(module, callee);
+                            let small = size(&module.functions[callee]) <= MAX_SIZE;
+                            (small && !recursive[callee]).then_some((id, i, callee))
+                        },
+                    )
+                });
+            let Some((block, i, callee)) = site else {
+                break;
+            };
+            let callee = module.functions[callee].clone();
+            inline_call(&mut module.functions[caller], block, i, &callee);
+        }
+    }
+}
+
+fn index(module: &Module, name: &str) -> usize {
+    module
+        .functions
+        .iter()
+        .position(|function| function.name == name)
+        .unwrap_or_else(|| panic!("{name} is never defined"))
+}
+
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| block.instructions.len() + 1)
+        .sum()
+}
+
+/// The functions each function calls, by index.
+fn call_graph(module: &Module) -> Vec<Vec<usize>> {
+    module
+        .functions
+        .iter()
+        .map(|function| {
+            let mut callees: Vec<_> = function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.instructions)
+                .filter_map(|instruction| match instruction {
+                    Instruction::Call { callee, .. } => Some(index(module, callee)),
+                    _ => None,
+                })
+                .collect();
+            callees.sort();
+            callees.dedup();
+            callees
+        })
+        .collect()
+}
+
+/// Whether a call from `from` can, through any number of calls, end up in
+/// `to`.
+fn reaches(calls: &[Vec<usize>], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; calls.len()];
+    let mut worklist = calls[from].clone();
+    while let Some(function) = worklist.pop() {
+        if function == to {
+            return true;
+        }
+        if !std::mem::replace(&mut seen[function], true) {
+            worklist.extend(&calls[function]);
+        }
+    }
+    false
+}
+
+/// Every function after the ones it calls, unless they call it back.
+fn postorder(calls: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut seen = vec![false; calls.len()];
+    for root in 0..calls.len() {
+        if seen[root] {
+            continue;
+        }
+        seen[root] = true;
+        // Functions with how many of their callees were visited.
+        let mut stack = vec![(root, 0)];
+        while let Some((function, next)) = stack.last_mut() {
+            match calls[*function].get(*next) {
+                Some(&callee) => {
+                    *next += 1;
+                    if !std::mem::replace(&mut seen[callee], true) {
+                        stack.push((callee, 0));
+                    }
+                }
+                None => {
+                    order.push(*function);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Replaces the call at `block`'s `i`th instruction with `callee`'s blocks.
+fn inline_call(function: &mut Function, block: BlockId, i: usize, callee: &Function) {
+    let Instruction::Call { dst, args, .. } =
+        function.blocks[block.index()].instructions[i].clone()
+    else {
+        unreachable!("only calls are inlined")
+    };
+    // The callee's registers, parameters being the arguments.
+    let mut registers: Vec<Option<VReg>> = vec![None; callee.types.len()];
+    for (param, arg) in callee.params.iter().zip(&args) {
+        registers[param.index()] = Some(*arg);
+    }
+    for (vreg, ty) in callee.types.iter().enumerate() {
+        if registers[vreg].is_none() {
+            registers[vreg] = Some(function.vreg(*ty));
+        }
+    }
+    let register = |vreg: &mut VReg| *vreg = registers[vreg.index()].unwrap();
+    let first = function.blocks.len() as u32;
+    let renumber = |id: &mut BlockId| *id = BlockId(first + id.0);
+    let done = BlockId(first + callee.blocks.len() as u32);
+
+    // The rest of the caller's block goes after the callee's blocks.
+    let caller = &mut function.blocks[block.index()];
+    let rest = caller.instructions.split_off(i + 1);
+    caller.instructions.pop();
+    let terminator = std::mem::replace(&mut caller.terminator, Terminator::Jump(BlockId(first)));
+    for successor in terminator.successors() {
+        retarget_phis(function, successor, block, done);
+    }
+
+    let mut returns = Vec::new();
+    for (id, mut copy) in callee.block_ids().zip(callee.blocks.iter().cloned()) {
+        for instruction in &mut copy.instructions {
+            instruction.uses_mut().into_iter().for_each(register);
+            if let Some(dst) = instruction.dst_mut() {
+                register(dst);
+            }
+            if let Instruction::Phi { args, .. } = instruction {
+                args.iter_mut().for_each(|(pred, _)| renumber(pred));
+            }
+        }
+        if let Some(vreg) = copy.terminator.uses_mut() {
+            register(vreg);
+        }
+        copy.terminator
+            .successors_mut()
+            .into_iter()
+            .for_each(renumber);
+        if let Terminator::Return(value) = copy.terminator {
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    let nil = function.vreg(Constant::Nil.ty());
+                    copy.instructions.push(Instruction::Const {
+                        dst: nil,
+                        value: Constant::Nil,
+                    });
+                    nil
+                }
+            };
+            returns.push((BlockId(first + id.0), value));
+            copy.terminator = Terminator::Jump(done);
+        }
+        function.blocks.push(copy);
+    }
+
+    let result = match returns[..] {
+        [] => Instruction::Const {
+            dst,
+            value: Constant::Nil,
+        },
+        [(_, src)] => Instruction::Copy { dst, src },
+        _ => Instruction::Phi { dst, args: returns },
+    };
+    let mut instructions = vec![result];
+    instructions.extend(rest);
+    function.blocks.push(Block {
+        instructions,
+        terminator,
+    });
+}
+
+/// Makes `block`'s phis take the arguments they had for `from` for `to`.
+fn retarget_phis(function: &mut Function, block: BlockId, from: BlockId, to: BlockId) {
+    for instruction in &mut function.blocks[block.index()].instructions {
+        let Instruction::Phi { args, .. } = instruction else {
+            break;
+        };
+        for (pred, _) in args {
+            if *pred == from {
+                *pred = to;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::Program,
+        mir::{interp, lower::lower, ssa::to_ssa, verify::verify_ssa, SCRIPT},
+    };
+
+    fn ssa(source: &str) -> Module {
+        let program: Program = source.parse().unwrap();
+        let mut module = lower(&program).unwrap();
+        module.functions.iter_mut().for_each(to_ssa);
+        module
+    }
+    fn output(module: &Module) -> String {
+        let mut out = Vec::new();
+        interp::run(module, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    /// Inlines `source`'s functions, checking the result is well formed and
+    /// prints the same.
+    fn inlined(source: &str) -> Module {
+        let mut module = ssa(source);
+        let before = output(&module);
+        inline(&mut module);
+        assert_eq!(verify_ssa(&module), Ok(()));
+        assert_eq!(output(&module), before);
+        module
+    }
+    /// The functions `name` calls, in order.
+    fn callees(module: &Module, name: &str) -> Vec<String> {
+        module
+            .function(name)
+            .unwrap()
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| match instruction {
+                Instruction::Call { callee, .. } => Some(callee.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn leaf_calls_are_inlined() {
+        let module = inlined("fun add(a, b) { return a + b; } print add(1, 2);");
+        assert_eq!(callees(&module, SCRIPT), Vec::<String>::new());
+        assert_eq!(output(&module), "3\n");
+    }
+
+    #[test]
+    fn callees_are_inlined_into_first() {
+        let module = inlined(
+            "fun one() { return 1; }
+             fun two() { return one() + one(); }
+             print two();",
+        );
+        assert_eq!(callees(&module, "two"), Vec::<String>::new());
+        assert_eq!(callees(&module, SCRIPT), Vec::<String>::new());
+    }
+
+    #[test]
+    fn recursive_calls_stay() {
+        let module = inlined(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+             print fib(10);",
+        );
+        assert_eq!(callees(&module, SCRIPT), ["fib"]);
+        assert_eq!(callees(&module, "fib"), ["fib", "fib"]);
+    }
+
+    #[test]
+    fn large_functions_stay() {
+        let body = "n = n * 2 + 1;".repeat(10);
+        let module = inlined(&format!(
+            "fun large(n) {{ {body} return n; }}
+             fun small(n) {{ return n * 2 + 1; }}
+             print large(1) + small(1);"
+        ));
+        assert!(size(module.function("large").unwrap()) > MAX_SIZE);
+        assert_eq!(callees(&module, SCRIPT), ["large"]);
+    }
+}
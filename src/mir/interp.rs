@@ -0,0 +1,204 @@
+//! Runs a [`Module`] without compiling it, as a reference for what the
+//! generated code should do: the same output, the same runtime errors.
+use super::{
+    BinaryOp, BlockId, Constant, Function, Instruction, Module, Terminator, UnaryOp, VReg, SCRIPT,
+};
+use crate::value::{RuntimeError, Value, MAX_DEPTH};
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Runtime(RuntimeError),
+    Io(io::Error),
+}
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Self::Runtime(err)
+    }
+}
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Runtime(err) => err.fmt(f),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Runs the module's script, printing to `out`.
+pub(crate) fn run(module: &Module, out: &mut dyn io::Write) -> Result<(), Error> {
+    let script = module.function(SCRIPT).expect("modules have a script");
+    Frame::new(module, script, 0).run(out)?;
+    Ok(())
+}
+
+/// One call of a function, with a value for each of its registers.
+struct Frame<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    registers: Vec<Option<Value>>,
+    /// How many calls haven't returned yet, this one included.
+    depth: usize,
+}
+impl<'a> Frame<'a> {
+    fn new(module: &'a Module, function: &'a Function, depth: usize) -> Self {
+        Self {
+            module,
+            function,
+            registers: vec![None; function.types.len()],
+            depth,
+        }
+    }
+    fn get(&self, vreg: VReg) -> &Value {
+        self.registers[vreg.index()]
+            .as_ref()
+            .unwrap_or_else(|| panic!("{vreg} is used before it's defined"))
+    }
+    /// Runs to a return, giving the value returned.
+    fn run(mut self, out: &mut dyn io::Write) -> Result<Value, Error> {
+        let mut previous = None;
+        let mut current = BlockId::ENTRY;
+        loop {
+            let block = self.function.block(current);
+            // Phis read their arguments at the same time, before any of
+            // them is written.
+            let phis: Vec<_> = block
+                .instructions
+                .iter()
+                .map_while(|instruction| match instruction {
+                    Instruction::Phi { dst, args } => {
+                        let (_, arg) = args
+                            .iter()
+                            .find(|(block, _)| Some(*block) == previous)
+                            .expect("phis have an argument for each predecessor");
+                        Some((*dst, self.get(*arg).clone()))
+                    }
+                    _ => None,
+                })
+                .collect();
+            for (dst, value) in phis {
+                self.registers[dst.index()] = Some(value);
+            }
+            for instruction in &block.instructions {
+                self.instruction(instruction, out)?;
+            }
+            previous = Some(current);
+            current = match &block.terminator {
+                Terminator::Jump(target) => *target,
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    if self.get(*cond).is_truthy() {
+                        *then
+                    } else {
+                        *otherwise
+                    }
+                }
+                Terminator::Return(value) => {
+                    return Ok(value.map_or(Value::Nil, |value| self.get(value).clone()))
+                }
+                Terminator::Unreachable => {
+                    panic!(
+                        "control reached the end of {} {current}",
+                        self.function.name
+                    )
+                }
+            };
+        }
+    }
+    fn instruction(
+        &mut self,
+        instruction: &Instruction,
+        out: &mut dyn io::Write,
+    ) -> Result<(), Error> {
+        let (dst, value) = match instruction {
+            Instruction::Const { dst, value } => (*dst, constant(value)),
+            Instruction::Copy { dst, src } => (*dst, self.get(*src).clone()),
+            Instruction::Unary { dst, op, src, line } => (*dst, unary(*op, self.get(*src), *line)?),
+            Instruction::Binary {
+                dst,
+                op,
+                lhs,
+                rhs,
+                line,
+            } => (*dst, binary(*op, self.get(*lhs), self.get(*rhs), *line)?),
+            Instruction::Print { src } => {
+                writeln!(out, "{}", self.get(*src))?;
+                return Ok(());
+            }
+            Instruction::Call {
+                dst,
+                callee,
+                args,
+                line,
+            } => {
+                if self.depth == MAX_DEPTH {
+                    return Err(RuntimeError::new("Stack overflow.", *line).into());
+                }
+                let callee = self
+                    .module
+                    .function(callee)
+                    .unwrap_or_else(|| panic!("{callee} is never defined"));
+                let mut frame = Frame::new(self.module, callee, self.depth + 1);
+                for (param, arg) in callee.params.iter().zip(args) {
+                    frame.registers[param.index()] = Some(self.get(*arg).clone());
+                }
+                (*dst, frame.run(out)?)
+            }
+            Instruction::Phi { .. } => return Ok(()),
+        };
+        self.registers[dst.index()] = Some(value);
+        Ok(())
+    }
+}
+
+pub(crate) fn constant(constant: &Constant) -> Value {
+    match constant {
+        Constant::Nil => Value::Nil,
+        Constant::Bool(b) => Value::Bool(*b),
+        Constant::Number(n) => Value::Number(*n),
+        Constant::String(s) => Value::String(s.as_str().into()),
+    }
+}
+
+pub(crate) fn unary(op: UnaryOp, src: &Value, line: usize) -> Result<Value, RuntimeError> {
+    Ok(match op {
+        UnaryOp::Negate => src.negate(line)?,
+        UnaryOp::Not => Value::Bool(!src.is_truthy()),
+        UnaryOp::ToString => Value::String(src.to_string().into()),
+    })
+}
+
+pub(crate) fn binary(
+    op: BinaryOp,
+    lhs: &Value,
+    rhs: &Value,
+    line: usize,
+) -> Result<Value, RuntimeError> {
+    Ok(match op {
+        BinaryOp::Add => lhs.add(rhs, line)?,
+        BinaryOp::Equal => Value::Bool(lhs == rhs),
+        BinaryOp::NotEqual => Value::Bool(lhs != rhs),
+        _ => {
+            let (a, b) = lhs.numbers(rhs, line)?;
+            match op {
+                BinaryOp::Subtract => Value::Number(a - b),
+                BinaryOp::Multiply => Value::Number(a * b),
+                BinaryOp::Divide => Value::Number(a / b),
+                BinaryOp::Modulo => Value::Number(a % b),
+                BinaryOp::Less => Value::Bool(a < b),
+                BinaryOp::LessEqual => Value::Bool(a <= b),
+                BinaryOp::Greater => Value::Bool(a > b),
+                BinaryOp::GreaterEqual => Value::Bool(a >= b),
+                BinaryOp::Add | BinaryOp::Equal | BinaryOp::NotEqual => unreachable!(),
+            }
+        }
+    })
+}
@@ -0,0 +1,121 @@
+//! Which registers are live, still to be read, at the edges of each block.
+//!
+//! A phi reads its arguments at the end of the predecessor they come from,
+//! not at the start of its own block, and defines its register before the
+//! block starts.
+use super::{cfg::Cfg, BlockId, Function, Instruction, VReg};
+
+/// A set of registers of one function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RegSet {
+    words: Vec<u64>,
+}
+impl RegSet {
+    /// An empty set that can hold the first `registers` registers.
+    pub(crate) fn new(registers: usize) -> Self {
+        Self {
+            words: vec![0; registers.div_ceil(64)],
+        }
+    }
+    pub(crate) fn contains(&self, vreg: VReg) -> bool {
+        self.words[vreg.index() / 64] & 1 << (vreg.index() % 64) != 0
+    }
+    /// Whether `vreg` wasn't already in the set.
+    pub(crate) fn insert(&mut self, vreg: VReg) -> bool {
+        let word = &mut self.words[vreg.index() / 64];
+        let bit = 1 << (vreg.index() % 64);
+        let new = *word & bit == 0;
+        *word |= bit;
+        new
+    }
+    pub(crate) fn remove(&mut self, vreg: VReg) {
+        self.words[vreg.index() / 64] &= !(1 << (vreg.index() % 64));
+    }
+    /// Adds everything in `other`, returns whether anything was new.
+    pub(crate) fn union(&mut self, other: &RegSet) -> bool {
+        let mut changed = false;
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            changed |= *other & !*word != 0;
+            *word |= other;
+        }
+        changed
+    }
+    pub(crate) fn iter(&self) -> impl Iterator<Item = VReg> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            (0..64)
+                .filter(move |bit| word & 1 << bit != 0)
+                .map(move |bit| VReg((i * 64 + bit) as u32))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Liveness {
+    live_in: Vec<RegSet>,
+    live_out: Vec<RegSet>,
+}
+impl Liveness {
+    pub(crate) fn new(function: &Function, cfg: &Cfg) -> Self {
+        let registers = function.types.len();
+        let blocks = function.blocks.len();
+
+        // What each block reads before writing, and writes.
+        let mut uses = vec![RegSet::new(registers); blocks];
+        let mut defs = vec![RegSet::new(registers); blocks];
+        // What each block's successors' phis read from it.
+        let mut phi_uses = vec![RegSet::new(registers); blocks];
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            let (uses, defs) = (&mut uses[id.index()], &mut defs[id.index()]);
+            for instruction in &block.instructions {
+                if let Instruction::Phi { args, .. } = instruction {
+                    for (predecessor, arg) in args {
+                        phi_uses[predecessor.index()].insert(*arg);
+                    }
+                } else {
+                    for vreg in instruction.uses() {
+                        if !defs.contains(vreg) {
+                            uses.insert(vreg);
+                        }
+                    }
+                }
+                if let Some(dst) = instruction.dst() {
+                    defs.insert(dst);
+                }
+            }
+            if let Some(vreg) = block.terminator.uses() {
+                if !defs.contains(vreg) {
+                    uses.insert(vreg);
+                }
+            }
+        }
+
+        let mut live_in = uses.clone();
+        let mut live_out = phi_uses;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // Backwards, so most blocks see their successors' final sets.
+            for &block in cfg.reverse_postorder().iter().rev() {
+                let i = block.index();
+                for successor in cfg.successors(block) {
+                    let successor = live_in[successor.index()].clone();
+                    live_out[i].union(&successor);
+                }
+                let mut through = live_out[i].clone();
+                for vreg in defs[i].iter() {
+                    through.remove(vreg);
+                }
+                changed |= live_in[i].union(&through);
+            }
+        }
+        Self { live_in, live_out }
+    }
+    /// The registers read after the start of `block` before being written.
+    /// Its phis' registers are written first so they aren't included.
+    pub(crate) fn live_in(&self, block: BlockId) -> &RegSet {
+        &self.live_in[block.index()]
+    }
+    pub(crate) fn live_out(&self, block: BlockId) -> &RegSet {
+        &self.live_out[block.index()]
+    }
+}
@@ -0,0 +1,377 @@
+//! Lowers the AST into a [`Module`].
+//!
+//! Every variable gets a register of its own, [`Ty::Value`] since
+//! assignments can change what it holds, and scopes only exist here, as a
+//! map from names to registers.
+//!
+//! Functions declared at the top level become functions of their own, as
+//! long as they're only ever called by name: the name is never assigned or
+//! declared again, so every call knows its callee. Their bodies can't use
+//! global variables yet, those live in the script's registers.
+use super::{
+    builder::Builder, BinaryOp, Constant, Function, Module, Terminator, Ty, UnaryOp, VReg, SCRIPT,
+};
+use crate::{
+    ast::{
+        Ast, Expression, FunctionDecl, IfStmt, LiteralExpr, NodeId, Program, ReturnStmt, Statement,
+        VarStmt, VariableExpr, WhileStmt,
+    },
+    symbol::Symbol,
+};
+use std::{collections::HashMap, fmt};
+
+/// Something the compiler can't handle yet, or a variable that's never
+/// declared.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Error {
+    message: String,
+    line: usize,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+pub(crate) fn lower(program: &Program) -> Result<Module, Error> {
+    let mut lowerer = Lowerer {
+        ast: &program.ast,
+        builder: Builder::new(SCRIPT),
+        scopes: vec![Vec::new()],
+        globals: None,
+        functions: HashMap::new(),
+        declared: program
+            .statements
+            .iter()
+            .filter_map(|statement| match program.ast.statement(*statement) {
+                Statement::Var(VarStmt { name, .. }) => Some(*name),
+                Statement::Function(function) => Some(function.name),
+                Statement::Class(class) => Some(class.name),
+                _ => None,
+            })
+            .collect(),
+        lowered: Vec::new(),
+    };
+    for statement in &program.statements {
+        lowerer.statement(*statement)?;
+    }
+    lowerer.builder.terminate(Terminator::Return(None));
+    let mut functions = vec![lowerer.builder.finish()];
+    functions.extend(lowerer.lowered);
+    Ok(Module { functions })
+}
+
+struct Lowerer<'a> {
+    ast: &'a Ast,
+    builder: Builder,
+    /// The variables in scope, innermost last. Globals are the first scope,
+    /// in a function its parameters are.
+    scopes: Vec<Vec<(Symbol, VReg)>>,
+    /// The script's globals, while lowering a function.
+    globals: Option<Vec<(Symbol, VReg)>>,
+    /// The functions declared so far, with how many parameters they take.
+    functions: HashMap<Symbol, usize>,
+    /// Every name declared at the top level, as often as it's declared.
+    declared: Vec<Symbol>,
+    lowered: Vec<Function>,
+}
+impl Lowerer<'_> {
+    fn unsupported<T>(what: &str, line: usize) -> Result<T, Error> {
+        Err(Error {
+            message: format!(
+                "{what} can't be compiled yet, run the script with 'lox run --tree-walk'."
+            ),
+            line,
+        })
+    }
+    fn lookup(&self, name: Symbol, line: usize) -> Result<VReg, Error> {
+        if let Some(vreg) = self.variable(name) {
+            return Ok(vreg);
+        }
+        if self.functions.contains_key(&name) {
+            return Self::unsupported("Functions used as values", line);
+        }
+        if self
+            .globals
+            .iter()
+            .flatten()
+            .any(|(variable, _)| *variable == name)
+        {
+            return Self::unsupported("Global variables used in functions", line);
+        }
+        Err(Error {
+            message: format!("Undefined variable '{name}'."),
+            line,
+        })
+    }
+    fn variable(&self, name: Symbol) -> Option<VReg> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(variable, _)| *variable == name)
+            .map(|(_, vreg)| *vreg)
+    }
+    fn at_top_level(&self) -> bool {
+        self.globals.is_none() && self.scopes.len() == 1
+    }
+    fn function(&mut self, function: &FunctionDecl, line: usize) -> Result<(), Error> {
+        if !self.at_top_level() {
+            return Self::unsupported("Local functions", line);
+        }
+        // Calls couldn't know which declaration they get.
+        if self
+            .declared
+            .iter()
+            .filter(|name| **name == function.name)
+            .count()
+            > 1
+        {
+            return Self::unsupported("Functions declared twice", line);
+        }
+        // Declared first, so the function can call itself.
+        self.functions.insert(function.name, function.params.len());
+        let script = std::mem::replace(&mut self.builder, Builder::new(function.name.as_str()));
+        let globals = std::mem::take(&mut self.scopes).pop();
+        self.globals = globals;
+        let params = function
+            .params
+            .iter()
+            .map(|param| (*param, self.builder.param(Ty::Value)))
+            .collect();
+        self.scopes.push(params);
+        for statement in &function.body {
+            self.statement(*statement)?;
+        }
+        self.builder.terminate(Terminator::Return(None));
+        let builder = std::mem::replace(&mut self.builder, script);
+        self.lowered.push(builder.finish());
+        self.scopes = vec![self.globals.take().unwrap()];
+        Ok(())
+    }
+    fn statement(&mut self, statement: NodeId) -> Result<(), Error> {
+        let line = self.ast.span(statement).line;
+        match self.ast.statement(statement) {
+            Statement::Expression(expression) => {
+                self.expression(*expression)?;
+            }
+            Statement::Print(expression) => {
+                let value = self.expression(*expression)?;
+                self.builder.print(value);
+            }
+            Statement::Var(VarStmt {
+                name, initializer, ..
+            }) => {
+                let value = match initializer {
+                    Some(initializer) => self.expression(*initializer)?,
+                    None => self.builder.constant(Constant::Nil),
+                };
+                let variable = self.builder.vreg(Ty::Value);
+                self.builder.copy(variable, value);
+                self.scopes.last_mut().unwrap().push((*name, variable));
+            }
+            Statement::Block(statements) => {
+                self.scopes.push(Vec::new());
+                let result = statements
+                    .iter()
+                    .try_for_each(|statement| self.statement(*statement));
+                self.scopes.pop();
+                result?;
+            }
+            Statement::If(IfStmt {
+                condition,
+                then,
+                otherwise,
+            }) => {
+                let cond = self.expression(*condition)?;
+                let then_block = self.builder.block();
+                let otherwise_block = self.builder.block();
+                let done = match otherwise {
+                    Some(_) => self.builder.block(),
+                    None => otherwise_block,
+                };
+                self.builder.terminate(Terminator::Branch {
+                    cond,
+                    then: then_block,
+                    otherwise: otherwise_block,
+                });
+                self.builder.switch_to(then_block);
+                self.statement(*then)?;
+                self.builder.terminate(Terminator::Jump(done));
+                if let Some(otherwise) = otherwise {
+                    self.builder.switch_to(otherwise_block);
+                    self.statement(*otherwise)?;
+                    self.builder.terminate(Terminator::Jump(done));
+                }
+                self.builder.switch_to(done);
+            }
+            Statement::While(WhileStmt { condition, body }) => {
+                let header = self.builder.block();
+                let body_block = self.builder.block();
+                let done = self.builder.block();
+                self.builder.terminate(Terminator::Jump(header));
+                self.builder.switch_to(header);
+                let cond = self.expression(*condition)?;
+                self.builder.terminate(Terminator::Branch {
+                    cond,
+                    then: body_block,
+                    otherwise: done,
+                });
+                self.builder.switch_to(body_block);
+                self.statement(*body)?;
+                self.builder.terminate(Terminator::Jump(header));
+                self.builder.switch_to(done);
+            }
+            Statement::Function(function) => self.function(function, line)?,
+            Statement::Return(ReturnStmt { value, .. }) => {
+                let value = value.map(|value| self.expression(value)).transpose()?;
+                self.builder.terminate(Terminator::Return(value));
+                // Anything after the return is never run.
+                let unreachable = self.builder.block();
+                self.builder.switch_to(unreachable);
+            }
+            Statement::Class(_) => return Self::unsupported("Classes", line),
+        }
+        Ok(())
+    }
+    fn expression(&mut self, expression: NodeId) -> Result<VReg, Error> {
+        let line = self.ast.span(expression).line;
+        Ok(match self.ast.expression(expression) {
+            Expression::Literal(literal) => self.builder.constant(match literal {
+                LiteralExpr::Nil => Constant::Nil,
+                LiteralExpr::True => Constant::Bool(true),
+                LiteralExpr::False => Constant::Bool(false),
+                LiteralExpr::Number(n) => Constant::Number(*n),
+                LiteralExpr::String(s) => Constant::String(*s),
+            }),
+            Expression::Grouping(inner) => self.expression(*inner)?,
+            Expression::ToString(inner) => {
+                let src = self.expression(*inner)?;
+                self.builder.unary(UnaryOp::ToString, src, line)
+            }
+            Expression::Unary(unary) => {
+                let src = self.expression(unary.expression)?;
+                let op = match unary.operator {
+                    "-" => UnaryOp::Negate,
+                    "!" => UnaryOp::Not,
+                    operator => unreachable!("unknown unary operator {operator}"),
+                };
+                self.builder.unary(op, src, line)
+            }
+            Expression::Binary(binary) => {
+                let lhs = self.expression(binary.lhs)?;
+                let rhs = self.expression(binary.rhs)?;
+                let op = match binary.operator {
+                    "+" => BinaryOp::Add,
+                    "-" => BinaryOp::Subtract,
+                    "*" => BinaryOp::Multiply,
+                    "/" => BinaryOp::Divide,
+                    "%" => BinaryOp::Modulo,
+                    "==" => BinaryOp::Equal,
+                    "!=" => BinaryOp::NotEqual,
+                    "<" => BinaryOp::Less,
+                    "<=" => BinaryOp::LessEqual,
+                    ">" => BinaryOp::Greater,
+                    ">=" => BinaryOp::GreaterEqual,
+                    operator => unreachable!("unknown binary operator {operator}"),
+                };
+                self.builder.binary(op, lhs, rhs, line)
+            }
+            Expression::Variable(variable) => {
+                let src = self.lookup(variable.name, line)?;
+                // A copy, so later assignments don't change this value.
+                let dst = self.builder.vreg(Ty::Value);
+                self.builder.copy(dst, src);
+                dst
+            }
+            Expression::Assign(assign) => {
+                if self.variable(assign.name).is_none() && self.functions.contains_key(&assign.name)
+                {
+                    return Self::unsupported("Assignments to functions", line);
+                }
+                let variable = self.lookup(assign.name, line)?;
+                let value = self.expression(assign.value)?;
+                self.builder.copy(variable, value);
+                value
+            }
+            Expression::Logical(logical) => {
+                // The result is the left operand unless it doesn't decide
+                // the result, then it's the right one.
+                let result = self.builder.vreg(Ty::Value);
+                let lhs = self.expression(logical.lhs)?;
+                self.builder.copy(result, lhs);
+                let rhs_block = self.builder.block();
+                let done = self.builder.block();
+                self.builder.terminate(match logical.operator {
+                    "and" => Terminator::Branch {
+                        cond: lhs,
+                        then: rhs_block,
+                        otherwise: done,
+                    },
+                    "or" => Terminator::Branch {
+                        cond: lhs,
+                        then: done,
+                        otherwise: rhs_block,
+                    },
+                    operator => unreachable!("unknown logical operator {operator}"),
+                });
+                self.builder.switch_to(rhs_block);
+                let rhs = self.expression(logical.rhs)?;
+                self.builder.copy(result, rhs);
+                self.builder.terminate(Terminator::Jump(done));
+                self.builder.switch_to(done);
+                result
+            }
+            Expression::Conditional(conditional) => {
+                let result = self.builder.vreg(Ty::Value);
+                let cond = self.expression(conditional.condition)?;
+                let then_block = self.builder.block();
+                let otherwise_block = self.builder.block();
+                let done = self.builder.block();
+                self.builder.terminate(Terminator::Branch {
+                    cond,
+                    then: then_block,
+                    otherwise: otherwise_block,
+                });
+                for (block, branch) in [
+                    (then_block, conditional.then),
+                    (otherwise_block, conditional.otherwise),
+                ] {
+                    self.builder.switch_to(block);
+                    let value = self.expression(branch)?;
+                    self.builder.copy(result, value);
+                    self.builder.terminate(Terminator::Jump(done));
+                }
+                self.builder.switch_to(done);
+                result
+            }
+            Expression::Call(call) => {
+                let Expression::Variable(VariableExpr { name, .. }) =
+                    self.ast.expression(call.callee)
+                else {
+                    return Self::unsupported("Calls to anything but functions", line);
+                };
+                let arity = match self.functions.get(name) {
+                    Some(arity) if self.variable(*name).is_none() => *arity,
+                    _ if self.declared.contains(name) && self.variable(*name).is_none() => {
+                        return Self::unsupported("Calls before the function is declared", line)
+                    }
+                    _ => return Self::unsupported("Calls to anything but functions", line),
+                };
+                if call.arguments.len() != arity {
+                    return Self::unsupported("Calls with the wrong number of arguments", line);
+                }
+                let args = call
+                    .arguments
+                    .iter()
+                    .map(|argument| self.expression(*argument))
+                    .collect::<Result<_, _>>()?;
+                self.builder.call(name.as_str(), args, line)
+            }
+            Expression::Get(_) => return Self::unsupported("Properties", line),
+            Expression::Set(_) => return Self::unsupported("Properties", line),
+            Expression::This => return Self::unsupported("'this'", line),
+            Expression::Super(_) => return Self::unsupported("'super'", line),
+        })
+    }
+}
@@ -0,0 +1,327 @@
+//! The mid-level IR, sitting between the AST and the x86_64 backend.
+//!
+//! A [`Module`] is a list of [`Function`]s, each a list of basic [`Block`]s.
+//! Blocks hold three-address [`Instruction`]s on typed virtual registers and
+//! end in exactly one [`Terminator`].
+pub(crate) mod builder;
+pub(crate) mod cfg;
+pub(crate) mod dce;
+pub(crate) mod dominators;
+pub(crate) mod fold;
+pub(crate) mod inline;
+pub(crate) mod interp;
+pub(crate) mod liveness;
+pub(crate) mod lower;
+mod printer;
+pub(crate) mod ssa;
+pub(crate) mod verify;
+
+use crate::symbol::Symbol;
+
+/// The name of the function holding a script's top level code.
+pub(crate) const SCRIPT: &str = "script";
+
+/// A virtual register, functions can use as many as they like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct VReg(pub(crate) u32);
+impl VReg {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct BlockId(pub(crate) u32);
+impl BlockId {
+    pub(crate) const ENTRY: BlockId = BlockId(0);
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// What a virtual register is known to hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Ty {
+    Nil,
+    Bool,
+    Number,
+    String,
+    /// Anything, only known at runtime.
+    Value,
+}
+impl Ty {
+    /// Whether a register of this type can hold a value of type `other`.
+    pub(crate) fn accepts(self, other: Ty) -> bool {
+        self == Ty::Value || self == other
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Constant {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Symbol),
+}
+impl Constant {
+    pub(crate) fn ty(&self) -> Ty {
+        match self {
+            Self::Nil => Ty::Nil,
+            Self::Bool(_) => Ty::Bool,
+            Self::Number(_) => Ty::Number,
+            Self::String(_) => Ty::String,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum UnaryOp {
+    Negate,
+    Not,
+    /// What `print` would write for a value.
+    ToString,
+}
+impl UnaryOp {
+    pub(crate) fn result(self) -> Ty {
+        match self {
+            Self::Negate => Ty::Number,
+            Self::Not => Ty::Bool,
+            Self::ToString => Ty::String,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// The remainder of dividing, with the sign of the dividend.
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+impl BinaryOp {
+    /// The type of `lhs op rhs`, if it doesn't fail at runtime.
+    pub(crate) fn result(self, lhs: Ty, rhs: Ty) -> Ty {
+        match self {
+            Self::Add => match (lhs, rhs) {
+                (Ty::Number, Ty::Number) => Ty::Number,
+                (Ty::String, Ty::String) => Ty::String,
+                _ => Ty::Value,
+            },
+            Self::Subtract | Self::Multiply | Self::Divide | Self::Modulo => Ty::Number,
+            _ => Ty::Bool,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Instruction {
+    Const {
+        dst: VReg,
+        value: Constant,
+    },
+    Copy {
+        dst: VReg,
+        src: VReg,
+    },
+    /// `line` is where runtime errors are reported.
+    Unary {
+        dst: VReg,
+        op: UnaryOp,
+        src: VReg,
+        line: usize,
+    },
+    Binary {
+        dst: VReg,
+        op: BinaryOp,
+        lhs: VReg,
+        rhs: VReg,
+        line: usize,
+    },
+    Print {
+        src: VReg,
+    },
+    /// Calls the function named `callee`, which takes as many parameters
+    /// as there are `args`.
+    Call {
+        dst: VReg,
+        callee: String,
+        args: Vec<VReg>,
+        line: usize,
+    },
+    /// Only in SSA form, at the start of a block: the value `args` has for
+    /// the predecessor control came from.
+    Phi {
+        dst: VReg,
+        args: Vec<(BlockId, VReg)>,
+    },
+}
+impl Instruction {
+    /// The register the instruction writes to.
+    pub(crate) fn dst(&self) -> Option<VReg> {
+        match self {
+            Self::Const { dst, .. }
+            | Self::Copy { dst, .. }
+            | Self::Unary { dst, .. }
+            | Self::Binary { dst, .. }
+            | Self::Call { dst, .. }
+            | Self::Phi { dst, .. } => Some(*dst),
+            Self::Print { .. } => None,
+        }
+    }
+    pub(crate) fn dst_mut(&mut self) -> Option<&mut VReg> {
+        match self {
+            Self::Const { dst, .. }
+            | Self::Copy { dst, .. }
+            | Self::Unary { dst, .. }
+            | Self::Binary { dst, .. }
+            | Self::Call { dst, .. }
+            | Self::Phi { dst, .. } => Some(dst),
+            Self::Print { .. } => None,
+        }
+    }
+    /// The registers the instruction reads.
+    pub(crate) fn uses(&self) -> Vec<VReg> {
+        match self {
+            Self::Const { .. } => Vec::new(),
+            Self::Copy { src, .. } | Self::Unary { src, .. } | Self::Print { src } => vec![*src],
+            Self::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Self::Call { args, .. } => args.clone(),
+            Self::Phi { args, .. } => args.iter().map(|(_, arg)| *arg).collect(),
+        }
+    }
+    pub(crate) fn uses_mut(&mut self) -> Vec<&mut VReg> {
+        match self {
+            Self::Const { .. } => Vec::new(),
+            Self::Copy { src, .. } | Self::Unary { src, .. } | Self::Print { src } => vec![src],
+            Self::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::Call { args, .. } => args.iter_mut().collect(),
+            Self::Phi { args, .. } => args.iter_mut().map(|(_, arg)| arg).collect(),
+        }
+    }
+    pub(crate) fn is_phi(&self) -> bool {
+        matches!(self, Self::Phi { .. })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Terminator {
+    Jump(BlockId),
+    /// Goes to `then` if `cond` is truthy, to `otherwise` if it isn't.
+    Branch {
+        cond: VReg,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    Return(Option<VReg>),
+    /// Control never reaches the end of the block.
+    Unreachable,
+}
+impl Terminator {
+    pub(crate) fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(target) => vec![*target],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Self::Return(_) | Self::Unreachable => Vec::new(),
+        }
+    }
+    pub(crate) fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Self::Jump(target) => vec![target],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Self::Return(_) | Self::Unreachable => Vec::new(),
+        }
+    }
+    pub(crate) fn uses(&self) -> Option<VReg> {
+        match self {
+            Self::Branch { cond, .. } => Some(*cond),
+            Self::Return(value) => *value,
+            Self::Jump(_) | Self::Unreachable => None,
+        }
+    }
+    pub(crate) fn uses_mut(&mut self) -> Option<&mut VReg> {
+        match self {
+            Self::Branch { cond, .. } => Some(cond),
+            Self::Return(value) => value.as_mut(),
+            Self::Jump(_) | Self::Unreachable => None,
+        }
+    }
+    /// Points every edge to `from` at `to` instead.
+    pub(crate) fn retarget(&mut self, from: BlockId, to: BlockId) {
+        match self {
+            Self::Jump(target) if *target == from => *target = to,
+            Self::Branch {
+                then, otherwise, ..
+            } => {
+                if *then == from {
+                    *then = to;
+                }
+                if *otherwise == from {
+                    *otherwise = to;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Block {
+    pub(crate) instructions: Vec<Instruction>,
+    pub(crate) terminator: Terminator,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) params: Vec<VReg>,
+    /// The type of every virtual register, indexed by its number.
+    pub(crate) types: Vec<Ty>,
+    /// The first block is the entry.
+    pub(crate) blocks: Vec<Block>,
+}
+impl Function {
+    pub(crate) fn ty(&self, vreg: VReg) -> Ty {
+        self.types[vreg.index()]
+    }
+    pub(crate) fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.index()]
+    }
+    pub(crate) fn block_ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+    /// A fresh register.
+    pub(crate) fn vreg(&mut self, ty: Ty) -> VReg {
+        self.types.push(ty);
+        VReg(self.types.len() as u32 - 1)
+    }
+    /// Appends an empty block ending in `terminator`.
+    pub(crate) fn add_block(&mut self, terminator: Terminator) -> BlockId {
+        self.blocks.push(Block {
+            instructions: Vec::new(),
+            terminator,
+        });
+        BlockId(self.blocks.len() as u32 - 1)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Module {
+    pub(crate) functions: Vec<Function>,
+}
+impl Module {
+    pub(crate) fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
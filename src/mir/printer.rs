@@ -0,0 +1,241 @@
+//! The textual form of the IR.
+//!
+//! ```text
+//! fn script() {
+//! bb0:
+//!     %0: number = const 1.0
+//!     %1: number = const 2.0
+//!     %2: number = add %0, %1
+//!     print %2
+//!     return
+//! }
+//! ```
+use super::{
+    BinaryOp, Block, BlockId, Constant, Function, Instruction, Module, Terminator, Ty, UnaryOp,
+    VReg,
+};
+use std::fmt;
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool => write!(f, "bool"),
+            Self::Number => write!(f, "number"),
+            Self::String => write!(f, "string"),
+            Self::Value => write!(f, "value"),
+        }
+    }
+}
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{n:?}"),
+            Self::String(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Negate => write!(f, "neg"),
+            Self::Not => write!(f, "not"),
+            Self::ToString => write!(f, "str"),
+        }
+    }
+}
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add => write!(f, "add"),
+            Self::Subtract => write!(f, "sub"),
+            Self::Multiply => write!(f, "mul"),
+            Self::Divide => write!(f, "div"),
+            Self::Modulo => write!(f, "mod"),
+            Self::Equal => write!(f, "eq"),
+            Self::NotEqual => write!(f, "ne"),
+            Self::Less => write!(f, "lt"),
+            Self::LessEqual => write!(f, "le"),
+            Self::Greater => write!(f, "gt"),
+            Self::GreaterEqual => write!(f, "ge"),
+        }
+    }
+}
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Const { dst, value } => write!(f, "{dst} = const {value}"),
+            Self::Copy { dst, src } => write!(f, "{dst} = copy {src}"),
+            Self::Unary { dst, op, src, .. } => write!(f, "{dst} = {op} {src}"),
+            Self::Binary {
+                dst, op, lhs, rhs, ..
+            } => write!(f, "{dst} = {op} {lhs}, {rhs}"),
+            Self::Print { src } => write!(f, "print {src}"),
+            Self::Call {
+                dst, callee, args, ..
+            } => {
+                write!(f, "{dst} = call {callee}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    let separator = if i > 0 { ", " } else { "" };
+                    write!(f, "{separator}{arg}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Phi { dst, args } => {
+                write!(f, "{dst} = phi")?;
+                for (i, (block, arg)) in args.iter().enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    write!(f, "{separator} [{block}: {arg}]")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jump {target}"),
+            Self::Branch {
+                cond,
+                then,
+                otherwise,
+            } => write!(f, "branch {cond}, {then}, {otherwise}"),
+            Self::Return(Some(value)) => write!(f, "return {value}"),
+            Self::Return(None) => write!(f, "return"),
+            Self::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+impl Function {
+    fn fmt_block(&self, f: &mut fmt::Formatter<'_>, id: BlockId, block: &Block) -> fmt::Result {
+        writeln!(f, "{id}:")?;
+        for instruction in &block.instructions {
+            // Annotate definitions with their type, `%0: number = ...`.
+            match instruction.dst() {
+                Some(dst) => {
+                    let text = instruction.to_string();
+                    let rest = &text[dst.to_string().len()..];
+                    writeln!(f, "    {dst}: {}{rest}", self.ty(dst))?;
+                }
+                None => writeln!(f, "    {instruction}")?,
+            }
+        }
+        writeln!(f, "    {}", block.terminator)
+    }
+}
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fn {}(", self.name)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{param}: {}", self.ty(*param))?;
+        }
+        writeln!(f, ") {{")?;
+        for (id, block) in self.block_ids().zip(&self.blocks) {
+            self.fmt_block(f, id, block)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            function.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mir::builder::Builder, symbol::Symbol};
+
+    #[test]
+    fn module() {
+        let mut max = Builder::new("max");
+        let (a, b) = (max.param(Ty::Number), max.param(Ty::Number));
+        let cond = max.binary(BinaryOp::Greater, a, b, 1);
+        let (then, otherwise, join) = (max.block(), max.block(), max.block());
+        max.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        for block in [then, otherwise] {
+            max.switch_to(block);
+            max.terminate(Terminator::Jump(join));
+        }
+        max.switch_to(join);
+        let result = max.vreg(Ty::Number);
+        max.push(Instruction::Phi {
+            dst: result,
+            args: vec![(then, a), (otherwise, b)],
+        });
+        max.terminate(Terminator::Return(Some(result)));
+
+        let mut script = Builder::new("script");
+        let one = script.constant(Constant::Number(1.0));
+        let half = script.constant(Constant::Number(-0.5));
+        script.constant(Constant::String(Symbol::intern("a \"b\"")));
+        script.constant(Constant::Nil);
+        let larger = script.call("max", vec![one, half], 2);
+        let text = script.unary(UnaryOp::ToString, larger, 2);
+        script.print(text);
+        script.terminate(Terminator::Return(None));
+        let dead = script.block();
+        script.switch_to(dead);
+
+        let module = Module {
+            functions: vec![max.finish(), script.finish()],
+        };
+        assert_eq!(
+            module.to_string(),
+            r#"fn max(%0: number, %1: number) {
+bb0:
+    %2: bool = gt %0, %1
+    branch %2, bb1, bb2
+bb1:
+    jump bb3
+bb2:
+    jump bb3
+bb3:
+    %3: number = phi [bb1: %0], [bb2: %1]
+    return %3
+}
+
+fn script() {
+bb0:
+    %0: number = const 1.0
+    %1: number = const -0.5
+    %2: string = const "a \"b\""
+    %3: nil = const nil
+    %4: value = call max(%0, %1)
+    %5: string = str %4
+    print %5
+    return
+bb1:
+    unreachable
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! Converting functions to and from static single assignment form, where
+//! every register is written by exactly one instruction and values that
+//! depend on the path taken meet in [`Instruction::Phi`]s.
+//!
+//! Phis are placed at the iterated dominance frontiers of a register's
+//! definitions, only where the register is still live (pruned SSA), and
+//! registers are renamed walking the dominator tree, as in Cytron et al.
+//! Leaving SSA gives every edge from a branch to a block with phis a block
+//! of its own, then replaces the phis with copies at the end of the
+//! predecessors.
+use super::{
+    cfg::Cfg, dominators::Dominators, liveness::Liveness, Block, BlockId, Function, Instruction,
+    Terminator, Ty, VReg,
+};
+use std::collections::HashMap;
+
+/// Rewrites `function`, which has no phis and uses no register before it's
+/// defined, into SSA form. Registers are renumbered, and unreachable blocks
+/// are emptied.
+pub(crate) fn to_ssa(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    for id in function.block_ids() {
+        if !cfg.is_reachable(id) {
+            let block = &mut function.blocks[id.index()];
+            block.instructions.clear();
+            block.terminator = Terminator::Unreachable;
+        }
+    }
+    let cfg = Cfg::new(function);
+    let dominators = Dominators::new(&cfg);
+    insert_phis(function, &cfg, &dominators);
+    Renamer::new(function, &cfg).rename(function, &dominators);
+}
+
+/// Adds phis for every register written more than once, with itself for
+/// every argument until renaming.
+fn insert_phis(function: &mut Function, cfg: &Cfg, dominators: &Dominators) {
+    let registers = function.types.len();
+    let mut definitions = vec![0usize; registers];
+    let mut sites = vec![Vec::new(); registers];
+    for param in &function.params {
+        definitions[param.index()] += 1;
+        sites[param.index()].push(BlockId::ENTRY);
+    }
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        for dst in block.instructions.iter().filter_map(Instruction::dst) {
+            definitions[dst.index()] += 1;
+            let sites = &mut sites[dst.index()];
+            if sites.last() != Some(&id) {
+                sites.push(id);
+            }
+        }
+    }
+
+    let liveness = Liveness::new(function, cfg);
+    let frontiers = dominators.frontiers(cfg);
+    let mut phis = vec![Vec::new(); function.blocks.len()];
+    // The register each block last got a phi for, or was queued for.
+    let mut placed = vec![None; function.blocks.len()];
+    let mut queued = vec![None; function.blocks.len()];
+    for vreg in (0..registers as u32).map(VReg) {
+        if definitions[vreg.index()] < 2 {
+            continue;
+        }
+        let mut work = sites[vreg.index()].clone();
+        for block in &work {
+            queued[block.index()] = Some(vreg);
+        }
+        while let Some(block) = work.pop() {
+            for &frontier in &frontiers[block.index()] {
+                if placed[frontier.index()] == Some(vreg) {
+                    continue;
+                }
+                placed[frontier.index()] = Some(vreg);
+                if liveness.live_in(frontier).contains(vreg) {
+                    phis[frontier.index()].push(vreg);
+                }
+                if queued[frontier.index()] != Some(vreg) {
+                    queued[frontier.index()] = Some(vreg);
+                    work.push(frontier);
+                }
+            }
+        }
+    }
+
+    for (id, vregs) in function.block_ids().zip(phis) {
+        let args: Vec<_> = cfg.predecessors(id).to_vec();
+        let phis = vregs.into_iter().map(|vreg| Instruction::Phi {
+            dst: vreg,
+            args: args.iter().map(|block| (*block, vreg)).collect(),
+        });
+        function.blocks[id.index()].instructions.splice(0..0, phis);
+    }
+}
+
+/// Gives every definition a register of its own.
+struct Renamer {
+    /// The new registers' types.
+    types: Vec<Ty>,
+    /// The current name of each old register, innermost last.
+    names: Vec<Vec<VReg>>,
+    successors: Vec<Vec<BlockId>>,
+}
+impl Renamer {
+    fn new(function: &Function, cfg: &Cfg) -> Self {
+        Self {
+            types: Vec::new(),
+            names: vec![Vec::new(); function.types.len()],
+            successors: function
+                .block_ids()
+                .map(|id| cfg.successors(id).to_vec())
+                .collect(),
+        }
+    }
+    fn define(&mut self, function: &Function, vreg: &mut VReg) {
+        let new = VReg(self.types.len() as u32);
+        self.types.push(function.ty(*vreg));
+        self.names[vreg.index()].push(new);
+        *vreg = new;
+    }
+    fn name(&self, vreg: &mut VReg) {
+        *vreg = *self.names[vreg.index()]
+            .last()
+            .unwrap_or_else(|| panic!("{vreg} is used before it's defined"));
+    }
+    fn rename(mut self, function: &mut Function, dominators: &Dominators) {
+        let mut params = std::mem::take(&mut function.params);
+        for param in &mut params {
+            self.define(function, param);
+        }
+        function.params = params;
+
+        // Blocks to enter, or leave once their subtree is done, with the
+        // old registers they defined.
+        enum Visit {
+            Enter(BlockId),
+            Leave(Vec<VReg>),
+        }
+        let mut stack = vec![Visit::Enter(BlockId::ENTRY)];
+        while let Some(visit) = stack.pop() {
+            let id = match visit {
+                Visit::Enter(id) => id,
+                Visit::Leave(defined) => {
+                    for vreg in defined {
+                        self.names[vreg.index()].pop();
+                    }
+                    continue;
+                }
+            };
+            let mut block = std::mem::replace(
+                &mut function.blocks[id.index()],
+                Block {
+                    instructions: Vec::new(),
+                    terminator: Terminator::Unreachable,
+                },
+            );
+            let mut defined = Vec::new();
+            for instruction in &mut block.instructions {
+                if !instruction.is_phi() {
+                    for vreg in instruction.uses_mut() {
+                        self.name(vreg);
+                    }
+                }
+                if let Some(dst) = instruction.dst_mut() {
+                    defined.push(*dst);
+                    self.define(function, dst);
+                }
+            }
+            if let Some(vreg) = block.terminator.uses_mut() {
+                self.name(vreg);
+            }
+            function.blocks[id.index()] = block;
+
+            for successor in self.successors[id.index()].clone() {
+                for instruction in &mut function.blocks[successor.index()].instructions {
+                    let Instruction::Phi { args, .. } = instruction else {
+                        break;
+                    };
+                    for (_, arg) in args.iter_mut().filter(|(block, _)| *block == id) {
+                        self.name(arg);
+                    }
+                }
+            }
+
+            stack.push(Visit::Leave(defined));
+            for &child in dominators.children(id).iter().rev() {
+                stack.push(Visit::Enter(child));
+            }
+        }
+        function.types = self.types;
+    }
+}
+
+/// Replaces `function`'s phis with copies, leaving it out of SSA form.
+pub(crate) fn from_ssa(function: &mut Function) {
+    split_edges(function);
+    // The copies at the end of each predecessor, all done at once.
+    let mut copies: HashMap<BlockId, Vec<(VReg, VReg)>> = HashMap::new();
+    for block in &mut function.blocks {
+        let phis = block.instructions.iter().take_while(|i| i.is_phi()).count();
+        for phi in block.instructions.drain(..phis) {
+            let Instruction::Phi { dst, args } = phi else {
+                unreachable!()
+            };
+            for (predecessor, arg) in args {
+                copies.entry(predecessor).or_default().push((dst, arg));
+            }
+        }
+    }
+    for id in function.block_ids().collect::<Vec<_>>() {
+        let Some(parallel) = copies.remove(&id) else {
+            continue;
+        };
+        debug_assert!(matches!(function.block(id).terminator, Terminator::Jump(_)));
+        let sequential = sequentialize(function, parallel);
+        function.blocks[id.index()].instructions.extend(sequential);
+    }
+}
+
+/// Moves every edge from a branch to a block with phis into a block of its
+/// own, so there's somewhere to put its copies after the branch reads its
+/// condition and before control meets other edges.
+fn split_edges(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    for id in function.block_ids().collect::<Vec<_>>() {
+        let has_phis = function.blocks[id.index()]
+            .instructions
+            .first()
+            .is_some_and(Instruction::is_phi);
+        if !has_phis {
+            continue;
+        }
+        for &predecessor in cfg.predecessors(id) {
+            let terminator = &function.blocks[predecessor.index()].terminator;
+            if !matches!(terminator, Terminator::Branch { .. }) {
+                continue;
+            }
+            let edge = function.add_block(Terminator::Jump(id));
+            function.blocks[predecessor.index()]
+                .terminator
+                .retarget(id, edge);
+            for instruction in &mut function.blocks[id.index()].instructions {
+                if let Instruction::Phi { args, .. } = instruction {
+                    for (block, _) in args.iter_mut().filter(|(block, _)| *block == predecessor) {
+                        *block = edge;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Orders copies that happen at the same time, `(dst, src)`, so none
+/// overwrites a register another still has to read. Cycles go through a new
+/// register.
+fn sequentialize(function: &mut Function, mut parallel: Vec<(VReg, VReg)>) -> Vec<Instruction> {
+    parallel.retain(|(dst, src)| dst != src);
+    let mut sequential = Vec::new();
+    while !parallel.is_empty() {
+        let free = parallel
+            .iter()
+            .position(|(dst, _)| parallel.iter().all(|(_, src)| src != dst));
+        match free {
+            Some(i) => {
+                let (dst, src) = parallel.remove(i);
+                sequential.push(Instruction::Copy { dst, src });
+            }
+            None => {
+                // Everything left is in cycles, save one destination and
+                // read it from the copy instead.
+                let (dst, _) = parallel[0];
+                let temp = function.vreg(function.ty(dst));
+                sequential.push(Instruction::Copy {
+                    dst: temp,
+                    src: dst,
+                });
+                for (_, src) in &mut parallel {
+                    if *src == dst {
+                        *src = temp;
+                    }
+                }
+            }
+        }
+    }
+    sequential
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{builder::Builder, interp, verify, BinaryOp, Constant, Module, SCRIPT};
+
+    fn module(function: Function) -> Module {
+        Module {
+            functions: vec![function],
+        }
+    }
+    fn output(function: &Function) -> String {
+        let mut out = Vec::new();
+        interp::run(&module(function.clone()), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    /// Takes `function` into SSA form and back, checking both are well
+    /// formed and print the same.
+    fn round_trip(function: &Function) -> Function {
+        let mut ssa = function.clone();
+        to_ssa(&mut ssa);
+        assert_eq!(verify::verify_ssa(&module(ssa.clone())), Ok(()));
+        assert_eq!(output(&ssa), output(function));
+        let mut out = ssa.clone();
+        from_ssa(&mut out);
+        assert_eq!(verify::verify(&module(out.clone())), Ok(()));
+        assert_eq!(output(&out), output(function));
+        ssa
+    }
+    fn phis(function: &Function) -> Vec<usize> {
+        function
+            .blocks
+            .iter()
+            .map(|block| block.instructions.iter().filter(|i| i.is_phi()).count())
+            .collect()
+    }
+
+    #[test]
+    fn diamond() {
+        let mut f = Builder::new(SCRIPT);
+        let cond = f.constant(Constant::Bool(true));
+        let x = f.constant(Constant::Number(1.0));
+        let (then, otherwise, join) = (f.block(), f.block(), f.block());
+        f.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        f.switch_to(then);
+        let two = f.constant(Constant::Number(2.0));
+        f.copy(x, two);
+        // Redefined but dead after the branch, so no phi.
+        f.copy(cond, cond);
+        f.terminate(Terminator::Jump(join));
+        f.switch_to(otherwise);
+        f.terminate(Terminator::Jump(join));
+        f.switch_to(join);
+        f.print(x);
+        f.terminate(Terminator::Return(None));
+
+        let ssa = round_trip(&f.finish());
+        assert_eq!(
+            ssa.to_string(),
+            "fn script() {
+bb0:
+    %0: bool = const true
+    %1: number = const 1.0
+    branch %0, bb1, bb2
+bb1:
+    %2: number = const 2.0
+    %3: number = copy %2
+    %4: bool = copy %0
+    jump bb3
+bb2:
+    jump bb3
+bb3:
+    %5: number = phi [bb1: %3], [bb2: %1]
+    print %5
+    return
+}
+"
+        );
+    }
+
+    #[test]
+    fn loops() {
+        // i = 0; while (i < 3) i = i + 1; print i;
+        let mut f = Builder::new(SCRIPT);
+        let i = f.constant(Constant::Number(0.0));
+        let (header, body, exit) = (f.block(), f.block(), f.block());
+        f.terminate(Terminator::Jump(header));
+        f.switch_to(header);
+        let three = f.constant(Constant::Number(3.0));
+        let cond = f.binary(BinaryOp::Less, i, three, 1);
+        f.terminate(Terminator::Branch {
+            cond,
+            then: body,
+            otherwise: exit,
+        });
+        f.switch_to(body);
+        let one = f.constant(Constant::Number(1.0));
+        let next = f.binary(BinaryOp::Add, i, one, 1);
+        f.copy(i, next);
+        f.terminate(Terminator::Jump(header));
+        f.switch_to(exit);
+        f.print(i);
+        f.terminate(Terminator::Return(None));
+
+        let function = f.finish();
+        assert_eq!(output(&function), "3\n");
+        let ssa = round_trip(&function);
+        assert_eq!(
+            ssa.to_string(),
+            "fn script() {
+bb0:
+    %0: number = const 0.0
+    jump bb1
+bb1:
+    %1: number = phi [bb0: %0], [bb2: %6]
+    %2: number = const 3.0
+    %3: bool = lt %1, %2
+    branch %3, bb2, bb3
+bb2:
+    %4: number = const 1.0
+    %5: number = add %1, %4
+    %6: number = copy %5
+    jump bb1
+bb3:
+    print %1
+    return
+}
+"
+        );
+    }
+
+    #[test]
+    fn nested_loops() {
+        // i = 0; j = 0;
+        // while (i < 3) { while (j < 3) j = j + 1; i = i + 1; }
+        // print i; print j;
+        let mut f = Builder::new(SCRIPT);
+        let i = f.constant(Constant::Number(0.0));
+        let j = f.constant(Constant::Number(0.0));
+        let three = f.constant(Constant::Number(3.0));
+        let one = f.constant(Constant::Number(1.0));
+        let blocks: Vec<_> = (0..5).map(|_| f.block()).collect();
+        let [outer, inner, inner_body, outer_latch, exit] = blocks[..] else {
+            unreachable!()
+        };
+        f.terminate(Terminator::Jump(outer));
+        for (block, counter, then, otherwise) in
+            [(outer, i, inner, exit), (inner, j, inner_body, outer_latch)]
+        {
+            f.switch_to(block);
+            let cond = f.binary(BinaryOp::Less, counter, three, 1);
+            f.terminate(Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            });
+        }
+        for (block, counter, header) in [(inner_body, j, inner), (outer_latch, i, outer)] {
+            f.switch_to(block);
+            let next = f.binary(BinaryOp::Add, counter, one, 1);
+            f.copy(counter, next);
+            f.terminate(Terminator::Jump(header));
+        }
+        f.switch_to(exit);
+        f.print(i);
+        f.print(j);
+        f.terminate(Terminator::Return(None));
+
+        let function = f.finish();
+        assert_eq!(output(&function), "3\n3\n");
+        let ssa = round_trip(&function);
+        // i and j meet at the outer loop's header, only j at the inner
+        // one's.
+        assert_eq!(phis(&ssa), [0, 2, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn unreachable_blocks_are_emptied() {
+        let mut f = Builder::new(SCRIPT);
+        f.terminate(Terminator::Return(None));
+        let dead = f.block();
+        f.switch_to(dead);
+        let n = f.constant(Constant::Number(1.0));
+        f.print(n);
+        f.terminate(Terminator::Return(None));
+        let mut function = f.finish();
+        to_ssa(&mut function);
+        assert!(function.blocks[1].instructions.is_empty());
+        assert_eq!(function.blocks[1].terminator, Terminator::Unreachable);
+    }
+
+    #[test]
+    fn swapped_copies_go_through_a_temporary() {
+        // Leaving SSA, `a, b = b, a` on a back edge can't be two copies.
+        let mut f = Function {
+            name: SCRIPT.to_string(),
+            params: Vec::new(),
+            types: Vec::new(),
+            blocks: Vec::new(),
+        };
+        let (a, b) = (f.vreg(Ty::Number), f.vreg(Ty::Number));
+        let copies = sequentialize(&mut f, vec![(a, b), (b, a)]);
+        assert_eq!(
+            copies,
+            vec![
+                Instruction::Copy {
+                    dst: VReg(2),
+                    src: a
+                },
+                Instruction::Copy { dst: a, src: b },
+                Instruction::Copy {
+                    dst: b,
+                    src: VReg(2)
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! Checks a [`Module`] is well formed: registers and blocks exist, every
+//! register is defined on all paths before it's used, instructions agree
+//! with the types of the registers they write, phis come first and have an
+//! argument for each predecessor, and calls pass each function as many
+//! arguments as it has parameters.
+use super::{cfg::Cfg, BlockId, Function, Instruction, Module, Ty};
+use std::{collections::HashSet, fmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Error {
+    function: String,
+    block: Option<BlockId>,
+    message: String,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "in {}, {block}: {}", self.function, self.message),
+            None => write!(f, "in {}: {}", self.function, self.message),
+        }
+    }
+}
+
+pub(crate) fn verify(module: &Module) -> Result<(), Vec<Error>> {
+    check(module, false)
+}
+
+/// Like [`verify`], and every register is written at most once.
+pub(crate) fn verify_ssa(module: &Module) -> Result<(), Vec<Error>> {
+    check(module, true)
+}
+
+fn check(module: &Module, ssa: bool) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    for function in &module.functions {
+        if !names.insert(function.name.as_str()) {
+            errors.push(Error {
+                function: function.name.clone(),
+                block: None,
+                message: "defined more than once".to_string(),
+            });
+        }
+        Verifier {
+            function,
+            errors: &mut errors,
+        }
+        .run(ssa);
+        calls(module, function, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Every function `function` calls has to exist and take the arguments
+/// it's passed.
+fn calls(module: &Module, function: &Function, errors: &mut Vec<Error>) {
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        for instruction in &block.instructions {
+            let Instruction::Call { callee, args, .. } = instruction else {
+                continue;
+            };
+            let message = match module.function(callee) {
+                None => format!("`{instruction}` calls a missing function"),
+                Some(callee) if callee.params.len() != args.len() => format!(
+                    "`{instruction}` passes {} arguments to {} parameters",
+                    args.len(),
+                    callee.params.len()
+                ),
+                Some(_) => continue,
+            };
+            errors.push(Error {
+                function: function.name.clone(),
+                block: Some(id),
+                message,
+            });
+        }
+    }
+}
+
+struct Verifier<'a> {
+    function: &'a Function,
+    errors: &'a mut Vec<Error>,
+}
+impl Verifier<'_> {
+    fn error<T: ToString>(&mut self, block: Option<BlockId>, message: T) {
+        self.errors.push(Error {
+            function: self.function.name.clone(),
+            block,
+            message: message.to_string(),
+        });
+    }
+    fn run(mut self, ssa: bool) {
+        let before = self.errors.len();
+        self.references();
+        // The other checks index with the references.
+        if self.errors.len() == before {
+            let cfg = Cfg::new(self.function);
+            self.phis(&cfg);
+            self.types();
+            self.definitions(&cfg);
+            if ssa {
+                self.single_definitions();
+            }
+        }
+    }
+    /// Every register and block mentioned has to exist.
+    fn references(&mut self) {
+        let function = self.function;
+        if function.blocks.is_empty() {
+            self.error(None, "has no blocks");
+        }
+        let registers = function.types.len();
+        let mut params = HashSet::new();
+        for param in &function.params {
+            if param.index() >= registers {
+                self.error(None, format!("parameter {param} isn't declared"));
+            } else if !params.insert(param) {
+                self.error(None, format!("parameter {param} appears twice"));
+            }
+        }
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            for instruction in &block.instructions {
+                for vreg in instruction.uses().into_iter().chain(instruction.dst()) {
+                    if vreg.index() >= registers {
+                        self.error(Some(id), format!("`{instruction}` uses undeclared {vreg}"));
+                    }
+                }
+                if let Instruction::Phi { args, .. } = instruction {
+                    if args
+                        .iter()
+                        .any(|(block, _)| block.index() >= function.blocks.len())
+                    {
+                        self.error(Some(id), format!("`{instruction}` names a missing block"));
+                    }
+                }
+            }
+            let terminator = &block.terminator;
+            if let Some(vreg) = terminator.uses() {
+                if vreg.index() >= registers {
+                    self.error(Some(id), format!("`{terminator}` uses undeclared {vreg}"));
+                }
+            }
+            for target in terminator.successors() {
+                if target.index() >= function.blocks.len() {
+                    self.error(Some(id), format!("`{terminator}` goes to a missing block"));
+                } else if target == BlockId::ENTRY {
+                    self.error(Some(id), format!("`{terminator}` goes to the entry block"));
+                }
+            }
+        }
+    }
+    /// Phis have to come before other instructions, with one argument for
+    /// each predecessor.
+    fn phis(&mut self, cfg: &Cfg) {
+        let function = self.function;
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            let mut predecessors = cfg.predecessors(id).to_vec();
+            predecessors.sort();
+            let mut others = false;
+            for instruction in &block.instructions {
+                let Instruction::Phi { args, .. } = instruction else {
+                    others = true;
+                    continue;
+                };
+                if others {
+                    self.error(Some(id), format!("`{instruction}` comes after non-phis"));
+                }
+                let mut blocks: Vec<_> = args.iter().map(|(block, _)| *block).collect();
+                blocks.sort();
+                if blocks != predecessors {
+                    self.error(
+                        Some(id),
+                        format!("`{instruction}` doesn't match the block's predecessors"),
+                    );
+                }
+            }
+        }
+    }
+    /// Registers have to be able to hold what's written to them.
+    fn types(&mut self) {
+        let function = self.function;
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            for instruction in &block.instructions {
+                let (dst, types) = match instruction {
+                    Instruction::Const { dst, value } => (*dst, vec![value.ty()]),
+                    Instruction::Copy { dst, src } => (*dst, vec![function.ty(*src)]),
+                    Instruction::Unary { dst, op, .. } => (*dst, vec![op.result()]),
+                    Instruction::Binary {
+                        dst, op, lhs, rhs, ..
+                    } => (*dst, vec![op.result(function.ty(*lhs), function.ty(*rhs))]),
+                    Instruction::Call { dst, .. } => (*dst, vec![Ty::Value]),
+                    Instruction::Phi { dst, args } => (
+                        *dst,
+                        args.iter().map(|(_, arg)| function.ty(*arg)).collect(),
+                    ),
+                    Instruction::Print { .. } => continue,
+                };
+                for ty in types {
+                    if !function.ty(dst).accepts(ty) {
+                        self.error(
+                            Some(id),
+                            format!(
+                                "`{instruction}` writes a {ty} to {dst}: {}",
+                                function.ty(dst)
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    /// Registers have to be written on every path to a use, checked for
+    /// the blocks reachable from the entry.
+    fn definitions(&mut self, cfg: &Cfg) {
+        let function = self.function;
+        let blocks = function.blocks.len();
+
+        // Registers defined on entry to each block, everything is assumed
+        // defined until a path proves otherwise.
+        let all = vec![true; function.types.len()];
+        let mut defined = vec![all; blocks];
+        defined[0] = vec![false; function.types.len()];
+        for param in &function.params {
+            defined[0][param.index()] = true;
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in function
+                .block_ids()
+                .skip(1)
+                .filter(|id| cfg.is_reachable(*id))
+            {
+                let mut entry = vec![true; function.types.len()];
+                for predecessor in cfg.predecessors(id) {
+                    if !cfg.is_reachable(*predecessor) {
+                        continue;
+                    }
+                    let exit = defined_after(function, *predecessor, &defined[predecessor.index()]);
+                    for (entry, exit) in entry.iter_mut().zip(exit) {
+                        *entry &= exit;
+                    }
+                }
+                if entry != defined[id.index()] {
+                    defined[id.index()] = entry;
+                    changed = true;
+                }
+            }
+        }
+
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            if !cfg.is_reachable(id) {
+                continue;
+            }
+            let mut defined_here = defined[id.index()].clone();
+            for instruction in &block.instructions {
+                let undefined: Vec<_> = match instruction {
+                    // Arguments are read on the way out of the predecessor.
+                    Instruction::Phi { args, .. } => args
+                        .iter()
+                        .filter(|(predecessor, vreg)| {
+                            let entry = &defined[predecessor.index()];
+                            cfg.is_reachable(*predecessor)
+                                && !defined_after(function, *predecessor, entry)[vreg.index()]
+                        })
+                        .map(|(_, vreg)| *vreg)
+                        .collect(),
+                    _ => instruction
+                        .uses()
+                        .into_iter()
+                        .filter(|vreg| !defined_here[vreg.index()])
+                        .collect(),
+                };
+                for vreg in undefined {
+                    self.error(
+                        Some(id),
+                        format!("`{instruction}` may use {vreg} before it's defined"),
+                    );
+                }
+                if let Some(dst) = instruction.dst() {
+                    defined_here[dst.index()] = true;
+                }
+            }
+            let terminator = &block.terminator;
+            if let Some(vreg) = terminator.uses() {
+                if !defined_here[vreg.index()] {
+                    self.error(
+                        Some(id),
+                        format!("`{terminator}` may use {vreg} before it's defined"),
+                    );
+                }
+            }
+        }
+    }
+    /// In SSA form, each register is written by one instruction or is a
+    /// parameter.
+    fn single_definitions(&mut self) {
+        let function = self.function;
+        let mut defined = vec![false; function.types.len()];
+        for param in &function.params {
+            defined[param.index()] = true;
+        }
+        for (id, block) in function.block_ids().zip(&function.blocks) {
+            for instruction in &block.instructions {
+                if let Some(dst) = instruction.dst() {
+                    if std::mem::replace(&mut defined[dst.index()], true) {
+                        self.error(Some(id), format!("`{instruction}` redefines {dst}"));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The registers defined at the end of `block`, given those defined at the
+/// start.
+fn defined_after(function: &Function, block: BlockId, entry: &[bool]) -> Vec<bool> {
+    let mut defined = entry.to_vec();
+    for dst in function
+        .block(block)
+        .instructions
+        .iter()
+        .filter_map(Instruction::dst)
+    {
+        defined[dst.index()] = true;
+    }
+    defined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{builder::Builder, BinaryOp, Constant, Terminator, VReg};
+
+    fn errors(functions: Vec<Function>) -> Vec<String> {
+        match verify(&Module { functions }) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(Error::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn well_formed() {
+        let mut add = Builder::new("add");
+        let n = add.param(Ty::Number);
+        let one = add.constant(Constant::Number(1.0));
+        let sum = add.binary(BinaryOp::Add, n, one, 1);
+        add.terminate(Terminator::Return(Some(sum)));
+        let mut script = Builder::new("script");
+        let two = script.constant(Constant::Number(2.0));
+        let three = script.call("add", vec![two], 2);
+        script.print(three);
+        script.terminate(Terminator::Return(None));
+        let module = Module {
+            functions: vec![add.finish(), script.finish()],
+        };
+        assert_eq!(verify(&module), Ok(()));
+        assert_eq!(verify_ssa(&module), Ok(()));
+    }
+
+    #[test]
+    fn undeclared_registers_and_missing_blocks() {
+        let mut f = Builder::new("f");
+        f.print(VReg(3));
+        f.terminate(Terminator::Jump(BlockId(5)));
+        assert_eq!(
+            errors(vec![f.finish()]),
+            vec![
+                "in f, bb0: `print %3` uses undeclared %3",
+                "in f, bb0: `jump bb5` goes to a missing block",
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_to_the_entry() {
+        let mut f = Builder::new("f");
+        f.terminate(Terminator::Jump(BlockId::ENTRY));
+        assert_eq!(
+            errors(vec![f.finish()]),
+            vec!["in f, bb0: `jump bb0` goes to the entry block"]
+        );
+    }
+
+    #[test]
+    fn use_defined_on_one_path() {
+        let mut f = Builder::new("f");
+        let cond = f.constant(Constant::Bool(true));
+        let (then, otherwise, join) = (f.block(), f.block(), f.block());
+        f.terminate(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        });
+        f.switch_to(then);
+        let n = f.constant(Constant::Number(1.0));
+        f.terminate(Terminator::Jump(join));
+        f.switch_to(otherwise);
+        f.terminate(Terminator::Jump(join));
+        f.switch_to(join);
+        f.print(n);
+        f.terminate(Terminator::Return(Some(n)));
+        assert_eq!(
+            errors(vec![f.finish()]),
+            vec![
+                "in f, bb3: `print %1` may use %1 before it's defined",
+                "in f, bb3: `return %1` may use %1 before it's defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_blocks_are_not_checked_for_definitions() {
+        let mut f = Builder::new("f");
+        f.terminate(Terminator::Return(None));
+        let dead = f.block();
+        f.switch_to(dead);
+        let n = f.vreg(Ty::Number);
+        f.print(n);
+        f.terminate(Terminator::Return(None));
+        assert_eq!(errors(vec![f.finish()]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn types_must_agree() {
+        let mut f = Builder::new("f");
+        let flag = f.vreg(Ty::Bool);
+        f.push(Instruction::Const {
+            dst: flag,
+            value: Constant::Number(1.0),
+        });
+        let any = f.vreg(Ty::Value);
+        f.copy(any, flag);
+        f.terminate(Terminator::Return(Some(any)));
+        assert_eq!(
+            errors(vec![f.finish()]),
+            vec!["in f, bb0: `%0 = const 1.0` writes a number to %0: bool"]
+        );
+    }
+
+    #[test]
+    fn phis_match_predecessors_and_come_first() {
+        let mut f = Builder::new("f");
+        let n = f.constant(Constant::Number(1.0));
+        let next = f.block();
+        f.terminate(Terminator::Jump(next));
+        f.switch_to(next);
+        let (a, b) = (f.vreg(Ty::Number), f.vreg(Ty::Number));
+        f.push(Instruction::Phi {
+            dst: a,
+            args: vec![(BlockId::ENTRY, n), (next, n)],
+        });
+        f.print(a);
+        f.push(Instruction::Phi {
+            dst: b,
+            args: vec![(BlockId::ENTRY, n)],
+        });
+        f.terminate(Terminator::Return(None));
+        assert_eq!(
+            errors(vec![f.finish()]),
+            vec![
+                "in f, bb1: `%1 = phi [bb0: %0], [bb1: %0]` doesn't match the block's predecessors",
+                "in f, bb1: `%2 = phi [bb0: %0]` comes after non-phis",
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_must_match_a_function() {
+        let mut f = Builder::new("f");
+        f.param(Ty::Value);
+        f.terminate(Terminator::Return(None));
+        let mut script = Builder::new("script");
+        script.call("f", Vec::new(), 1);
+        script.call("g", Vec::new(), 2);
+        script.terminate(Terminator::Return(None));
+        let mut again = Builder::new("f");
+        again.terminate(Terminator::Return(None));
+        assert_eq!(
+            errors(vec![f.finish(), script.finish(), again.finish()]),
+            vec![
+                "in script, bb0: `%0 = call f()` passes 0 arguments to 1 parameters",
+                "in script, bb0: `%1 = call g()` calls a missing function",
+                "in f: defined more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn ssa_registers_are_written_once() {
+        let mut f = Builder::new("f");
+        let n = f.constant(Constant::Number(1.0));
+        let copy = f.vreg(Ty::Number);
+        f.copy(copy, n);
+        f.copy(copy, n);
+        f.terminate(Terminator::Return(Some(copy)));
+        let module = Module {
+            functions: vec![f.finish()],
+        };
+        assert_eq!(verify(&module), Ok(()));
+        let errors: Vec<_> = verify_ssa(&module)
+            .unwrap_err()
+            .iter()
+            .map(Error::to_string)
+            .collect();
+        assert_eq!(errors, vec!["in f, bb0: `%1 = copy %0` redefines %1"]);
+    }
+}
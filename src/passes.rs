@@ -0,0 +1,104 @@
+//! The optimization passes that can be turned on and off, and the `-O`
+//! levels choosing between them.
+use std::{fmt::Display, str::FromStr};
+
+/// An optional pass, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Pass {
+    /// Inlining small functions, on MIR.
+    Inline,
+    /// Constant folding, on MIR.
+    Fold,
+    /// Dead code elimination, on MIR.
+    Dce,
+    /// Linear scan register allocation. Without it every virtual register
+    /// gets a stack slot.
+    Regalloc,
+    /// Peephole optimization, on the generated code.
+    Peephole,
+}
+impl Pass {
+    pub(crate) const ALL: [Pass; 5] = [
+        Pass::Inline,
+        Pass::Fold,
+        Pass::Dce,
+        Pass::Regalloc,
+        Pass::Peephole,
+    ];
+    fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Fold => "fold",
+            Pass::Dce => "dce",
+            Pass::Regalloc => "regalloc",
+            Pass::Peephole => "peephole",
+        }
+    }
+}
+impl Display for Pass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl FromStr for Pass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .into_iter()
+            .find(|pass| pass.name() == s)
+            .ok_or_else(|| format!("unknown pass '{s}'"))
+    }
+}
+
+/// Which of the optional passes run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Passes {
+    enabled: [bool; Pass::ALL.len()],
+}
+impl Passes {
+    pub(crate) fn none() -> Self {
+        Self {
+            enabled: [false; Pass::ALL.len()],
+        }
+    }
+    /// The passes `-O<level>` runs: none at 0, folding, dead code
+    /// elimination and register allocation at 1, everything at 2.
+    pub(crate) fn level(level: u8) -> Option<Self> {
+        let passes: &[Pass] = match level {
+            0 => &[],
+            1 => &[Pass::Fold, Pass::Dce, Pass::Regalloc],
+            2 => &Pass::ALL,
+            _ => return None,
+        };
+        Some(passes.iter().copied().collect())
+    }
+    /// Parses a comma separated list of pass names, which may be empty.
+    pub(crate) fn list(list: &str) -> Result<Vec<Pass>, String> {
+        list.split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+    pub(crate) fn enabled(&self, pass: Pass) -> bool {
+        self.enabled[pass as usize]
+    }
+    pub(crate) fn set(&mut self, pass: Pass, enabled: bool) {
+        self.enabled[pass as usize] = enabled;
+    }
+}
+impl Default for Passes {
+    fn default() -> Self {
+        Self {
+            enabled: [true; Pass::ALL.len()],
+        }
+    }
+}
+impl FromIterator<Pass> for Passes {
+    fn from_iter<T: IntoIterator<Item = Pass>>(iter: T) -> Self {
+        let mut passes = Self::none();
+        for pass in iter {
+            passes.set(pass, true);
+        }
+        passes
+    }
+}
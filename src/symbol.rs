@@ -0,0 +1,66 @@
+//! Interned strings. Identifiers and string literals are stored once for the
+//! whole run and referred to by index, so comparing them is comparing
+//! numbers, and the same literal written twice is one string.
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{LazyLock, Mutex},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct Symbol(u32);
+impl Symbol {
+    pub(crate) const INIT: Symbol = Symbol(0);
+    pub(crate) const THIS: Symbol = Symbol(1);
+    pub(crate) const SUPER: Symbol = Symbol(2);
+    pub(crate) const CLOCK: Symbol = Symbol(3);
+    pub(crate) const EMPTY: Symbol = Symbol(4);
+
+    pub(crate) fn intern(string: &str) -> Self {
+        INTERNER.lock().unwrap().intern(string)
+    }
+    pub(crate) fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().strings[self.0 as usize]
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// The symbols with constants, in order.
+const PREDEFINED: [&str; 5] = ["init", "this", "super", "clock", ""];
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| {
+    let mut interner = Interner::default();
+    for string in PREDEFINED {
+        interner.intern(string);
+    }
+    Mutex::new(interner)
+});
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+impl Interner {
+    fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(string) {
+            return symbol;
+        }
+        // Symbols are never freed, only source text and what folding
+        // makes of it gets interned.
+        let string: &'static str = Box::leak(string.into());
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(string);
+        self.symbols.insert(string, symbol);
+        symbol
+    }
+}
@@ -0,0 +1,143 @@
+//! Lox values as the interpreters see them, behaving exactly like the
+//! NaN-boxed values of the compiled runtime, printing included.
+use crate::interpreter::{Class, Function, Instance, Native};
+use std::{fmt, rc::Rc};
+
+/// How deep calls can nest before the script is stopped.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+#[derive(Clone)]
+pub(crate) enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    /// Only the tree-walking interpreter has the rest.
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+}
+impl Value {
+    /// Only nil and false are falsey.
+    pub(crate) fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+    pub(crate) fn negate(&self, line: usize) -> Result<Value, RuntimeError> {
+        match self {
+            Self::Number(n) => Ok(Self::Number(-n)),
+            _ => Err(RuntimeError::new("Operand must be a number.", line)),
+        }
+    }
+    pub(crate) fn add(&self, other: &Value, line: usize) -> Result<Value, RuntimeError> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => Ok(Self::Number(a + b)),
+            (Self::String(a), Self::String(b)) => Ok(Self::String(format!("{a}{b}").into())),
+            _ => Err(RuntimeError::new(
+                "Operands must be two numbers or two strings.",
+                line,
+            )),
+        }
+    }
+    /// Both operands as numbers, for arithmetic and comparisons.
+    pub(crate) fn numbers(&self, other: &Value, line: usize) -> Result<(f64, f64), RuntimeError> {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => Ok((*a, *b)),
+            _ => Err(RuntimeError::new("Operands must be numbers.", line)),
+        }
+    }
+}
+/// Numbers compare as floats, so NaN isn't equal to itself, strings by
+/// their contents and everything else by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            (Self::Native(a), Self::Native(b)) => Rc::ptr_eq(a, b),
+            (Self::Class(a), Self::Class(b)) => Rc::ptr_eq(a, b),
+            (Self::Instance(a), Self::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{}", Number(*n)),
+            Self::String(s) => write!(f, "{s}"),
+            Self::Function(function) => write!(f, "<fn {}>", function.name()),
+            Self::Native(_) => write!(f, "<native fn>"),
+            Self::Class(class) => write!(f, "{}", class.name()),
+            Self::Instance(instance) => write!(f, "{} instance", instance.class().name()),
+        }
+    }
+}
+
+/// Prints a number the way the runtime's `lox_number_to_string` does: up
+/// to six decimals with trailing zeros trimmed, switching to an exponent
+/// from 1e12 up and below 1e-4.
+struct Number(f64);
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = self.0;
+        if n.is_nan() {
+            return write!(f, "nan");
+        }
+        if n.is_sign_negative() {
+            write!(f, "-")?;
+        }
+        let mut a = n.abs();
+        if a.is_infinite() {
+            return write!(f, "inf");
+        }
+        let mut exponent = 0;
+        if a >= 1e12 {
+            while a >= 10.0 {
+                a /= 10.0;
+                exponent += 1;
+            }
+        } else if a < 1e-4 && a != 0.0 {
+            while a < 1.0 {
+                a *= 10.0;
+                exponent -= 1;
+            }
+        }
+        let scaled = (a * 1e6).round_ties_even() as i64;
+        write!(f, "{}", scaled / 1_000_000)?;
+        let fraction = scaled % 1_000_000;
+        if fraction != 0 {
+            let digits = format!("{fraction:06}");
+            write!(f, ".{}", digits.trim_end_matches('0'))?;
+        }
+        if exponent != 0 {
+            write!(f, "e{exponent}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error that stops a running script, exit code 70.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RuntimeError {
+    message: String,
+    line: usize,
+}
+impl RuntimeError {
+    pub(crate) fn new<T: ToString>(message: T, line: usize) -> Self {
+        Self {
+            message: message.to_string(),
+            line,
+        }
+    }
+}
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n[line {}]", self.message, self.line)
+    }
+}
@@ -0,0 +1,155 @@
+//! The System V AMD64 calling convention, and the entry points that start a
+//! compiled script.
+use super::{Directive, OpCode, Reg, Regester, Syscall};
+use crate::ir::{Blob, Section};
+
+/// Integer arguments, in order.
+pub(crate) const ARGUMENTS: [Reg; 6] = [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
+/// Registers a function has to restore before returning.
+pub(crate) const CALLEE_SAVED: [Reg; 6] =
+    [Reg::Rbx, Reg::Rbp, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+/// Registers any call may clobber.
+pub(crate) const CALLER_SAVED: [Reg; 9] = [
+    Reg::Rax,
+    Reg::Rcx,
+    Reg::Rdx,
+    Reg::Rsi,
+    Reg::Rdi,
+    Reg::R8,
+    Reg::R9,
+    Reg::R10,
+    Reg::R11,
+];
+
+/// The function the compiled script ends up in, `int lox_main(void)`.
+///
+/// It returns the process exit code, so C programs can run a script by
+/// linking against it.
+pub(crate) const SCRIPT: &str = "lox_main";
+
+/// A function's stack frame: `rbp` is pushed and pointed at the saved
+/// `rbp`, then the callee saved registers in `saved` are pushed and `locals`
+/// bytes reserved below them, padded so `rsp` stays 16 byte aligned.
+pub(crate) struct Frame {
+    saved: Vec<Reg>,
+    locals: u32,
+}
+impl Frame {
+    pub(crate) fn new(saved: &[Reg], locals: u32) -> Self {
+        let saved = saved
+            .iter()
+            .copied()
+            .filter(|r| *r != Reg::Rbp)
+            .collect::<Vec<_>>();
+        // The return address and rbp make 16 bytes, so everything pushed
+        // after them has to add up to a multiple of 16 too.
+        let used = saved.len() as u32 * 8 + locals;
+        let locals = locals + (16 - used % 16) % 16;
+        Self { saved, locals }
+    }
+    pub(crate) fn prologue(&self) -> Blob {
+        let mut code = vec![
+            OpCode::Push(Reg::Rbp.into()),
+            OpCode::Mov(Reg::Rbp.into(), Reg::Rsp.into()),
+        ];
+        for reg in &self.saved {
+            code.push(OpCode::Push((*reg).into()));
+        }
+        if self.locals > 0 {
+            code.push(OpCode::Sub(Reg::Rsp.into(), self.locals.into()));
+        }
+        Blob::from(code)
+    }
+    /// The `index`th 8 byte local, counting down from the saved registers.
+    pub(crate) fn local(&self, index: u32) -> Regester {
+        let offset = 8 * (self.saved.len() as i32 + 1 + index as i32);
+        Directive::qword(Reg::Rbp, true, Some(-offset)).into()
+    }
+    /// The `index`th argument of a compiled Lox function. They're all
+    /// passed on the stack, the first pushed last.
+    pub(crate) fn argument(index: u32) -> Regester {
+        // Above the saved rbp and the return address.
+        let offset = 16 + 8 * index as i32;
+        Directive::qword(Reg::Rbp, true, Some(offset)).into()
+    }
+    /// Restores the caller's registers and returns.
+    pub(crate) fn epilogue(&self) -> Blob {
+        let mut code = Vec::new();
+        if self.locals > 0 {
+            code.push(OpCode::Add(Reg::Rsp.into(), self.locals.into()));
+        }
+        for reg in self.saved.iter().rev() {
+            code.push(OpCode::Pop((*reg).into()));
+        }
+        code.push(OpCode::Pop(Reg::Rbp.into()));
+        code.push(OpCode::Ret);
+        Blob::from(code)
+    }
+}
+
+/// Calls the C function `name` from code that doesn't track the stack
+/// alignment, with the arguments already in [`ARGUMENTS`].
+///
+/// `rsp` is aligned to 16 bytes for the call and restored afterwards, `rbx`
+/// is used to remember it. Variadic functions aren't supported, they'd need
+/// the number of `xmm` arguments in `al`.
+pub(crate) fn call_c(name: &str) -> Blob {
+    Blob::from(vec![
+        OpCode::Push(Reg::Rbx.into()),
+        OpCode::Mov(Reg::Rbx.into(), Reg::Rsp.into()),
+        OpCode::And(Reg::Rsp.into(), (-16i64 as u64).into()),
+        OpCode::Call(name.to_string()),
+        OpCode::Mov(Reg::Rsp.into(), Reg::Rbx.into()),
+        OpCode::Pop(Reg::Rbx.into()),
+    ])
+}
+
+/// What the compiled program runs on top of.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// A `_start` that talks to the kernel through syscalls, linked with ld.
+    #[default]
+    Freestanding,
+    /// Linked against libc with `cc`. Without `main` only [`SCRIPT`] is
+    /// exported, for linking into a C program.
+    Libc { main: bool },
+}
+impl Target {
+    /// The symbol execution starts at.
+    pub(crate) fn entry(&self) -> &'static str {
+        match self {
+            Self::Freestanding => "_start",
+            Self::Libc { main: true } => "main",
+            Self::Libc { main: false } => SCRIPT,
+        }
+    }
+    /// The code at [`Target::entry`], it runs [`SCRIPT`] and exits with the
+    /// code it returns.
+    pub(crate) fn entry_section(&self) -> Option<Section> {
+        match self {
+            Self::Freestanding => Some(Section::new(
+                self.entry(),
+                vec![Blob::from(vec![
+                    // Marks the outermost frame for debuggers.
+                    OpCode::Xor(Reg::Rbp.into(), Reg::Rbp.into()),
+                    OpCode::Call(SCRIPT.to_string()),
+                    OpCode::Mov(Reg::Rdi.into(), Reg::Rax.into()),
+                    OpCode::Mov(Reg::Rax.into(), Syscall::Exit.into()),
+                    OpCode::Syscall,
+                ])],
+            )),
+            Self::Libc { main: true } => {
+                let frame = Frame::new(&[], 0);
+                Some(Section::new(
+                    self.entry(),
+                    vec![
+                        frame.prologue(),
+                        Blob::from(vec![OpCode::Call(SCRIPT.to_string())]),
+                        frame.epilogue(),
+                    ],
+                ))
+            }
+            Self::Libc { main: false } => None,
+        }
+    }
+}
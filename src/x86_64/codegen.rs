@@ -0,0 +1,481 @@
+//! Generates code for a [`mir::Module`](crate::mir::Module).
+//!
+//! Virtual registers live where the [`regalloc`] put them, in hardware
+//! registers or, when they run out, stack slots. Numbers are worked on in
+//! `xmm0` and `xmm1`, everything else goes through calls to the runtime.
+use super::{
+    abi::{self, Frame, Target},
+    regalloc::{self, Allocation, Call, Location},
+    runtime::{self, FALSE, NIL, SIGN, STRING, TRUE},
+    Cond, OpCode, Reg, Regester, Xmm,
+};
+use crate::{
+    ir::{Blob, Program, Section},
+    mir::{self, BinaryOp, BlockId, Constant, Function, Instruction, Terminator, Ty, UnaryOp},
+    symbol::Symbol,
+};
+use std::collections::HashMap;
+
+/// A program running `module` on `target`, including the runtime. Without
+/// `allocate`, every virtual register is kept on the stack.
+pub(crate) fn generate(module: &mir::Module, target: Target, allocate: bool) -> Program {
+    let mut strings = Strings::default();
+    let mut text = Vec::new();
+    for function in &module.functions {
+        let allocation = if allocate {
+            regalloc::allocate(function, |instruction| calls(function, instruction))
+        } else {
+            regalloc::spill(function)
+        };
+        text.push(
+            Generator {
+                function,
+                frame: Frame::new(&allocation.saved, 8 * allocation.slots),
+                allocation,
+                symbol: symbol(&function.name),
+                strings: &mut strings,
+                labels: 0,
+            }
+            .function(),
+        );
+    }
+    let runtime = runtime::runtime(target);
+    text.extend(runtime.text);
+    let mut rodata = runtime.rodata;
+    rodata.extend(strings.objects);
+    let program = Program::new(None, text).with_rodata(Section::new(".rodata", rodata));
+    if runtime.bss.is_empty() {
+        program
+    } else {
+        program.with_bss(Section::new(".bss", runtime.bss))
+    }
+}
+
+/// The symbol a function is compiled to.
+pub(crate) fn symbol(name: &str) -> String {
+    if name == mir::SCRIPT {
+        abi::SCRIPT.to_string()
+    } else {
+        format!("lox_fn_{name}")
+    }
+}
+
+/// String constants, each stored once in .rodata.
+#[derive(Default)]
+struct Strings {
+    labels: HashMap<Symbol, String>,
+    objects: Vec<Blob>,
+}
+impl Strings {
+    fn label(&mut self, text: Symbol) -> String {
+        if let Some(label) = self.labels.get(&text) {
+            return label.clone();
+        }
+        let label = format!("lox_str_{}", self.objects.len());
+        self.objects
+            .push(runtime::string_object(&label, text.as_str().as_bytes()));
+        self.labels.insert(text, label.clone());
+        label
+    }
+}
+
+struct Generator<'a> {
+    function: &'a Function,
+    allocation: Allocation,
+    frame: Frame,
+    symbol: String,
+    strings: &'a mut Strings,
+    /// Counts the labels made up inside blocks.
+    labels: usize,
+}
+impl Generator<'_> {
+    fn function(mut self) -> Section {
+        let mut code = vec![self.frame.prologue(), self.params()];
+        for id in self.function.block_ids() {
+            let block = self.function.block(id);
+            let mut blob = vec![OpCode::Label(self.block_label(id))];
+            for instruction in &block.instructions {
+                blob.extend(self.instruction(instruction));
+            }
+            blob.extend(self.terminator(&block.terminator));
+            code.push(Blob::from(blob));
+        }
+        Section::new(&self.symbol, code)
+    }
+    /// Moves the arguments where the parameters are allocated.
+    fn params(&self) -> Blob {
+        let mut code = Vec::new();
+        for (i, param) in (0..).zip(&self.function.params) {
+            if !self.allocation.is_used(*param) {
+                continue;
+            }
+            match self.reg(*param) {
+                Some(reg) => code.push(OpCode::Mov(reg.into(), Frame::argument(i).into())),
+                None => code.extend([
+                    OpCode::Mov(Reg::Rax.into(), Frame::argument(i).into()),
+                    self.store(*param, Reg::Rax),
+                ]),
+            }
+        }
+        Blob::from(code)
+    }
+    fn block_label(&self, id: BlockId) -> String {
+        format!("{}_{id}", self.symbol)
+    }
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("{}_l{}", self.symbol, self.labels)
+    }
+    fn location(&self, vreg: mir::VReg) -> Regester {
+        match self.allocation.location(vreg) {
+            Location::Reg(reg) => reg.into(),
+            Location::Stack(slot) => self.frame.local(slot),
+        }
+    }
+    /// The hardware register `vreg` is allocated to, if it isn't spilled.
+    fn reg(&self, vreg: mir::VReg) -> Option<Reg> {
+        match self.allocation.location(vreg) {
+            Location::Reg(reg) => Some(reg),
+            Location::Stack(_) => None,
+        }
+    }
+    fn load(&self, reg: Reg, vreg: mir::VReg) -> OpCode {
+        OpCode::Mov(reg.into(), self.location(vreg).into())
+    }
+    fn store(&self, vreg: mir::VReg, reg: Reg) -> OpCode {
+        OpCode::Mov(self.location(vreg), reg.into())
+    }
+    /// Moves the bits of `vreg` into `xmm`. Clobbers rax.
+    fn load_xmm(&self, xmm: Xmm, vreg: mir::VReg) -> Vec<OpCode> {
+        match self.reg(vreg) {
+            Some(reg) => vec![OpCode::MovqToXmm(xmm, reg)],
+            None => vec![self.load(Reg::Rax, vreg), OpCode::MovqToXmm(xmm, Reg::Rax)],
+        }
+    }
+    /// Moves the bits in `xmm` into `vreg`.
+    fn store_xmm(&self, vreg: mir::VReg, xmm: Xmm) -> Vec<OpCode> {
+        match self.reg(vreg) {
+            Some(reg) => vec![OpCode::MovqFromXmm(reg, xmm)],
+            None => vec![
+                OpCode::MovqFromXmm(Reg::Rax, xmm),
+                self.store(vreg, Reg::Rax),
+            ],
+        }
+    }
+    /// Every instruction reads all its operands before writing its
+    /// destination, which may share a register with one of them.
+    fn instruction(&mut self, instruction: &Instruction) -> Vec<OpCode> {
+        let mut code = Vec::new();
+        match instruction {
+            Instruction::Const { dst, value } => match value {
+                Constant::String(text) => code.extend([
+                    OpCode::Lea(Reg::Rax, self.strings.label(*text)),
+                    OpCode::Mov(Reg::Rcx.into(), STRING.into()),
+                    OpCode::Add(Reg::Rax.into(), Reg::Rcx.into()),
+                    self.store(*dst, Reg::Rax),
+                ]),
+                _ => match self.reg(*dst) {
+                    Some(reg) => code.push(OpCode::Mov(reg.into(), boxed(value).into())),
+                    None => code.extend([
+                        OpCode::Mov(Reg::Rax.into(), boxed(value).into()),
+                        self.store(*dst, Reg::Rax),
+                    ]),
+                },
+            },
+            Instruction::Copy { dst, src } => match (self.reg(*dst), self.reg(*src)) {
+                (Some(dst), Some(src)) if dst == src => (),
+                (Some(_), _) | (_, Some(_)) => {
+                    code.push(OpCode::Mov(self.location(*dst), self.location(*src).into()))
+                }
+                (None, None) => {
+                    code.extend([self.load(Reg::Rax, *src), self.store(*dst, Reg::Rax)])
+                }
+            },
+            Instruction::Unary {
+                dst,
+                op: UnaryOp::Negate,
+                src,
+                line,
+            } => {
+                if self.function.ty(*src) != Ty::Number {
+                    code.extend([
+                        self.load(Reg::Rdi, *src),
+                        OpCode::Mov(Reg::Rsi.into(), (*line as u64).into()),
+                        OpCode::Call(runtime::CHECK_NUMBER.to_string()),
+                    ]);
+                }
+                code.extend([
+                    self.load(Reg::Rax, *src),
+                    OpCode::Mov(Reg::Rcx.into(), SIGN.into()),
+                    OpCode::Xor(Reg::Rax.into(), Reg::Rcx.into()),
+                    self.store(*dst, Reg::Rax),
+                ]);
+            }
+            Instruction::Unary {
+                dst,
+                op: UnaryOp::Not,
+                src,
+                ..
+            } => {
+                let done = self.label();
+                code.extend([
+                    self.load(Reg::Rax, *src),
+                    OpCode::Mov(Reg::Rdx.into(), TRUE.into()),
+                ]);
+                code.extend(falsey(Reg::Rax, &done));
+                code.extend([
+                    OpCode::Mov(Reg::Rdx.into(), FALSE.into()),
+                    OpCode::Label(done),
+                    self.store(*dst, Reg::Rdx),
+                ]);
+            }
+            Instruction::Unary {
+                dst,
+                op: UnaryOp::ToString,
+                src,
+                ..
+            } => code.extend([
+                self.load(Reg::Rdi, *src),
+                OpCode::Call(runtime::TO_STRING.to_string()),
+                self.store(*dst, Reg::Rax),
+            ]),
+            Instruction::Binary {
+                dst,
+                op: op @ (BinaryOp::Equal | BinaryOp::NotEqual),
+                lhs,
+                rhs,
+                ..
+            } => {
+                code.extend([
+                    self.load(Reg::Rdi, *lhs),
+                    self.load(Reg::Rsi, *rhs),
+                    OpCode::Call(runtime::EQUAL.to_string()),
+                ]);
+                if *op == BinaryOp::NotEqual {
+                    // true and false only differ in the lowest bit.
+                    code.extend([
+                        OpCode::Mov(Reg::Rcx.into(), 1u32.into()),
+                        OpCode::Xor(Reg::Rax.into(), Reg::Rcx.into()),
+                    ]);
+                }
+                code.push(self.store(*dst, Reg::Rax));
+            }
+            Instruction::Binary {
+                dst,
+                op: BinaryOp::Add,
+                lhs,
+                rhs,
+                line,
+            } if !self.numbers(*lhs, *rhs) => code.extend([
+                self.load(Reg::Rdi, *lhs),
+                self.load(Reg::Rsi, *rhs),
+                OpCode::Mov(Reg::Rdx.into(), (*line as u64).into()),
+                OpCode::Call(runtime::ADD.to_string()),
+                self.store(*dst, Reg::Rax),
+            ]),
+            Instruction::Binary {
+                dst,
+                op: BinaryOp::Modulo,
+                lhs,
+                rhs,
+                line,
+            } => {
+                code.extend([self.load(Reg::Rdi, *lhs), self.load(Reg::Rsi, *rhs)]);
+                if !self.numbers(*lhs, *rhs) {
+                    code.extend([
+                        OpCode::Mov(Reg::Rdx.into(), (*line as u64).into()),
+                        OpCode::Call(runtime::CHECK_NUMBERS.to_string()),
+                    ]);
+                }
+                code.extend([
+                    OpCode::Call(runtime::MODULO.to_string()),
+                    self.store(*dst, Reg::Rax),
+                ]);
+            }
+            Instruction::Binary {
+                dst,
+                op,
+                lhs,
+                rhs,
+                line,
+            } => {
+                if !self.numbers(*lhs, *rhs) {
+                    code.extend([
+                        self.load(Reg::Rdi, *lhs),
+                        self.load(Reg::Rsi, *rhs),
+                        OpCode::Mov(Reg::Rdx.into(), (*line as u64).into()),
+                        OpCode::Call(runtime::CHECK_NUMBERS.to_string()),
+                    ]);
+                }
+                code.extend(self.load_xmm(Xmm::Xmm0, *lhs));
+                code.extend(self.load_xmm(Xmm::Xmm1, *rhs));
+                let arithmetic = match op {
+                    BinaryOp::Add => OpCode::Addsd(Xmm::Xmm0, Xmm::Xmm1),
+                    BinaryOp::Subtract => OpCode::Subsd(Xmm::Xmm0, Xmm::Xmm1),
+                    BinaryOp::Multiply => OpCode::Mulsd(Xmm::Xmm0, Xmm::Xmm1),
+                    BinaryOp::Divide => OpCode::Divsd(Xmm::Xmm0, Xmm::Xmm1),
+                    BinaryOp::Modulo => unreachable!("the runtime finds remainders"),
+                    BinaryOp::Less => return self.compare(code, *dst, true, Cond::A),
+                    BinaryOp::LessEqual => return self.compare(code, *dst, true, Cond::Ae),
+                    BinaryOp::Greater => return self.compare(code, *dst, false, Cond::A),
+                    BinaryOp::GreaterEqual => return self.compare(code, *dst, false, Cond::Ae),
+                    BinaryOp::Equal | BinaryOp::NotEqual => unreachable!(),
+                };
+                code.push(arithmetic);
+                code.extend(self.store_xmm(*dst, Xmm::Xmm0));
+            }
+            Instruction::Print { src } => code.extend([
+                self.load(Reg::Rdi, *src),
+                OpCode::Call(runtime::PRINT.to_string()),
+            ]),
+            Instruction::Call {
+                dst,
+                callee,
+                args,
+                line,
+            } => {
+                code.extend([
+                    OpCode::Mov(Reg::Rdi.into(), (*line as u64).into()),
+                    OpCode::Call(runtime::ENTER.to_string()),
+                ]);
+                // Keeps rsp 16 byte aligned for the call.
+                let pushed = args.len().next_multiple_of(2) as u32;
+                if pushed as usize > args.len() {
+                    code.push(OpCode::Sub(Reg::Rsp.into(), 8u32.into()));
+                }
+                for arg in args.iter().rev() {
+                    code.push(OpCode::Push(self.location(*arg)));
+                }
+                code.push(OpCode::Call(symbol(callee)));
+                if pushed > 0 {
+                    code.push(OpCode::Add(Reg::Rsp.into(), (8 * pushed).into()));
+                }
+                code.extend([
+                    self.store(*dst, Reg::Rax),
+                    OpCode::Call(runtime::LEAVE.to_string()),
+                ]);
+            }
+            Instruction::Phi { .. } => unreachable!("phis are lowered out of SSA first"),
+        }
+        code
+    }
+    fn numbers(&self, lhs: mir::VReg, rhs: mir::VReg) -> bool {
+        self.function.ty(lhs) == Ty::Number && self.function.ty(rhs) == Ty::Number
+    }
+    /// Appends to `code`, which left the operands in xmm0 and xmm1, writing
+    /// whether `cond` holds after comparing them, in reverse order if
+    /// `swap`, to `dst`. Comparisons with NaN are false.
+    fn compare(
+        &mut self,
+        mut code: Vec<OpCode>,
+        dst: mir::VReg,
+        swap: bool,
+        cond: Cond,
+    ) -> Vec<OpCode> {
+        let done = self.label();
+        let (a, b) = if swap {
+            (Xmm::Xmm1, Xmm::Xmm0)
+        } else {
+            (Xmm::Xmm0, Xmm::Xmm1)
+        };
+        code.extend([
+            OpCode::Ucomisd(a, b),
+            OpCode::Mov(Reg::Rax.into(), TRUE.into()),
+            OpCode::Jcc(cond, done.clone()),
+            OpCode::Mov(Reg::Rax.into(), FALSE.into()),
+            OpCode::Label(done),
+            self.store(dst, Reg::Rax),
+        ]);
+        code
+    }
+    fn terminator(&mut self, terminator: &Terminator) -> Vec<OpCode> {
+        match terminator {
+            Terminator::Jump(target) => vec![OpCode::Jmp(self.block_label(*target))],
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let mut code = Vec::new();
+                let reg = self.reg(*cond).unwrap_or_else(|| {
+                    code.push(self.load(Reg::Rax, *cond));
+                    Reg::Rax
+                });
+                code.extend(falsey(reg, &self.block_label(*otherwise)));
+                code.push(OpCode::Jmp(self.block_label(*then)));
+                code
+            }
+            Terminator::Return(value) => {
+                let mut code = vec![match value {
+                    Some(value) => self.load(Reg::Rax, *value),
+                    // The script's exit code.
+                    None if self.function.name == mir::SCRIPT => {
+                        OpCode::Xor(Reg::Rax.into(), Reg::Rax.into())
+                    }
+                    None => OpCode::Mov(Reg::Rax.into(), NIL.into()),
+                }];
+                code.extend(self.frame.epilogue());
+                code
+            }
+            Terminator::Unreachable => vec![OpCode::Ud2],
+        }
+    }
+}
+
+/// How the code for `instruction` calls into the runtime, if it does.
+fn calls(function: &Function, instruction: &Instruction) -> Option<Call> {
+    let number = |vreg: mir::VReg| function.ty(vreg) == Ty::Number;
+    match instruction {
+        Instruction::Print { .. } | Instruction::Call { .. } => Some(Call::Last),
+        Instruction::Unary {
+            op: UnaryOp::Negate,
+            src,
+            ..
+        } if !number(*src) => Some(Call::Check),
+        Instruction::Unary {
+            op: UnaryOp::ToString,
+            ..
+        } => Some(Call::Last),
+        Instruction::Binary {
+            op: BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::Modulo,
+            ..
+        } => Some(Call::Last),
+        Instruction::Binary {
+            op: BinaryOp::Add,
+            lhs,
+            rhs,
+            ..
+        } if !(number(*lhs) && number(*rhs)) => Some(Call::Last),
+        Instruction::Binary { lhs, rhs, .. } if !(number(*lhs) && number(*rhs)) => {
+            Some(Call::Check)
+        }
+        Instruction::Const { .. }
+        | Instruction::Copy { .. }
+        | Instruction::Unary { .. }
+        | Instruction::Binary { .. }
+        | Instruction::Phi { .. } => None,
+    }
+}
+
+/// Jumps to `label` if the value in `reg` is nil or false. Clobbers rcx.
+fn falsey(reg: Reg, label: &str) -> Vec<OpCode> {
+    vec![
+        OpCode::Mov(Reg::Rcx.into(), NIL.into()),
+        OpCode::Cmp(reg.into(), Reg::Rcx.into()),
+        OpCode::Jcc(Cond::E, label.to_string()),
+        OpCode::Mov(Reg::Rcx.into(), FALSE.into()),
+        OpCode::Cmp(reg.into(), Reg::Rcx.into()),
+        OpCode::Jcc(Cond::E, label.to_string()),
+    ]
+}
+
+/// The NaN-boxed bits of a constant that isn't a string.
+fn boxed(constant: &Constant) -> u64 {
+    match constant {
+        Constant::Nil => NIL,
+        Constant::Bool(true) => TRUE,
+        Constant::Bool(false) => FALSE,
+        Constant::Number(n) => n.to_bits(),
+        Constant::String(_) => unreachable!("strings live in .rodata"),
+    }
+}
@@ -0,0 +1,900 @@
+//! Turns [`OpCode`]s into x86_64 machine code without an external assembler.
+use super::{Cond, Directive, OpCode, Reg, Regester, Value, Xmm};
+use crate::ir::Section;
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Error {
+    instruction: String,
+    message: String,
+}
+impl Error {
+    fn new<T: ToString>(message: T, instruction: &OpCode) -> Self {
+        Self {
+            message: message.to_string(),
+            instruction: instruction.to_string(),
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot encode `{}`: {}", self.instruction, self.message)
+    }
+}
+
+/// A named position in the encoded code.
+pub(crate) struct Symbol {
+    pub(crate) name: String,
+    pub(crate) offset: usize,
+}
+
+/// What a `rel32` field is used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reference {
+    /// The target of a `call` or jump, which may go through the PLT.
+    Branch,
+    /// An address loaded with `lea`.
+    Data,
+}
+
+/// A `rel32` field referring to a label that isn't defined in the code.
+///
+/// The linker has to patch `offset` with `symbol + addend - offset`.
+pub(crate) struct Relocation {
+    pub(crate) offset: usize,
+    pub(crate) symbol: String,
+    pub(crate) addend: i64,
+    pub(crate) reference: Reference,
+}
+
+/// The output of the [`Encoder`].
+pub(crate) struct Encoded {
+    pub(crate) code: Vec<u8>,
+    pub(crate) symbols: Vec<Symbol>,
+    pub(crate) relocations: Vec<Relocation>,
+}
+
+/// Operand size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Width {
+    Byte,
+    Word,
+    DWord,
+    QWord,
+}
+
+/// A general purpose register as seen by the instruction encoding.
+#[derive(Clone, Copy)]
+struct Register {
+    /// Register number, 8-15 need a REX prefix.
+    number: u8,
+    width: Width,
+    /// `ah`, `bh`, `ch` and `dh` can't be used together with a REX prefix.
+    high_byte: bool,
+}
+impl From<Reg> for Register {
+    fn from(value: Reg) -> Self {
+        use Width::*;
+        let (number, width, high_byte) = match value {
+            Reg::Rax => (0, QWord, false),
+            Reg::Eax => (0, DWord, false),
+            Reg::Ax => (0, Word, false),
+            Reg::Al => (0, Byte, false),
+            Reg::Ah => (4, Byte, true),
+            Reg::Rcx => (1, QWord, false),
+            Reg::Ecx => (1, DWord, false),
+            Reg::Cx => (1, Word, false),
+            Reg::Cl => (1, Byte, false),
+            Reg::Ch => (5, Byte, true),
+            Reg::Rdx => (2, QWord, false),
+            Reg::Edx => (2, DWord, false),
+            Reg::Dx => (2, Word, false),
+            Reg::Dl => (2, Byte, false),
+            Reg::Dh => (6, Byte, true),
+            Reg::Rbx => (3, QWord, false),
+            Reg::Ebx => (3, DWord, false),
+            Reg::Bx => (3, Word, false),
+            Reg::Bl => (3, Byte, false),
+            Reg::Bh => (7, Byte, true),
+            Reg::Rsp => (4, QWord, false),
+            Reg::Rbp => (5, QWord, false),
+            Reg::Rsi => (6, QWord, false),
+            Reg::Rdi => (7, QWord, false),
+            Reg::R8 => (8, QWord, false),
+            Reg::R8d => (8, DWord, false),
+            Reg::R8w => (8, Word, false),
+            Reg::R8b => (8, Byte, false),
+            Reg::R9 => (9, QWord, false),
+            Reg::R9d => (9, DWord, false),
+            Reg::R9w => (9, Word, false),
+            Reg::R9b => (9, Byte, false),
+            Reg::R10 => (10, QWord, false),
+            Reg::R10d => (10, DWord, false),
+            Reg::R10w => (10, Word, false),
+            Reg::R10b => (10, Byte, false),
+            Reg::R11 => (11, QWord, false),
+            Reg::R11d => (11, DWord, false),
+            Reg::R11w => (11, Word, false),
+            Reg::R11b => (11, Byte, false),
+            Reg::R12 => (12, QWord, false),
+            Reg::R12d => (12, DWord, false),
+            Reg::R12w => (12, Word, false),
+            Reg::R12b => (12, Byte, false),
+            Reg::R13 => (13, QWord, false),
+            Reg::R13d => (13, DWord, false),
+            Reg::R13w => (13, Word, false),
+            Reg::R13b => (13, Byte, false),
+            Reg::R14 => (14, QWord, false),
+            Reg::R14d => (14, DWord, false),
+            Reg::R14w => (14, Word, false),
+            Reg::R14b => (14, Byte, false),
+            Reg::R15 => (15, QWord, false),
+            Reg::R15d => (15, DWord, false),
+            Reg::R15w => (15, Word, false),
+            Reg::R15b => (15, Byte, false),
+        };
+        Self {
+            number,
+            width,
+            high_byte,
+        }
+    }
+}
+
+/// A `[base + disp]` memory operand.
+#[derive(Clone, Copy)]
+struct Memory {
+    base: u8,
+    disp: i32,
+    /// `None` when the operand had no size directive.
+    width: Option<Width>,
+}
+
+/// Anything that can go in the ModRM `r/m` field.
+#[derive(Clone, Copy)]
+enum Rm {
+    Register(Register),
+    Memory(Memory),
+}
+impl Rm {
+    fn width(&self) -> Option<Width> {
+        match self {
+            Self::Register(r) => Some(r.width),
+            Self::Memory(m) => m.width,
+        }
+    }
+}
+
+/// A branch whose `rel32` still has to be filled in.
+struct Fixup {
+    /// Where the `rel32` field starts.
+    offset: usize,
+    label: String,
+    reference: Reference,
+}
+
+pub(crate) struct Encoder {
+    code: Vec<u8>,
+    labels: HashMap<String, usize>,
+    symbols: Vec<Symbol>,
+    fixups: Vec<Fixup>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            labels: HashMap::new(),
+            symbols: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+    /// Encodes a section, defining its name as a label at the current position.
+    pub(crate) fn section(&mut self, section: &Section) -> Result<(), Error> {
+        self.instruction(&OpCode::Label(section.name().to_string()))?;
+        self.body(section)
+    }
+    /// Encodes the contents of a section without defining its name, the way
+    /// data sections are laid out.
+    pub(crate) fn body(&mut self, section: &Section) -> Result<(), Error> {
+        for blob in section {
+            for instruction in blob {
+                self.instruction(instruction)?;
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn instruction(&mut self, instruction: &OpCode) -> Result<(), Error> {
+        let err = |message: &str| Error::new(message, instruction);
+        match instruction {
+            OpCode::Label(name) => {
+                if self.labels.insert(name.clone(), self.code.len()).is_some() {
+                    return Err(err("label defined twice"));
+                }
+                self.symbols.push(Symbol {
+                    name: name.clone(),
+                    offset: self.code.len(),
+                });
+                Ok(())
+            }
+            OpCode::Db(bytes) => {
+                self.code.extend(bytes);
+                Ok(())
+            }
+            OpCode::Dq(n) => {
+                self.code.extend(n.to_le_bytes());
+                Ok(())
+            }
+            OpCode::Resb(n) => {
+                self.code.resize(self.code.len() + n, 0);
+                Ok(())
+            }
+            OpCode::Mov(dst, src) => self.mov(rm(dst), value(src)).map_err(err),
+            OpCode::Lea(dst, label) => {
+                let dst = Register::from(*dst);
+                if dst.width != Width::QWord {
+                    return Err(err("lea needs a 64-bit destination"));
+                }
+                self.code
+                    .push(if dst.number & 8 != 0 { 0x4C } else { 0x48 });
+                // mod = 00, r/m = 101 is [rip + disp32].
+                self.code.extend([0x8D, ((dst.number & 7) << 3) | 0x05]);
+                self.rel32(label, Reference::Data);
+                Ok(())
+            }
+            OpCode::LeaOffset(dst, base, offset) => {
+                let (dst, base) = (Register::from(*dst), Register::from(*base));
+                if dst.width != Width::QWord || base.width != Width::QWord {
+                    return Err(err("lea needs 64-bit registers"));
+                }
+                let memory = Memory {
+                    base: base.number,
+                    disp: *offset,
+                    width: None,
+                };
+                self.modrm(&[0x8D], Width::QWord, dst.number, Rm::Memory(memory))
+                    .map_err(err)
+            }
+            OpCode::Add(dst, src) => self.alu(0x00, 0, rm(dst), value(src)).map_err(err),
+            OpCode::Sub(dst, src) => self.alu(0x28, 5, rm(dst), value(src)).map_err(err),
+            OpCode::And(dst, src) => self.alu(0x20, 4, rm(dst), value(src)).map_err(err),
+            OpCode::Cmp(dst, src) => self.alu(0x38, 7, rm(dst), value(src)).map_err(err),
+            OpCode::Xor(dst, src) => self
+                .alu(0x30, 6, rm(dst), rm(src).map(Operand::Rm))
+                .map_err(err),
+            OpCode::Div(divisor) => {
+                let divisor = Register::from(*divisor);
+                let opcode = if divisor.width == Width::Byte {
+                    0xF6
+                } else {
+                    0xF7
+                };
+                self.modrm(&[opcode], divisor.width, 6, Rm::Register(divisor))
+                    .map_err(err)
+            }
+            OpCode::Push(src) => self.push_pop(0x50, 0xFF, 6, rm(src)).map_err(err),
+            OpCode::Pop(dst) => self.push_pop(0x58, 0x8F, 0, rm(dst)).map_err(err),
+            OpCode::Jmp(label) => {
+                self.code.push(0xE9);
+                self.rel32(label, Reference::Branch);
+                Ok(())
+            }
+            OpCode::Jcc(cond, label) => {
+                self.code.extend([0x0F, 0x80 | cond_code(*cond)]);
+                self.rel32(label, Reference::Branch);
+                Ok(())
+            }
+            OpCode::Call(label) => {
+                self.code.push(0xE8);
+                self.rel32(label, Reference::Branch);
+                Ok(())
+            }
+            OpCode::Ret => {
+                self.code.push(0xC3);
+                Ok(())
+            }
+            OpCode::Syscall => {
+                self.code.extend([0x0F, 0x05]);
+                Ok(())
+            }
+            OpCode::Ud2 => {
+                self.code.extend([0x0F, 0x0B]);
+                Ok(())
+            }
+            OpCode::MovqToXmm(dst, src) => self.sse(0x66, 0x6E, dst.number(), *src).map_err(err),
+            OpCode::MovqFromXmm(dst, src) => self.sse(0x66, 0x7E, src.number(), *dst).map_err(err),
+            OpCode::Addsd(dst, src) => self.sse_xmm(0xF2, 0x58, *dst, *src),
+            OpCode::Mulsd(dst, src) => self.sse_xmm(0xF2, 0x59, *dst, *src),
+            OpCode::Subsd(dst, src) => self.sse_xmm(0xF2, 0x5C, *dst, *src),
+            OpCode::Divsd(dst, src) => self.sse_xmm(0xF2, 0x5E, *dst, *src),
+            OpCode::Ucomisd(a, b) => self.sse_xmm(0x66, 0x2E, *a, *b),
+            OpCode::Cvtsd2si(dst, src) => {
+                let dst = Register::from(*dst);
+                if dst.width != Width::QWord {
+                    return Err(err("cvtsd2si needs a 64-bit destination"));
+                }
+                self.sse_prefixed(0xF2, 0x2D, true, dst.number, src.number());
+                Ok(())
+            }
+            OpCode::Cvtsi2sd(dst, src) => self.sse(0xF2, 0x2A, dst.number(), *src).map_err(err),
+        }
+    }
+    /// Patches every branch to a label defined in the code, the rest are
+    /// returned as relocations against external symbols.
+    pub(crate) fn finish(mut self) -> Encoded {
+        let mut relocations = Vec::new();
+        for fixup in self.fixups.drain(..) {
+            match self.labels.get(&fixup.label) {
+                Some(&target) => {
+                    let rel = target as i64 - (fixup.offset as i64 + 4);
+                    self.code[fixup.offset..fixup.offset + 4]
+                        .copy_from_slice(&(rel as i32).to_le_bytes());
+                }
+                None => relocations.push(Relocation {
+                    offset: fixup.offset,
+                    symbol: fixup.label,
+                    addend: -4,
+                    reference: fixup.reference,
+                }),
+            }
+        }
+        Encoded {
+            code: self.code,
+            symbols: self.symbols,
+            relocations,
+        }
+    }
+
+    fn rel32(&mut self, label: &str, reference: Reference) {
+        self.fixups.push(Fixup {
+            offset: self.code.len(),
+            label: label.to_string(),
+            reference,
+        });
+        self.code.extend([0; 4]);
+    }
+    /// Emits the prefixes, `opcode` and ModRM (plus SIB and displacement) for
+    /// an instruction with `reg` in the ModRM `reg` field.
+    fn modrm(&mut self, opcode: &[u8], width: Width, reg: u8, rm: Rm) -> Result<(), &'static str> {
+        self.modrm_with(opcode, width, reg, false, rm)
+    }
+    fn modrm_with(
+        &mut self,
+        opcode: &[u8],
+        width: Width,
+        reg: u8,
+        reg_high_byte: bool,
+        rm: Rm,
+    ) -> Result<(), &'static str> {
+        let mut rex = 0;
+        if width == Width::QWord {
+            rex |= 0x08;
+        }
+        if reg & 8 != 0 {
+            rex |= 0x04;
+        }
+        let (base, high_byte) = match rm {
+            Rm::Register(r) => (r.number, r.high_byte || reg_high_byte),
+            Rm::Memory(m) => (m.base, reg_high_byte),
+        };
+        if base & 8 != 0 {
+            rex |= 0x01;
+        }
+        if high_byte && rex != 0 {
+            return Err("high byte registers can't be used with a REX prefix");
+        }
+        if width == Width::Word {
+            self.code.push(0x66);
+        }
+        if rex != 0 {
+            self.code.push(0x40 | rex);
+        }
+        self.code.extend(opcode);
+        let reg = (reg & 7) << 3;
+        match rm {
+            Rm::Register(r) => self.code.push(0xC0 | reg | (r.number & 7)),
+            Rm::Memory(m) => {
+                let base = m.base & 7;
+                // rbp and r13 as a base always need a displacement, mod = 00
+                // means rip relative for them.
+                if m.disp == 0 && base != 5 {
+                    self.code.push(reg | base);
+                } else if i8::try_from(m.disp).is_ok() {
+                    self.code.push(0x40 | reg | base);
+                } else {
+                    self.code.push(0x80 | reg | base);
+                }
+                // rsp and r12 as a base can only be expressed through a SIB byte.
+                if base == 4 {
+                    self.code.push(0x24);
+                }
+                if m.disp != 0 || base == 5 {
+                    match i8::try_from(m.disp) {
+                        Ok(disp) => self.code.push(disp as u8),
+                        Err(_) => self.code.extend(m.disp.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Emits `value` as an immediate of `width` bytes; QWORD immediates are
+    /// sign extended from 32 bits by the CPU.
+    fn immediate(&mut self, width: Width, value: u64) -> Result<(), &'static str> {
+        let value = sign_extend(width, value).ok_or("immediate too large")?;
+        match width {
+            Width::Byte => self.code.push(value as u8),
+            Width::Word => self.code.extend((value as u16).to_le_bytes()),
+            Width::DWord => self.code.extend((value as u32).to_le_bytes()),
+            Width::QWord => self.code.extend(
+                i32::try_from(value)
+                    .map_err(|_| "immediate doesn't fit in a sign extended 32-bit value")?
+                    .to_le_bytes(),
+            ),
+        }
+        Ok(())
+    }
+    fn mov(
+        &mut self,
+        dst: Result<Rm, &'static str>,
+        src: Result<Operand, &'static str>,
+    ) -> Result<(), &'static str> {
+        match (dst?, src?) {
+            (Rm::Register(d), Operand::Immediate(n)) => {
+                let rex = if d.number & 8 != 0 { 0x41 } else { 0 };
+                match d.width {
+                    Width::Byte => {
+                        if rex != 0 {
+                            self.code.push(rex);
+                        }
+                        self.code.push(0xB0 | (d.number & 7));
+                        self.immediate(Width::Byte, n)
+                    }
+                    Width::Word => {
+                        self.code.push(0x66);
+                        if rex != 0 {
+                            self.code.push(rex);
+                        }
+                        self.code.push(0xB8 | (d.number & 7));
+                        self.immediate(Width::Word, n)
+                    }
+                    // Writing the 32-bit register zero extends into the full
+                    // register, so `mov r64, imm32` doesn't need REX.W.
+                    Width::DWord => {
+                        if rex != 0 {
+                            self.code.push(rex);
+                        }
+                        self.code.push(0xB8 | (d.number & 7));
+                        self.immediate(Width::DWord, n)
+                    }
+                    Width::QWord if n <= u32::MAX as u64 => {
+                        if rex != 0 {
+                            self.code.push(rex);
+                        }
+                        self.code.push(0xB8 | (d.number & 7));
+                        self.immediate(Width::DWord, n)
+                    }
+                    Width::QWord if i32::try_from(n as i64).is_ok() => {
+                        self.modrm(&[0xC7], Width::QWord, 0, Rm::Register(d))?;
+                        self.immediate(Width::QWord, n)
+                    }
+                    Width::QWord => {
+                        self.code.push(0x48 | (rex & 1));
+                        self.code.push(0xB8 | (d.number & 7));
+                        self.code.extend(n.to_le_bytes());
+                        Ok(())
+                    }
+                }
+            }
+            (Rm::Memory(d), Operand::Immediate(n)) => {
+                let width = d.width.ok_or("operation size not specified")?;
+                let opcode = if width == Width::Byte { 0xC6 } else { 0xC7 };
+                self.modrm(&[opcode], width, 0, Rm::Memory(d))?;
+                self.immediate(width, n)
+            }
+            (dst, Operand::Rm(Rm::Register(s))) => {
+                same_width(dst.width(), s.width)?;
+                let opcode = if s.width == Width::Byte { 0x88 } else { 0x89 };
+                self.modrm_with(&[opcode], s.width, s.number, s.high_byte, dst)
+            }
+            (Rm::Register(d), Operand::Rm(src)) => {
+                same_width(src.width(), d.width)?;
+                let opcode = if d.width == Width::Byte { 0x8A } else { 0x8B };
+                self.modrm_with(&[opcode], d.width, d.number, d.high_byte, src)
+            }
+            (Rm::Memory(_), Operand::Rm(Rm::Memory(_))) => Err("too many memory operands"),
+        }
+    }
+    /// `add`, `sub`, `cmp` and friends, `base` is the `r/m8, r8` opcode and
+    /// `digit` the ModRM extension used by the immediate forms.
+    fn alu(
+        &mut self,
+        base: u8,
+        digit: u8,
+        dst: Result<Rm, &'static str>,
+        src: Result<Operand, &'static str>,
+    ) -> Result<(), &'static str> {
+        let dst = dst?;
+        match src? {
+            Operand::Immediate(n) => {
+                let width = dst.width().ok_or("operation size not specified")?;
+                let short = sign_extend(width, n).is_some_and(|n| i8::try_from(n).is_ok());
+                if width == Width::Byte {
+                    self.modrm(&[0x80], width, digit, dst)?;
+                    self.immediate(width, n)
+                } else if short {
+                    self.modrm(&[0x83], width, digit, dst)?;
+                    self.code.push(n as u8);
+                    Ok(())
+                } else {
+                    self.modrm(&[0x81], width, digit, dst)?;
+                    self.immediate(width, n)
+                }
+            }
+            Operand::Rm(Rm::Register(s)) => self.alu_rm_reg(base, dst, s),
+            Operand::Rm(src) => self.alu_reg_rm(base, dst, src),
+        }
+    }
+    fn alu_rm_reg(&mut self, base: u8, dst: Rm, src: Register) -> Result<(), &'static str> {
+        same_width(dst.width(), src.width)?;
+        let opcode = if src.width == Width::Byte {
+            base
+        } else {
+            base + 1
+        };
+        self.modrm_with(&[opcode], src.width, src.number, src.high_byte, dst)
+    }
+    fn alu_reg_rm(&mut self, base: u8, dst: Rm, src: Rm) -> Result<(), &'static str> {
+        let Rm::Register(dst) = dst else {
+            return Err("too many memory operands");
+        };
+        same_width(src.width(), dst.width)?;
+        let opcode = if dst.width == Width::Byte {
+            base + 2
+        } else {
+            base + 3
+        };
+        self.modrm_with(&[opcode], dst.width, dst.number, dst.high_byte, src)
+    }
+    /// An SSE instruction between `xmm` (in the ModRM `reg` field) and a
+    /// 64-bit general purpose register.
+    fn sse(&mut self, prefix: u8, opcode: u8, xmm: u8, gpr: Reg) -> Result<(), &'static str> {
+        let gpr = Register::from(gpr);
+        if gpr.width != Width::QWord {
+            return Err("SSE moves and conversions need a 64-bit register");
+        }
+        self.sse_prefixed(prefix, opcode, true, xmm, gpr.number);
+        Ok(())
+    }
+    /// An SSE instruction between two `xmm` registers.
+    fn sse_xmm(&mut self, prefix: u8, opcode: u8, reg: Xmm, rm: Xmm) -> Result<(), Error> {
+        self.sse_prefixed(prefix, opcode, false, reg.number(), rm.number());
+        Ok(())
+    }
+    /// The mandatory prefix has to come before REX.
+    fn sse_prefixed(&mut self, prefix: u8, opcode: u8, wide: bool, reg: u8, rm: u8) {
+        self.code.push(prefix);
+        let rex = (u8::from(wide) << 3) | ((reg & 8) >> 1) | ((rm & 8) >> 3);
+        if rex != 0 {
+            self.code.push(0x40 | rex);
+        }
+        self.code
+            .extend([0x0F, opcode, 0xC0 | ((reg & 7) << 3) | (rm & 7)]);
+    }
+    /// `short` is the `+r` opcode for registers, `long` with `digit` the
+    /// memory form.
+    fn push_pop(
+        &mut self,
+        short: u8,
+        long: u8,
+        digit: u8,
+        operand: Result<Rm, &'static str>,
+    ) -> Result<(), &'static str> {
+        match operand? {
+            Rm::Register(r) => {
+                match r.width {
+                    Width::QWord => (),
+                    Width::Word => self.code.push(0x66),
+                    _ => return Err("only 16 and 64-bit registers can be pushed or popped"),
+                }
+                if r.number & 8 != 0 {
+                    self.code.push(0x41);
+                }
+                self.code.push(short | (r.number & 7));
+                Ok(())
+            }
+            Rm::Memory(m) => match m.width {
+                // push and pop default to 64-bit operands, no REX.W needed.
+                None | Some(Width::QWord) => {
+                    self.modrm(&[long], Width::DWord, digit, Rm::Memory(m))
+                }
+                _ => Err("only 64-bit memory can be pushed or popped"),
+            },
+        }
+    }
+}
+
+enum Operand {
+    Rm(Rm),
+    Immediate(u64),
+}
+fn value(value: &Value) -> Result<Operand, &'static str> {
+    match value {
+        Value::Number(n) => Ok(Operand::Immediate(*n)),
+        Value::Regester(r) => rm(r).map(Operand::Rm),
+    }
+}
+fn rm(operand: &Regester) -> Result<Rm, &'static str> {
+    match operand {
+        Regester::Regester(r) => Ok(Rm::Register((*r).into())),
+        Regester::Deref(r) => memory(*r, true, None, None),
+        Regester::Directive(d) => match d {
+            Directive::Byte {
+                regester,
+                deref,
+                offset,
+            } => memory(*regester, *deref, *offset, Some(Width::Byte)),
+            Directive::QWord {
+                regester,
+                deref,
+                offset,
+            } => memory(*regester, *deref, *offset, Some(Width::QWord)),
+        },
+    }
+}
+fn memory(
+    base: Reg,
+    deref: bool,
+    offset: Option<i32>,
+    width: Option<Width>,
+) -> Result<Rm, &'static str> {
+    let base = Register::from(base);
+    if !deref {
+        if offset.is_some() {
+            return Err("an offset needs a memory operand");
+        }
+        same_width(width, base.width)?;
+        return Ok(Rm::Register(base));
+    }
+    if base.width != Width::QWord {
+        return Err("memory operands need a 64-bit base register");
+    }
+    let disp = offset.unwrap_or(0);
+    Ok(Rm::Memory(Memory {
+        base: base.number,
+        disp,
+        width,
+    }))
+}
+/// `value` as a signed number of `width`, accepting both the signed and
+/// the unsigned reading of the bits like assemblers do.
+fn sign_extend(width: Width, value: u64) -> Option<i64> {
+    let bits = match width {
+        Width::Byte => 8,
+        Width::Word => 16,
+        Width::DWord => 32,
+        Width::QWord => return Some(value as i64),
+    };
+    let signed = value as i64;
+    if value < 1 << bits {
+        // Reinterpret the low bits as a two's complement number.
+        Some((signed << (64 - bits)) >> (64 - bits))
+    } else if signed < 0 && signed >= -(1 << (bits - 1)) {
+        Some(signed)
+    } else {
+        None
+    }
+}
+fn same_width(a: Option<Width>, b: Width) -> Result<(), &'static str> {
+    match a {
+        Some(a) if a != b => Err("mismatch in operand sizes"),
+        _ => Ok(()),
+    }
+}
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::B => 0x2,
+        Cond::Ae => 0x3,
+        Cond::E => 0x4,
+        Cond::Ne => 0x5,
+        Cond::A => 0x7,
+        Cond::P => 0xA,
+        Cond::Ge => 0xD,
+        Cond::G => 0xF,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(code: &[OpCode]) -> Encoded {
+        let mut encoder = Encoder::new();
+        for instruction in code {
+            encoder.instruction(instruction).unwrap();
+        }
+        encoder.finish()
+    }
+    fn bytes(instruction: OpCode) -> Vec<u8> {
+        encode(&[instruction]).code
+    }
+    fn mov(dst: impl Into<Regester>, src: impl Into<Value>) -> OpCode {
+        OpCode::Mov(dst.into(), src.into())
+    }
+    fn at(base: Reg, offset: i32) -> Directive {
+        Directive::qword(base, true, Some(offset))
+    }
+
+    #[test]
+    fn rex_prefixes() {
+        assert_eq!(bytes(mov(Reg::Rax, Reg::Rbx)), [0x48, 0x89, 0xD8]);
+        // REX.R for the reg field, REX.B for r/m.
+        assert_eq!(bytes(mov(Reg::R8, Reg::R15)), [0x4D, 0x89, 0xF8]);
+        assert_eq!(
+            bytes(OpCode::Add(Reg::R12.into(), Reg::Rax.into())),
+            [0x49, 0x01, 0xC4]
+        );
+        // 32-bit operands only need REX.B.
+        assert_eq!(
+            bytes(mov(Reg::R8d, 5u32)),
+            [0x41, 0xB8, 0x05, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(bytes(OpCode::Push(Reg::Rbx.into())), [0x53]);
+        assert_eq!(bytes(OpCode::Push(Reg::R12.into())), [0x41, 0x54]);
+        assert_eq!(bytes(OpCode::Pop(Reg::R15.into())), [0x41, 0x5F]);
+    }
+
+    #[test]
+    fn rsp_and_r12_bases_need_a_sib_byte() {
+        assert_eq!(
+            bytes(mov(Reg::Rax, Regester::Deref(Reg::Rsp))),
+            [0x48, 0x8B, 0x04, 0x24]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rax, at(Reg::R12, 8))),
+            [0x49, 0x8B, 0x44, 0x24, 0x08]
+        );
+        assert_eq!(
+            bytes(mov(at(Reg::Rsp, 8), Reg::Rdi)),
+            [0x48, 0x89, 0x7C, 0x24, 0x08]
+        );
+    }
+
+    #[test]
+    fn rbp_and_r13_bases_need_a_displacement() {
+        assert_eq!(
+            bytes(mov(Reg::Rax, Regester::Deref(Reg::Rbp))),
+            [0x48, 0x8B, 0x45, 0x00]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rax, Regester::Deref(Reg::R13))),
+            [0x49, 0x8B, 0x45, 0x00]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rax, at(Reg::Rbp, -8))),
+            [0x48, 0x8B, 0x45, 0xF8]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rax, at(Reg::Rbp, 127))),
+            [0x48, 0x8B, 0x45, 0x7F]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rax, at(Reg::Rbp, -256))),
+            [0x48, 0x8B, 0x85, 0x00, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            bytes(mov(Reg::Rcx, at(Reg::R13, 128))),
+            [0x49, 0x8B, 0x8D, 0x80, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn immediates() {
+        assert_eq!(
+            bytes(OpCode::Add(Reg::Rax.into(), 1u32.into())),
+            [0x48, 0x83, 0xC0, 0x01]
+        );
+        assert_eq!(
+            bytes(OpCode::Add(Reg::Rax.into(), 128u32.into())),
+            [0x48, 0x81, 0xC0, 0x80, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            bytes(OpCode::Sub(Reg::Rsp.into(), (-1i64 as u64).into())),
+            [0x48, 0x83, 0xEC, 0xFF]
+        );
+        assert_eq!(
+            bytes(OpCode::Cmp(Reg::R11.into(), 0x7Fu32.into())),
+            [0x49, 0x83, 0xFB, 0x7F]
+        );
+        assert_eq!(
+            bytes(OpCode::And(Reg::Rax.into(), 0xFFu32.into())),
+            [0x48, 0x81, 0xE0, 0xFF, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            bytes(OpCode::Cmp(
+                Directive::byte(Reg::Rax, true, None).into(),
+                1u32.into()
+            )),
+            [0x80, 0x38, 0x01]
+        );
+        assert_eq!(
+            bytes(mov(at(Reg::Rbp, -8), 3u32)),
+            [0x48, 0xC7, 0x45, 0xF8, 0x03, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn mov_immediates_take_the_shortest_form() {
+        // Writing eax zero extends, so no REX.W.
+        assert_eq!(bytes(mov(Reg::Rax, 1u32)), [0xB8, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            bytes(mov(Reg::Rax, -1i64 as u64)),
+            [0x48, 0xC7, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            bytes(mov(Reg::R9, 1u64 << 40)),
+            [0x49, 0xB9, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn rel32_fixups() {
+        let encoded = encode(&[
+            OpCode::Label("top".to_string()),
+            OpCode::Jmp("end".to_string()),
+            OpCode::Jcc(Cond::E, "top".to_string()),
+            OpCode::Label("end".to_string()),
+            OpCode::Call("external".to_string()),
+            OpCode::Lea(Reg::Rdi, "message".to_string()),
+            OpCode::Ret,
+            OpCode::Label("message".to_string()),
+            OpCode::Db(b"hi".to_vec()),
+        ]);
+        assert_eq!(
+            encoded.code,
+            [
+                0xE9, 0x06, 0x00, 0x00, 0x00, // jmp end
+                0x0F, 0x84, 0xF5, 0xFF, 0xFF, 0xFF, // je top
+                0xE8, 0x00, 0x00, 0x00, 0x00, // call external
+                0x48, 0x8D, 0x3D, 0x01, 0x00, 0x00, 0x00, // lea rdi,[rel message]
+                0xC3, b'h', b'i',
+            ]
+        );
+        let symbols: Vec<_> = encoded
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.offset))
+            .collect();
+        assert_eq!(symbols, [("top", 0), ("end", 11), ("message", 24)]);
+        let [relocation] = &encoded.relocations[..] else {
+            panic!("one relocation expected");
+        };
+        assert_eq!(relocation.offset, 12);
+        assert_eq!(relocation.symbol, "external");
+        assert_eq!(relocation.addend, -4);
+        assert_eq!(relocation.reference, Reference::Branch);
+    }
+
+    #[test]
+    fn errors() {
+        let mut encoder = Encoder::new();
+        let error = |encoder: &mut Encoder, instruction| {
+            encoder.instruction(&instruction).unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(&mut encoder, mov(Regester::Deref(Reg::Rax), 1u32)),
+            "cannot encode `mov [rax],1`: operation size not specified"
+        );
+        assert_eq!(
+            error(&mut encoder, mov(Reg::Eax, Reg::Rbx)),
+            "cannot encode `mov eax,rbx`: mismatch in operand sizes"
+        );
+        encoder
+            .instruction(&OpCode::Label("a".to_string()))
+            .unwrap();
+        assert_eq!(
+            error(&mut encoder, OpCode::Label("a".to_string())),
+            "cannot encode `a:`: label defined twice"
+        );
+    }
+}
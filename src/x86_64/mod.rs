@@ -1,12 +1,20 @@
 use std::fmt;
-pub(crate) mod regesters;
+pub(crate) mod abi;
+pub(crate) mod codegen;
 pub(crate) mod directives;
+pub(crate) mod encoder;
 pub(crate) mod op_code;
-pub(crate) use op_code::*;
+pub(crate) mod peephole;
+pub(crate) mod printer;
+pub(crate) mod regalloc;
+pub(crate) mod regesters;
+pub(crate) mod runtime;
 pub(crate) use directives::*;
-pub(crate) use  regesters::*;
+pub(crate) use op_code::*;
+pub(crate) use regesters::*;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum Value {
-    Number(u32),
+    Number(u64),
     Regester(Regester),
 }
 impl<T: Into<Regester>> From<T> for Value {
@@ -19,20 +27,38 @@ impl From<u32> for Value {
         Self::Number(value.into())
     }
 }
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::Number(value)
+    }
+}
 impl From<Syscall> for Value {
     fn from(value: Syscall) -> Self {
-        Self::Number(value.into())
+        Self::Number(u32::from(value).into())
     }
 }
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Number(n) => n.fmt(f),
+            Self::Number(n) => Immediate(*n).fmt(f),
             Self::Regester(n) => n.fmt(f),
         }
     }
 }
 
+/// Formats an immediate, values with the top bit set are written as
+/// negative numbers so assemblers accept them as sign extended.
+pub(crate) struct Immediate(pub(crate) u64);
+impl fmt::Display for Immediate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 > i64::MAX as u64 {
+            (self.0 as i64).fmt(f)
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
 pub(crate) enum Syscall {
     /// 1: %rax(0x01), %rdi(unsigned int fd), %rsi(char *buf), %rdx(size_t count)
     Write,
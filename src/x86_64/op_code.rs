@@ -1,114 +1,128 @@
-use super::{Reg, Regester, Syscall, Value};
-use crate::ir::Blob;
+use super::{Reg, Regester, Value, Xmm};
 use std::fmt;
+/// Condition codes for conditional jumps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Cond {
+    /// Equal (zf = 1)
+    E,
+    /// Not equal (zf = 0)
+    Ne,
+    /// Signed greater than
+    G,
+    /// Signed greater than or equal
+    Ge,
+    /// Unsigned below
+    B,
+    /// Unsigned above
+    A,
+    /// Unsigned above or equal
+    Ae,
+    /// Parity (pf = 1), after `ucomisd` one of the operands was NaN
+    P,
+}
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::E => write!(f, "e"),
+            Self::Ne => write!(f, "ne"),
+            Self::G => write!(f, "g"),
+            Self::Ge => write!(f, "ge"),
+            Self::B => write!(f, "b"),
+            Self::A => write!(f, "a"),
+            Self::Ae => write!(f, "ae"),
+            Self::P => write!(f, "p"),
+        }
+    }
+}
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum OpCode {
+    /// Marks the position of the next instruction.
+    Label(String),
+    /// Raw bytes, `db`.
+    Db(Vec<u8>),
+    /// A 64-bit little endian value, `dq`.
+    Dq(u64),
+    /// Reserves zeroed bytes, `resb`.
+    Resb(usize),
     Mov(Regester, Value),
+    /// Loads the address of a label, relative to `rip`.
+    Lea(Reg, String),
+    /// Loads `base + offset`, without changing the flags.
+    LeaOffset(Reg, Reg, i32),
     Push(Regester),
     Pop(Regester),
     Add(Regester, Value),
     Sub(Regester, Value),
-    Mul(Regester, Value),
     Div(Reg),
     Xor(Regester, Regester),
+    And(Regester, Value),
+    Cmp(Regester, Value),
+    Jmp(String),
+    Jcc(Cond, String),
+    Call(String),
+    Ret,
     Syscall,
-}
-
-impl OpCode {
-    pub(crate) fn exit<T: Into<Value>>(exit_code: T) -> Blob {
-        let exit_code = exit_code.into();
-        let mov = if let Value::Number(0) = exit_code {
-            Self::Xor(Reg::Rdi.into(), Reg::Rdi.into())
-        } else {
-            Self::Mov(Reg::Rdi.into(), exit_code)
-        };
-        Blob::from(vec![
-            mov,
-            Self::Mov(Reg::Rax.into(), Syscall::Exit.into()),
-            Self::Syscall,
-        ])
-    }
-    pub(crate) fn constant(cons: u32) -> Blob {
-        Blob::from(vec![
-            Self::Mov(Reg::Rax.into(), cons.into()),
-            Self::Push(Reg::Rax.into()),
-        ])
-    }
-    pub(crate) fn add() -> Blob {
-        Blob::from(vec![
-            Self::Pop(Reg::Rbx.into()),
-            Self::Pop(Reg::Rax.into()),
-            Self::Add(Reg::Rax.into(), Reg::Rbx.into()),
-            Self::Push(Reg::Rax.into()),
-        ])
-    }
-    pub(crate) fn add_v(a: u32, b: u32) -> Blob {
-        Blob::from(vec![
-            Self::Mov(Reg::Rax.into(), a.into()),
-            Self::Mov(Reg::Rbx.into(), b.into()),
-            Self::Sub(Reg::Rax.into(), Reg::Rbx.into()),
-        ])
-    }
-    pub(crate) fn sub() -> Blob {
-        Blob::from(vec![
-            Self::Pop(Reg::Rbx.into()),
-            Self::Pop(Reg::Rax.into()),
-            Self::Sub(Reg::Rax.into(), Reg::Rbx.into()),
-            Self::Push(Reg::Rax.into()),
-        ])
-    }
-    pub(crate) fn sub_v(a: u32, b: u32) -> Blob {
-        Blob::from(vec![
-            Self::Mov(Reg::Rax.into(), a.into()),
-            Self::Mov(Reg::Rbx.into(), b.into()),
-            Self::Sub(Reg::Rax.into(), Reg::Rbx.into()),
-        ])
-    }
-    pub(crate) fn mul() -> Blob {
-        Blob::from(vec![
-            Self::Pop(Reg::Rbx.into()),
-            Self::Pop(Reg::Rax.into()),
-            Self::Mul(Reg::Rax.into(), Reg::Rbx.into()),
-            Self::Push(Reg::Rax.into()),
-        ])
-    }
-    pub(crate) fn mul_v(a: u32, b: u32) -> Blob {
-        Blob::from(vec![
-            Self::Mov(Reg::Rax.into(), a.into()),
-            Self::Mov(Reg::Rbx.into(), b.into()),
-            Self::Mul(Reg::Rax.into(), Reg::Rbx.into()),
-        ])
-    }
-    pub(crate) fn div() -> Blob {
-        Blob::from(vec![
-            OpCode::Pop(Reg::Rax.into()),                  // Move dividend into rax
-            OpCode::Pop(Reg::Rbx.into()),                  // Move divisor into rbx
-            OpCode::Xor(Reg::Rdx.into(), Reg::Rdx.into()), // Clear rdx
-            OpCode::Div(Reg::Rbx),
-            OpCode::Push(Reg::Rax.into()),
-        ])
-    }
-    pub(crate) fn div_v(a: u32, b: u32) -> Blob {
-        Blob::from(vec![
-            OpCode::Mov(Reg::Rax.into(), b.into()), // Move dividend (bigger number) into rax
-            OpCode::Mov(Reg::Rbx.into(), a.into()), // Move divisor (smaller number) into rbx
-            OpCode::Xor(Reg::Rdx.into(), Reg::Rdx.into()), // Clear rdx
-            OpCode::Div(Reg::Rbx),
-        ])
-    }
+    /// Raises an invalid opcode exception, for code that must never run.
+    Ud2,
+    /// `movq`, copies the bits of a general purpose regester into an SSE one.
+    MovqToXmm(Xmm, Reg),
+    /// `movq`, copies the bits of an SSE regester into a general purpose one.
+    MovqFromXmm(Reg, Xmm),
+    Addsd(Xmm, Xmm),
+    Subsd(Xmm, Xmm),
+    Mulsd(Xmm, Xmm),
+    Divsd(Xmm, Xmm),
+    /// Compares two doubles, setting the flags like an unsigned compare;
+    /// pf is set when they're unordered (either is NaN).
+    Ucomisd(Xmm, Xmm),
+    /// Converts a double to a signed integer, rounding to nearest even.
+    Cvtsd2si(Reg, Xmm),
+    /// Converts a signed integer to a double.
+    Cvtsi2sd(Xmm, Reg),
 }
 
 impl fmt::Display for OpCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Label(l) => write!(f, "{l}:"),
+            Self::Db(bytes) => {
+                write!(f, "db ")?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{byte}")?;
+                }
+                Ok(())
+            }
+            Self::Dq(n) => write!(f, "dq {n}"),
+            Self::Resb(n) => write!(f, "resb {n}"),
             Self::Mov(d, s) => write!(f, "mov {d},{s}"),
+            Self::Lea(d, l) => write!(f, "lea {d},[rel {l}]"),
+            Self::LeaOffset(d, b, o) => write!(f, "lea {d},[{b}{o:+}]"),
             Self::Push(s) => write!(f, "push {s}"),
             Self::Pop(d) => write!(f, "pop {d}"),
             Self::Add(d, s) => write!(f, "add {d},{s}"),
             Self::Sub(d, s) => write!(f, "sub {d},{s}"),
-            Self::Mul(d, s) => write!(f, "imul {d},{s}"),
             Self::Div(s) => write!(f, "div {s}"),
             Self::Xor(d, s) => write!(f, "xor {d},{s}"),
+            Self::And(d, s) => write!(f, "and {d},{s}"),
+            Self::Cmp(a, b) => write!(f, "cmp {a},{b}"),
+            Self::Jmp(l) => write!(f, "jmp {l}"),
+            Self::Jcc(c, l) => write!(f, "j{c} {l}"),
+            Self::Call(l) => write!(f, "call {l}"),
+            Self::Ret => write!(f, "ret"),
             Self::Syscall => write!(f, "syscall"),
+            Self::Ud2 => write!(f, "ud2"),
+            Self::MovqToXmm(d, s) => write!(f, "movq {d},{s}"),
+            Self::MovqFromXmm(d, s) => write!(f, "movq {d},{s}"),
+            Self::Addsd(d, s) => write!(f, "addsd {d},{s}"),
+            Self::Subsd(d, s) => write!(f, "subsd {d},{s}"),
+            Self::Mulsd(d, s) => write!(f, "mulsd {d},{s}"),
+            Self::Divsd(d, s) => write!(f, "divsd {d},{s}"),
+            Self::Ucomisd(a, b) => write!(f, "ucomisd {a},{b}"),
+            Self::Cvtsd2si(d, s) => write!(f, "cvtsd2si {d},{s}"),
+            Self::Cvtsi2sd(d, s) => write!(f, "cvtsi2sd {d},{s}"),
         }
     }
 }
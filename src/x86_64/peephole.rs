@@ -0,0 +1,299 @@
+//! Peephole optimizations: small rewrites of neighbouring instructions in
+//! the text of a [`Program`], runtime included.
+//!
+//! - `push x` then `pop x` goes away, `push x` then `pop y` becomes a `mov`
+//! - `mov r,0` becomes `xor r,r`
+//! - `mov r,s` then `add r,n` becomes `lea r,[s+n]`
+//! - a `jmp` to the instruction right after it goes away
+//!
+//! `xor` and `add` change the flags and `mov` and `lea` don't, so those two
+//! only apply where nothing reads the flags before they're written again.
+use super::{Directive, OpCode, Reg, Regester, Value};
+use crate::ir::{Blob, Program, Section};
+
+pub(crate) fn optimize(program: &mut Program) {
+    for section in program.text_mut() {
+        self::section(section);
+    }
+}
+
+fn section(section: &mut Section) {
+    // Rules can reach across blobs, so they work on the whole section with
+    // the blob every instruction came from.
+    let blobs = std::mem::take(section.code_mut());
+    let count = blobs.len();
+    let mut code: Vec<(usize, OpCode)> = blobs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, blob)| blob.into_iter().map(move |instruction| (i, instruction)))
+        .collect();
+    while rewrite(&mut code) {}
+    let mut blobs = vec![Vec::new(); count];
+    for (i, instruction) in code {
+        blobs[i].push(instruction);
+    }
+    *section.code_mut() = blobs.into_iter().map(Blob::from).collect();
+}
+
+/// One pass over `code`, returns whether anything changed.
+fn rewrite(code: &mut Vec<(usize, OpCode)>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < code.len() {
+        let next = code.get(i + 1).map(|(_, instruction)| instruction);
+        let replacement = match (&code[i].1, next) {
+            (OpCode::Push(src), Some(OpCode::Pop(dst))) => push_pop(src, dst).map(|mov| (2, mov)),
+            (OpCode::Mov(Regester::Regester(dst), Value::Number(0)), _)
+                if !flags_read(code, i + 1) =>
+            {
+                Some((1, vec![OpCode::Xor((*dst).into(), (*dst).into())]))
+            }
+            (
+                OpCode::Mov(Regester::Regester(dst), Value::Regester(Regester::Regester(src))),
+                Some(OpCode::Add(Regester::Regester(add), Value::Number(n))),
+            ) if add == dst && !flags_read(code, i + 2) => lea(*dst, *src, *n).map(|lea| (2, lea)),
+            (OpCode::Jmp(target), _) if jumps_to_next(code, i, target) => Some((1, Vec::new())),
+            _ => None,
+        };
+        match replacement {
+            Some((replaced, instructions)) => {
+                let blob = code[i].0;
+                code.splice(
+                    i..i + replaced,
+                    instructions
+                        .into_iter()
+                        .map(|instruction| (blob, instruction)),
+                );
+                changed = true;
+            }
+            None => i += 1,
+        }
+    }
+    changed
+}
+
+/// What `push src` then `pop dst` can be replaced with.
+fn push_pop(src: &Regester, dst: &Regester) -> Option<Vec<OpCode>> {
+    let uses_rsp = |operand: &Regester| match operand {
+        Regester::Regester(reg) | Regester::Deref(reg) => *reg == Reg::Rsp,
+        Regester::Directive(
+            Directive::Byte { regester, .. } | Directive::QWord { regester, .. },
+        ) => *regester == Reg::Rsp,
+    };
+    // Pushing changes where rsp relative operands point.
+    if uses_rsp(src) || uses_rsp(dst) {
+        return None;
+    }
+    if src == dst {
+        return Some(Vec::new());
+    }
+    match (src, dst) {
+        (Regester::Regester(_), _) | (_, Regester::Regester(_)) => {
+            Some(vec![OpCode::Mov(*dst, (*src).into())])
+        }
+        // There's no `mov` between two memory operands.
+        _ => None,
+    }
+}
+
+/// `lea dst,[src+n]`, if `n` fits in a displacement and the registers are
+/// 64-bit.
+fn lea(dst: Reg, src: Reg, n: u64) -> Option<Vec<OpCode>> {
+    if dst.size() != 8 || src.size() != 8 {
+        return None;
+    }
+    // `add` sign extends its 32-bit immediate.
+    let offset = i32::try_from(n as i64).ok()?;
+    Some(vec![OpCode::LeaOffset(dst, src, offset)])
+}
+
+/// Whether the instructions from `start` on might read the flags before
+/// writing them. Not knowing what comes after a jump or the end of the
+/// section, it says they might.
+fn flags_read(code: &[(usize, OpCode)], start: usize) -> bool {
+    for (_, instruction) in &code[start.min(code.len())..] {
+        match instruction {
+            OpCode::Jcc(..) | OpCode::Jmp(_) => return true,
+            OpCode::Add(..)
+            | OpCode::Sub(..)
+            | OpCode::Div(_)
+            | OpCode::Xor(..)
+            | OpCode::And(..)
+            | OpCode::Cmp(..)
+            | OpCode::Ucomisd(..) => return false,
+            // Calls don't keep the flags, returns don't hand them back.
+            OpCode::Call(_) | OpCode::Ret | OpCode::Ud2 => return false,
+            _ => (),
+        }
+    }
+    true
+}
+
+/// Whether `target` labels the instruction after the `jmp` at `i`.
+fn jumps_to_next(code: &[(usize, OpCode)], i: usize, target: &str) -> bool {
+    code[i + 1..]
+        .iter()
+        .map_while(|(_, instruction)| match instruction {
+            OpCode::Label(label) => Some(label),
+            _ => None,
+        })
+        .any(|label| label == target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x86_64::Cond;
+
+    fn optimized(code: Vec<OpCode>) -> Vec<OpCode> {
+        let mut section = Section::new("test", vec![Blob::from(code)]);
+        self::section(&mut section);
+        section.into_iter().flatten().cloned().collect()
+    }
+
+    #[test]
+    fn push_pop_same_operand() {
+        let code = vec![
+            OpCode::Push(Reg::Rax.into()),
+            OpCode::Pop(Reg::Rax.into()),
+            OpCode::Ret,
+        ];
+        assert_eq!(optimized(code), vec![OpCode::Ret]);
+    }
+
+    #[test]
+    fn push_pop_becomes_mov() {
+        let slot = Directive::qword(Reg::Rbp, true, Some(-8));
+        let code = vec![OpCode::Push(slot.into()), OpCode::Pop(Reg::Rbx.into())];
+        assert_eq!(
+            optimized(code),
+            vec![OpCode::Mov(Reg::Rbx.into(), slot.into())]
+        );
+    }
+
+    #[test]
+    fn push_pop_memory_to_memory_stays() {
+        let a = Directive::qword(Reg::Rbp, true, Some(-8));
+        let b = Directive::qword(Reg::Rbp, true, Some(-16));
+        let code = vec![OpCode::Push(a.into()), OpCode::Pop(b.into())];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn push_pop_rsp_stays() {
+        let code = vec![OpCode::Push(Reg::Rsp.into()), OpCode::Pop(Reg::Rax.into())];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn mov_zero_becomes_xor() {
+        let code = vec![OpCode::Mov(Reg::Rax.into(), 0u32.into()), OpCode::Ret];
+        assert_eq!(
+            optimized(code),
+            vec![OpCode::Xor(Reg::Rax.into(), Reg::Rax.into()), OpCode::Ret]
+        );
+    }
+
+    #[test]
+    fn mov_zero_keeps_flags_for_jcc() {
+        let code = vec![
+            OpCode::Cmp(Reg::Rax.into(), Reg::Rbx.into()),
+            OpCode::Mov(Reg::Rax.into(), 0u32.into()),
+            OpCode::Jcc(Cond::E, "done".to_string()),
+            OpCode::Label("done".to_string()),
+            OpCode::Ret,
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn mov_add_becomes_lea() {
+        let code = vec![
+            OpCode::Mov(Reg::Rdi.into(), Reg::Rax.into()),
+            OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+            OpCode::Call("lox_alloc".to_string()),
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![
+                OpCode::LeaOffset(Reg::Rdi, Reg::Rax, 8),
+                OpCode::Call("lox_alloc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mov_add_negative_immediate() {
+        let code = vec![
+            OpCode::Mov(Reg::R8.into(), Reg::R12.into()),
+            OpCode::Add(Reg::R8.into(), (-16i64 as u64).into()),
+            OpCode::Ret,
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![OpCode::LeaOffset(Reg::R8, Reg::R12, -16), OpCode::Ret]
+        );
+    }
+
+    #[test]
+    fn mov_add_keeps_flags_for_jcc() {
+        let code = vec![
+            OpCode::Mov(Reg::Rdi.into(), Reg::Rax.into()),
+            OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+            OpCode::Jcc(Cond::B, "overflow".to_string()),
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn mov_add_large_immediate_stays() {
+        let code = vec![
+            OpCode::Mov(Reg::Rdi.into(), Reg::Rax.into()),
+            OpCode::Add(Reg::Rdi.into(), (1u64 << 40).into()),
+            OpCode::Ret,
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn jump_to_next_instruction() {
+        let mut section = Section::new(
+            "test",
+            vec![
+                Blob::from(vec![OpCode::Jmp("b".to_string())]),
+                Blob::from(vec![
+                    OpCode::Label("a".to_string()),
+                    OpCode::Label("b".to_string()),
+                    OpCode::Ret,
+                ]),
+            ],
+        );
+        self::section(&mut section);
+        let blobs: Vec<Vec<_>> = section
+            .into_iter()
+            .map(|blob| blob.into_iter().cloned().collect())
+            .collect();
+        assert_eq!(
+            blobs,
+            vec![
+                vec![],
+                vec![
+                    OpCode::Label("a".to_string()),
+                    OpCode::Label("b".to_string()),
+                    OpCode::Ret,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_over_code_stays() {
+        let code = vec![
+            OpCode::Jmp("b".to_string()),
+            OpCode::Ud2,
+            OpCode::Label("b".to_string()),
+            OpCode::Ret,
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+}
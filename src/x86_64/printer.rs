@@ -0,0 +1,475 @@
+//! Assembly syntaxes an [`ir::Program`](crate::ir::Program) can be printed in.
+use super::{Directive, Immediate, OpCode, Reg, Regester, Value};
+use std::fmt::{self, Display};
+
+/// How a program gets written out as assembly text.
+pub(crate) trait Syntax {
+    /// Written once, before anything else.
+    fn prologue(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+    /// Switches to the section called `name`, e.g. `.text`.
+    fn section(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result;
+    /// Makes `name` visible to the linker.
+    fn global(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result;
+    /// Declares `name` as defined by something we link against.
+    fn external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result;
+    fn instruction(&self, f: &mut fmt::Formatter<'_>, instruction: &OpCode) -> fmt::Result;
+    /// A call to an [`external`](Syntax::external) function, which may live
+    /// in a shared library.
+    fn call_external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        write!(f, "call {name}")
+    }
+    /// Written once, after everything else.
+    fn epilogue(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// NASM syntax, what [`OpCode`]'s `Display` produces.
+pub(crate) struct Nasm;
+impl Syntax for Nasm {
+    fn section(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "section {name}")
+    }
+    fn global(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  global {name}")
+    }
+    fn external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  extern {name}")
+    }
+    fn instruction(&self, f: &mut fmt::Formatter<'_>, instruction: &OpCode) -> fmt::Result {
+        Display::fmt(instruction, f)
+    }
+    fn call_external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        write!(f, "call {name} wrt ..plt")
+    }
+    /// Marks the stack as non executable.
+    fn epilogue(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "section .note.GNU-stack noalloc noexec nowrite progbits")
+    }
+}
+
+/// GNU as with `.intel_syntax noprefix`.
+pub(crate) struct GasIntel;
+impl Syntax for GasIntel {
+    fn prologue(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, ".intel_syntax noprefix")
+    }
+    fn section(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, ".section {name}")
+    }
+    fn global(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  .globl {name}")
+    }
+    fn external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  .extern {name}")
+    }
+    fn epilogue(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        gas_epilogue(f)
+    }
+    fn instruction(&self, f: &mut fmt::Formatter<'_>, instruction: &OpCode) -> fmt::Result {
+        match instruction {
+            OpCode::Mov(d, s) => write!(f, "mov {},{}", intel(d.into()), intel(s.into())),
+            OpCode::Lea(d, l) => write!(f, "lea {d},[rip+{l}]"),
+            OpCode::Push(s) => write!(f, "push {}", intel(s.into())),
+            OpCode::Pop(d) => write!(f, "pop {}", intel(d.into())),
+            OpCode::Add(d, s) => write!(f, "add {},{}", intel(d.into()), intel(s.into())),
+            OpCode::Sub(d, s) => write!(f, "sub {},{}", intel(d.into()), intel(s.into())),
+            OpCode::Xor(d, s) => write!(f, "xor {},{}", intel(d.into()), intel(s.into())),
+            OpCode::And(d, s) => write!(f, "and {},{}", intel(d.into()), intel(s.into())),
+            OpCode::Cmp(a, b) => write!(f, "cmp {},{}", intel(a.into()), intel(b.into())),
+            _ => gas_common(f, instruction),
+        }
+    }
+}
+
+/// GNU as AT&T syntax: `%` registers, `$` immediates, source operand first
+/// and the operand size as a mnemonic suffix.
+pub(crate) struct GasAtt;
+impl Syntax for GasAtt {
+    fn section(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, ".section {name}")
+    }
+    fn global(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  .globl {name}")
+    }
+    fn external(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        writeln!(f, "  .extern {name}")
+    }
+    fn epilogue(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        gas_epilogue(f)
+    }
+    fn instruction(&self, f: &mut fmt::Formatter<'_>, instruction: &OpCode) -> fmt::Result {
+        let two = |f: &mut fmt::Formatter<'_>, mnemonic: &str, d: &Regester, s: Operand| {
+            let d = Operand::from(d);
+            let suffix = suffix(d.size().or(s.size()));
+            write!(f, "{mnemonic}{suffix} {s},{d}")
+        };
+        match instruction {
+            OpCode::Mov(d, s) => two(f, "mov", d, s.into()),
+            OpCode::Lea(d, l) => write!(f, "leaq {l}(%rip),%{d}"),
+            OpCode::LeaOffset(d, b, o) => write!(f, "leaq {o}(%{b}),%{d}"),
+            OpCode::Push(s) => {
+                let s = Operand::from(s);
+                write!(f, "push{} {s}", suffix(s.size().or(Some(8))))
+            }
+            OpCode::Pop(d) => {
+                let d = Operand::from(d);
+                write!(f, "pop{} {d}", suffix(d.size().or(Some(8))))
+            }
+            OpCode::Add(d, s) => two(f, "add", d, s.into()),
+            OpCode::Sub(d, s) => two(f, "sub", d, s.into()),
+            OpCode::Xor(d, s) => two(f, "xor", d, s.into()),
+            OpCode::And(d, s) => two(f, "and", d, s.into()),
+            OpCode::Cmp(a, b) => two(f, "cmp", a, b.into()),
+            OpCode::Div(s) => write!(f, "div{} %{s}", suffix(Some(s.size()))),
+            OpCode::MovqToXmm(d, s) => write!(f, "movq %{s},%{d}"),
+            OpCode::MovqFromXmm(d, s) => write!(f, "movq %{s},%{d}"),
+            OpCode::Addsd(d, s) => write!(f, "addsd %{s},%{d}"),
+            OpCode::Subsd(d, s) => write!(f, "subsd %{s},%{d}"),
+            OpCode::Mulsd(d, s) => write!(f, "mulsd %{s},%{d}"),
+            OpCode::Divsd(d, s) => write!(f, "divsd %{s},%{d}"),
+            OpCode::Ucomisd(a, b) => write!(f, "ucomisd %{b},%{a}"),
+            OpCode::Cvtsd2si(d, s) => write!(f, "cvtsd2si %{s},%{d}"),
+            OpCode::Cvtsi2sd(d, s) => write!(f, "cvtsi2sdq %{s},%{d}"),
+            _ => gas_common(f, instruction),
+        }
+    }
+}
+
+/// Marks the stack as non executable.
+fn gas_epilogue(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, ".section .note.GNU-stack,\"\",@progbits")
+}
+
+/// The instructions both GNU as syntaxes print the same way.
+fn gas_common(f: &mut fmt::Formatter<'_>, instruction: &OpCode) -> fmt::Result {
+    match instruction {
+        OpCode::Db(bytes) => {
+            write!(f, ".byte ")?;
+            for (i, byte) in bytes.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{byte}")?;
+            }
+            Ok(())
+        }
+        OpCode::Dq(n) => write!(f, ".quad {n}"),
+        OpCode::Resb(n) => write!(f, ".zero {n}"),
+        // Labels, branches and the rest read the same as in NASM.
+        _ => Display::fmt(instruction, f),
+    }
+}
+
+/// An operand taken apart into the pieces the syntaxes arrange differently.
+enum Operand {
+    Regester(Reg),
+    Memory {
+        base: Reg,
+        offset: Option<i32>,
+        /// The size directive, if there was one.
+        size: Option<u8>,
+    },
+    Immediate(u64),
+}
+impl Operand {
+    fn size(&self) -> Option<u8> {
+        match self {
+            Self::Regester(r) => Some(r.size()),
+            Self::Memory { size, .. } => *size,
+            Self::Immediate(_) => None,
+        }
+    }
+}
+impl From<&Regester> for Operand {
+    fn from(value: &Regester) -> Self {
+        let (regester, deref, offset, size) = match value {
+            Regester::Regester(r) => return Self::Regester(*r),
+            Regester::Deref(r) => {
+                return Self::Memory {
+                    base: *r,
+                    offset: None,
+                    size: None,
+                }
+            }
+            Regester::Directive(Directive::Byte {
+                regester,
+                deref,
+                offset,
+            }) => (regester, deref, offset, 1),
+            Regester::Directive(Directive::QWord {
+                regester,
+                deref,
+                offset,
+            }) => (regester, deref, offset, 8),
+        };
+        if !*deref && offset.is_none() {
+            Self::Regester(*regester)
+        } else {
+            Self::Memory {
+                base: *regester,
+                offset: *offset,
+                size: Some(size),
+            }
+        }
+    }
+}
+impl From<&Value> for Operand {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Number(n) => Self::Immediate(*n),
+            Value::Regester(r) => r.into(),
+        }
+    }
+}
+/// AT&T operands.
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Regester(r) => write!(f, "%{r}"),
+            Self::Memory {
+                base,
+                offset: Some(offset),
+                ..
+            } => write!(f, "{offset}(%{base})"),
+            Self::Memory { base, .. } => write!(f, "(%{base})"),
+            Self::Immediate(n) => write!(f, "${}", Immediate(*n)),
+        }
+    }
+}
+fn suffix(size: Option<u8>) -> &'static str {
+    match size {
+        Some(1) => "b",
+        Some(2) => "w",
+        Some(4) => "l",
+        Some(8) => "q",
+        _ => "",
+    }
+}
+
+/// Formats an operand the way GNU as expects it in Intel syntax.
+fn intel(operand: Operand) -> String {
+    match operand {
+        Operand::Regester(r) => r.to_string(),
+        Operand::Immediate(n) => Immediate(n).to_string(),
+        Operand::Memory { base, offset, size } => {
+            let size = match size {
+                Some(1) => "BYTE PTR ",
+                Some(2) => "WORD PTR ",
+                Some(4) => "DWORD PTR ",
+                Some(8) => "QWORD PTR ",
+                _ => "",
+            };
+            match offset {
+                Some(offset) => format!("{size}[{base}{offset:+}]"),
+                None => format!("{size}[{base}]"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ir::{Blob, Program, Section},
+        x86_64::{Cond, Xmm},
+    };
+
+    /// A bit of everything the syntaxes print differently: sections, memory
+    /// operands with and without offsets and sizes, immediates, labels,
+    /// calls and SSE instructions.
+    fn program() -> Program {
+        let slot = Directive::qword(Reg::Rbp, true, Some(-8));
+        let data = Section::new(
+            "data",
+            vec![Blob::from(vec![
+                OpCode::Label("counter".to_string()),
+                OpCode::Dq(7),
+                OpCode::Label("text".to_string()),
+                OpCode::Db(vec![104, 105, 10]),
+            ])],
+        );
+        let text = Section::new(
+            "_start",
+            vec![Blob::from(vec![
+                OpCode::Mov(Reg::Rax.into(), 1u32.into()),
+                OpCode::Mov(Reg::Rcx.into(), (-2i64 as u64).into()),
+                OpCode::Mov(slot.into(), Reg::Rax.into()),
+                OpCode::Mov(Reg::Rdx.into(), Regester::Deref(Reg::Rax).into()),
+                OpCode::Mov(Directive::byte(Reg::Rax, true, None).into(), 0u32.into()),
+                OpCode::Push(slot.into()),
+                OpCode::Pop(Reg::R12.into()),
+                OpCode::Lea(Reg::Rsi, "text".to_string()),
+                OpCode::LeaOffset(Reg::Rdi, Reg::Rsp, 16),
+                OpCode::Sub(Reg::Rsp.into(), 16u32.into()),
+                OpCode::And(Reg::Rsp.into(), (-16i64 as u64).into()),
+                OpCode::Cmp(slot.into(), 3u32.into()),
+                OpCode::Jcc(Cond::Ne, "done".to_string()),
+                OpCode::Div(Reg::Rcx),
+                OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rax),
+                OpCode::Addsd(Xmm::Xmm0, Xmm::Xmm1),
+                OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+                OpCode::Cvtsi2sd(Xmm::Xmm1, Reg::Rdx),
+                OpCode::Cvtsd2si(Reg::Rax, Xmm::Xmm0),
+                OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm1),
+                OpCode::Label("done".to_string()),
+                OpCode::Call("helper".to_string()),
+                OpCode::Call("exit".to_string()),
+                OpCode::Jmp("done".to_string()),
+                OpCode::Label("helper".to_string()),
+                OpCode::Ret,
+            ])],
+        );
+        let bss = Section::new("bss", vec![Blob::from(vec![OpCode::Resb(16)])]);
+        Program::new(Some(data), vec![text]).with_bss(bss)
+    }
+
+    #[test]
+    fn nasm() {
+        assert_eq!(
+            program().listing(&Nasm).to_string(),
+            r#"  extern exit
+section .data
+    counter:
+    dq 7
+    text:
+    db 104,105,10
+
+section .bss
+    resb 16
+
+section .text
+  global _start
+_start:
+    mov rax,1
+    mov rcx,-2
+    mov QWORD [rbp-8],rax
+    mov rdx,[rax]
+    mov BYTE [rax],0
+    push QWORD [rbp-8]
+    pop r12
+    lea rsi,[rel text]
+    lea rdi,[rsp+16]
+    sub rsp,16
+    and rsp,-16
+    cmp QWORD [rbp-8],3
+    jne done
+    div rcx
+    movq xmm0,rax
+    addsd xmm0,xmm1
+    ucomisd xmm0,xmm1
+    cvtsi2sd xmm1,rdx
+    cvtsd2si rax,xmm0
+    movq rax,xmm1
+    done:
+    call helper
+    call exit wrt ..plt
+    jmp done
+    helper:
+    ret
+
+section .note.GNU-stack noalloc noexec nowrite progbits
+"#
+        );
+    }
+
+    #[test]
+    fn gas_intel() {
+        assert_eq!(
+            program().listing(&GasIntel).to_string(),
+            r#".intel_syntax noprefix
+  .extern exit
+.section .data
+    counter:
+    .quad 7
+    text:
+    .byte 104,105,10
+
+.section .bss
+    .zero 16
+
+.section .text
+  .globl _start
+_start:
+    mov rax,1
+    mov rcx,-2
+    mov QWORD PTR [rbp-8],rax
+    mov rdx,[rax]
+    mov BYTE PTR [rax],0
+    push QWORD PTR [rbp-8]
+    pop r12
+    lea rsi,[rip+text]
+    lea rdi,[rsp+16]
+    sub rsp,16
+    and rsp,-16
+    cmp QWORD PTR [rbp-8],3
+    jne done
+    div rcx
+    movq xmm0,rax
+    addsd xmm0,xmm1
+    ucomisd xmm0,xmm1
+    cvtsi2sd xmm1,rdx
+    cvtsd2si rax,xmm0
+    movq rax,xmm1
+    done:
+    call helper
+    call exit
+    jmp done
+    helper:
+    ret
+
+.section .note.GNU-stack,"",@progbits
+"#
+        );
+    }
+
+    #[test]
+    fn gas_att() {
+        assert_eq!(
+            program().listing(&GasAtt).to_string(),
+            r#"  .extern exit
+.section .data
+    counter:
+    .quad 7
+    text:
+    .byte 104,105,10
+
+.section .bss
+    .zero 16
+
+.section .text
+  .globl _start
+_start:
+    movq $1,%rax
+    movq $-2,%rcx
+    movq %rax,-8(%rbp)
+    movq (%rax),%rdx
+    movb $0,(%rax)
+    pushq -8(%rbp)
+    popq %r12
+    leaq text(%rip),%rsi
+    leaq 16(%rsp),%rdi
+    subq $16,%rsp
+    andq $-16,%rsp
+    cmpq $3,-8(%rbp)
+    jne done
+    divq %rcx
+    movq %rax,%xmm0
+    addsd %xmm1,%xmm0
+    ucomisd %xmm1,%xmm0
+    cvtsi2sdq %rdx,%xmm1
+    cvtsd2si %xmm0,%rax
+    movq %xmm1,%rax
+    done:
+    call helper
+    call exit
+    jmp done
+    helper:
+    ret
+
+.section .note.GNU-stack,"",@progbits
+"#
+        );
+    }
+}
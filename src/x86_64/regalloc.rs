@@ -0,0 +1,404 @@
+//! Linear scan register allocation, as in Poletto and Sarkar, for functions
+//! out of SSA form.
+//!
+//! Every virtual register gets one interval, from the first to the last
+//! position it's live at with the blocks laid out in order, and either a
+//! hardware register for all of it or a stack slot. `rax`, `rcx`, `rdx`,
+//! `rsi` and `rdi` are left for the code generator to pass arguments and
+//! keep temporaries in, and `rbp` holds the frame pointer.
+use super::{abi, Reg};
+use crate::mir::{cfg::Cfg, liveness::Liveness, Function, Instruction, VReg};
+
+/// The callee saved registers handed out, kept across calls so they have
+/// to be saved in the prologue.
+fn callee_saved() -> impl DoubleEndedIterator<Item = Reg> {
+    abi::CALLEE_SAVED.into_iter().filter(|reg| *reg != Reg::Rbp)
+}
+/// The caller saved registers handed out, only for intervals that don't
+/// span a call.
+fn caller_saved() -> impl DoubleEndedIterator<Item = Reg> {
+    abi::CALLER_SAVED
+        .into_iter()
+        .filter(|reg| *reg != Reg::Rax && !abi::ARGUMENTS[..4].contains(reg))
+}
+
+/// Where a virtual register lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Location {
+    Reg(Reg),
+    /// The index of a stack slot.
+    Stack(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Allocation {
+    /// `None` for registers that are never used.
+    locations: Vec<Option<Location>>,
+    /// The callee saved registers handed out, in the order of
+    /// [`abi::CALLEE_SAVED`].
+    pub(crate) saved: Vec<Reg>,
+    /// How many stack slots spilled registers need.
+    pub(crate) slots: u32,
+}
+impl Allocation {
+    pub(crate) fn location(&self, vreg: VReg) -> Location {
+        self.locations[vreg.index()].unwrap_or_else(|| panic!("{vreg} is never used"))
+    }
+    pub(crate) fn is_used(&self, vreg: VReg) -> bool {
+        self.locations[vreg.index()].is_some()
+    }
+}
+
+/// How the code for an instruction calls into the runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Call {
+    /// After reading its operands.
+    Last,
+    /// To check the types of its operands, which are read again after.
+    Check,
+}
+
+#[derive(Clone, Copy)]
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+}
+
+/// Allocates registers for `function`. `calls` tells which instructions
+/// the code generator turns into calls, which clobber the caller saved
+/// registers.
+pub(crate) fn allocate(
+    function: &Function,
+    calls: impl Fn(&Instruction) -> Option<Call>,
+) -> Allocation {
+    let (intervals, calls) = intervals(function, calls);
+    // A register copied from another gets the same hardware register if
+    // it's free, which makes the copy go away.
+    let mut hints = vec![None; function.types.len()];
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        if let Instruction::Copy { dst, src } = instruction {
+            hints[dst.index()].get_or_insert(*src);
+        }
+    }
+    let mut locations = vec![None; function.types.len()];
+    let mut slots = 0;
+    let mut spill = |vreg: VReg, locations: &mut Vec<Option<Location>>| {
+        locations[vreg.index()] = Some(Location::Stack(slots));
+        slots += 1;
+    };
+    // Reversed, registers are taken from the end.
+    let mut free_callee: Vec<_> = callee_saved().rev().collect();
+    let mut free_caller: Vec<_> = caller_saved().rev().collect();
+    let mut used_callee = Vec::new();
+    // Sorted by the end of the interval.
+    let mut active: Vec<(Interval, Reg)> = Vec::new();
+    for interval in intervals {
+        // An interval ending right before this one starts was read by the
+        // instruction writing this one, so they can share a register.
+        active.retain(|&(active, reg)| {
+            let expired = active.end < interval.start;
+            if expired {
+                if abi::CALLEE_SAVED.contains(&reg) {
+                    free_callee.push(reg);
+                } else {
+                    free_caller.push(reg);
+                }
+            }
+            !expired
+        });
+        let spans_call = {
+            let first = calls.partition_point(|&call| call < interval.start);
+            calls.get(first).is_some_and(|&call| call < interval.end)
+        };
+        let hint =
+            hints[interval.vreg.index()].and_then(|src: VReg| match locations[src.index()] {
+                Some(Location::Reg(reg)) => Some(reg),
+                _ => None,
+            });
+        let take = |free: &mut Vec<Reg>| {
+            let i = free
+                .iter()
+                .position(|reg| Some(*reg) == hint)
+                .unwrap_or(free.len().checked_sub(1)?);
+            Some(free.remove(i))
+        };
+        let free = if spans_call {
+            take(&mut free_callee)
+        } else {
+            take(&mut free_caller).or_else(|| take(&mut free_callee))
+        };
+        let reg = match free {
+            Some(reg) => reg,
+            None => {
+                // Spill whichever interval ends last, this one or an
+                // active one with a register this one can use.
+                let victim = active
+                    .iter()
+                    .rposition(|(_, reg)| !spans_call || abi::CALLEE_SAVED.contains(reg))
+                    .filter(|&i| active[i].0.end > interval.end);
+                let Some(victim) = victim else {
+                    spill(interval.vreg, &mut locations);
+                    continue;
+                };
+                let (victim, reg) = active.remove(victim);
+                spill(victim.vreg, &mut locations);
+                reg
+            }
+        };
+        if abi::CALLEE_SAVED.contains(&reg) && !used_callee.contains(&reg) {
+            used_callee.push(reg);
+        }
+        locations[interval.vreg.index()] = Some(Location::Reg(reg));
+        let at = active.partition_point(|(active, _)| active.end <= interval.end);
+        active.insert(at, (interval, reg));
+    }
+    Allocation {
+        locations,
+        saved: callee_saved()
+            .filter(|reg| used_callee.contains(reg))
+            .collect(),
+        slots,
+    }
+}
+
+/// Gives every virtual register in `function` a stack slot of its own.
+pub(crate) fn spill(function: &Function) -> Allocation {
+    let slots = function.types.len() as u32;
+    Allocation {
+        locations: (0..slots).map(|slot| Some(Location::Stack(slot))).collect(),
+        saved: Vec::new(),
+        slots,
+    }
+}
+
+/// The interval of every register that's used, sorted by where they start,
+/// and the positions calls read their arguments at.
+fn intervals(
+    function: &Function,
+    calls: impl Fn(&Instruction) -> Option<Call>,
+) -> (Vec<Interval>, Vec<usize>) {
+    let cfg = Cfg::new(function);
+    let liveness = Liveness::new(function, &cfg);
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; function.types.len()];
+    let mut cover = |vreg: VReg, position: usize| {
+        let range = ranges[vreg.index()].get_or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    };
+    let mut call_positions = Vec::new();
+    // Parameters arrive before the first instruction.
+    for param in &function.params {
+        cover(*param, 0);
+    }
+    // In block order, each block starts with a position of its own, where
+    // its live in registers are. Instructions read at one position and
+    // write at the next, calls clobber registers between the two.
+    let mut position = 1;
+    for (id, block) in function.block_ids().zip(&function.blocks) {
+        for vreg in liveness.live_in(id).iter() {
+            cover(vreg, position);
+        }
+        position += 1;
+        for instruction in &block.instructions {
+            let call = calls(instruction);
+            // Operands read after a call have to survive it.
+            let read = match call {
+                Some(Call::Check) => position + 1,
+                _ => position,
+            };
+            for vreg in instruction.uses() {
+                cover(vreg, read);
+            }
+            if call.is_some() {
+                call_positions.push(position);
+            }
+            if let Some(dst) = instruction.dst() {
+                cover(dst, position + 1);
+            }
+            position += 2;
+        }
+        if let Some(vreg) = block.terminator.uses() {
+            cover(vreg, position);
+        }
+        for vreg in liveness.live_out(id).iter() {
+            cover(vreg, position);
+        }
+        position += 1;
+    }
+    let mut intervals: Vec<_> = ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, range)| {
+            let (start, end) = range?;
+            Some(Interval {
+                vreg: VReg(i as u32),
+                start,
+                end,
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| interval.start);
+    (intervals, call_positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{builder::Builder, BinaryOp, Constant, Terminator, Ty};
+
+    /// Calls are calls, nothing else goes through the runtime.
+    fn calls(instruction: &Instruction) -> Option<Call> {
+        matches!(instruction, Instruction::Call { .. }).then_some(Call::Last)
+    }
+    fn constants(builder: &mut Builder, count: usize) -> Vec<VReg> {
+        (0..count)
+            .map(|i| builder.constant(Constant::Number(i as f64)))
+            .collect()
+    }
+    fn registers(allocation: &Allocation, vregs: &[VReg]) -> Vec<Location> {
+        vregs
+            .iter()
+            .map(|vreg| allocation.location(*vreg))
+            .collect()
+    }
+
+    #[test]
+    fn intervals_spanning_calls_get_callee_saved_registers() {
+        let mut f = Builder::new("f");
+        let kept = f.constant(Constant::Number(1.0));
+        let result = f.call("g", Vec::new(), 1);
+        f.print(kept);
+        f.print(result);
+        f.terminate(Terminator::Return(None));
+        let allocation = allocate(&f.finish(), calls);
+        assert_eq!(allocation.location(kept), Location::Reg(Reg::Rbx));
+        assert_eq!(allocation.location(result), Location::Reg(Reg::R8));
+        assert_eq!(allocation.saved, vec![Reg::Rbx]);
+        assert_eq!(allocation.slots, 0);
+    }
+
+    #[test]
+    fn operands_checked_by_a_call_survive_it() {
+        let mut f = Builder::new("f");
+        let a = f.param(Ty::Value);
+        let b = f.param(Ty::Value);
+        let sum = f.binary(BinaryOp::Add, a, b, 1);
+        f.terminate(Terminator::Return(Some(sum)));
+        let function = f.finish();
+        let allocation = allocate(&function, |_| Some(Call::Check));
+        assert_eq!(
+            registers(&allocation, &[a, b, sum]),
+            vec![
+                Location::Reg(Reg::Rbx),
+                Location::Reg(Reg::R12),
+                Location::Reg(Reg::R8)
+            ]
+        );
+        let allocation = allocate(&function, |_| Some(Call::Last));
+        assert_eq!(
+            registers(&allocation, &[a, b]),
+            vec![Location::Reg(Reg::R8), Location::Reg(Reg::R9)]
+        );
+        assert!(allocation.saved.is_empty());
+    }
+
+    #[test]
+    fn spills_the_interval_ending_last() {
+        // Ten registers live at once, one more than there are to hand out.
+        let mut f = Builder::new("f");
+        let vregs = constants(&mut f, 10);
+        for vreg in vregs.iter().rev() {
+            f.print(*vreg);
+        }
+        f.terminate(Terminator::Return(None));
+        let allocation = allocate(&f.finish(), calls);
+        assert_eq!(
+            registers(&allocation, &vregs),
+            vec![
+                Location::Stack(0),
+                Location::Reg(Reg::R9),
+                Location::Reg(Reg::R10),
+                Location::Reg(Reg::R11),
+                Location::Reg(Reg::Rbx),
+                Location::Reg(Reg::R12),
+                Location::Reg(Reg::R13),
+                Location::Reg(Reg::R14),
+                Location::Reg(Reg::R15),
+                Location::Reg(Reg::R8),
+            ]
+        );
+        assert_eq!(allocation.slots, 1);
+    }
+
+    #[test]
+    fn spills_the_new_interval_if_it_ends_last() {
+        let mut f = Builder::new("f");
+        let vregs = constants(&mut f, 10);
+        for vreg in &vregs {
+            f.print(*vreg);
+        }
+        f.terminate(Terminator::Return(None));
+        let allocation = allocate(&f.finish(), calls);
+        assert_eq!(allocation.location(vregs[9]), Location::Stack(0));
+        assert!(vregs[..9]
+            .iter()
+            .all(|vreg| matches!(allocation.location(*vreg), Location::Reg(_))));
+        assert_eq!(allocation.slots, 1);
+    }
+
+    #[test]
+    fn spills_only_callee_saved_registers_across_calls() {
+        // Six registers live across a call, one more than there are callee
+        // saved ones, while the caller saved ones are all free.
+        let mut f = Builder::new("f");
+        let vregs = constants(&mut f, 5);
+        let crossing = f.constant(Constant::Number(5.0));
+        let result = f.call("g", Vec::new(), 1);
+        f.print(crossing);
+        for vreg in vregs.iter().rev() {
+            f.print(*vreg);
+        }
+        f.print(result);
+        f.terminate(Terminator::Return(None));
+        let allocation = allocate(&f.finish(), calls);
+        assert_eq!(
+            registers(&allocation, &vregs),
+            vec![
+                Location::Stack(0),
+                Location::Reg(Reg::R12),
+                Location::Reg(Reg::R13),
+                Location::Reg(Reg::R14),
+                Location::Reg(Reg::R15),
+            ]
+        );
+        assert_eq!(allocation.location(crossing), Location::Reg(Reg::Rbx));
+        assert_eq!(allocation.location(result), Location::Reg(Reg::R8));
+        assert_eq!(
+            allocation.saved,
+            vec![Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15]
+        );
+    }
+
+    #[test]
+    fn saved_lists_the_callee_saved_registers_used_in_order() {
+        // The caller saved registers go first, then rbx and r12.
+        let mut f = Builder::new("f");
+        let vregs = constants(&mut f, 6);
+        for vreg in &vregs {
+            f.print(*vreg);
+        }
+        f.terminate(Terminator::Return(None));
+        let allocation = allocate(&f.finish(), calls);
+        assert_eq!(allocation.saved, vec![Reg::Rbx, Reg::R12]);
+        assert_eq!(allocation.slots, 0);
+
+        let mut f = Builder::new("f");
+        let vregs = constants(&mut f, 4);
+        for vreg in &vregs {
+            f.print(*vreg);
+        }
+        f.terminate(Terminator::Return(None));
+        assert!(allocate(&f.finish(), calls).saved.is_empty());
+    }
+}
@@ -1,8 +1,8 @@
-
-use std::fmt;
 use super::Directive;
+use std::fmt;
 #[allow(dead_code)]
 /// x86_64 Regesters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum Reg {
     /// a extended 64-bit regester
     Rax,
@@ -191,6 +191,26 @@ impl fmt::Display for Reg {
         }
     }
 }
+impl Reg {
+    /// Width of the regester in bytes.
+    pub(crate) fn size(&self) -> u8 {
+        match self {
+            Self::Al | Self::Ah | Self::Bl | Self::Bh => 1,
+            Self::Cl | Self::Ch | Self::Dl | Self::Dh => 1,
+            Self::R8b | Self::R9b | Self::R10b | Self::R11b => 1,
+            Self::R12b | Self::R13b | Self::R14b | Self::R15b => 1,
+            Self::Ax | Self::Bx | Self::Cx | Self::Dx => 2,
+            Self::R8w | Self::R9w | Self::R10w | Self::R11w => 2,
+            Self::R12w | Self::R13w | Self::R14w | Self::R15w => 2,
+            Self::Eax | Self::Ebx | Self::Ecx | Self::Edx => 4,
+            Self::R8d | Self::R9d | Self::R10d | Self::R11d => 4,
+            Self::R12d | Self::R13d | Self::R14d | Self::R15d => 4,
+            _ => 8,
+        }
+    }
+}
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum Regester {
     Directive(Directive),
     Deref(Reg),
@@ -216,3 +236,36 @@ impl From<Reg> for Regester {
         Self::Regester(value)
     }
 }
+
+#[allow(dead_code)]
+/// SSE regesters, used for floating point math.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Xmm {
+    Xmm0,
+    Xmm1,
+    Xmm2,
+    Xmm3,
+    Xmm4,
+    Xmm5,
+    Xmm6,
+    Xmm7,
+    Xmm8,
+    Xmm9,
+    Xmm10,
+    Xmm11,
+    Xmm12,
+    Xmm13,
+    Xmm14,
+    Xmm15,
+}
+impl Xmm {
+    /// The regester number used in the instruction encoding.
+    pub(crate) fn number(&self) -> u8 {
+        *self as u8
+    }
+}
+impl fmt::Display for Xmm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "xmm{}", self.number())
+    }
+}
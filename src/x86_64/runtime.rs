@@ -0,0 +1,772 @@
+//! Routines compiled programs call into, written directly as [`OpCode`]s.
+//!
+//! Lox values are NaN-boxed into 64 bits: numbers are plain doubles, the
+//! other values hide in the payload of a quiet NaN that arithmetic never
+//! produces. A string value carries a pointer to its length followed by
+//! its bytes. The routines follow the System V calling convention.
+use super::{
+    abi::{self, Target},
+    Cond, Directive, OpCode, Reg, Regester, Syscall, Value, Xmm,
+};
+use crate::{
+    ir::{Blob, Section},
+    value::MAX_DEPTH,
+};
+
+/// The bits every boxed value that isn't a number has set.
+pub(crate) const QNAN: u64 = 0x7ffc_0000_0000_0000;
+pub(crate) const SIGN: u64 = 1 << 63;
+pub(crate) const NIL: u64 = QNAN | 1;
+pub(crate) const FALSE: u64 = QNAN | 2;
+pub(crate) const TRUE: u64 = QNAN | 3;
+/// The tag of a string, the low 48 bits are the pointer.
+pub(crate) const STRING: u64 = SIGN | QNAN;
+pub(crate) const POINTER: u64 = 0x0000_ffff_ffff_ffff;
+
+/// Bytes the freestanding allocator hands out before giving up.
+const HEAP_SIZE: u32 = 64 << 20;
+
+/// `lox_print(value)`, prints a value and a newline.
+pub(crate) const PRINT: &str = "lox_print";
+/// `lox_add(lhs, rhs, line) -> value`, adds numbers or concatenates strings.
+pub(crate) const ADD: &str = "lox_add";
+/// `lox_equal(lhs, rhs) -> bool value`
+pub(crate) const EQUAL: &str = "lox_equal";
+/// `lox_modulo(lhs, rhs) -> number`, the remainder of dividing two numbers.
+pub(crate) const MODULO: &str = "lox_modulo";
+/// `lox_check_number(value, line)`, fails unless `value` is a number.
+pub(crate) const CHECK_NUMBER: &str = "lox_check_number";
+/// `lox_check_numbers(lhs, rhs, line)`, fails unless both are numbers.
+pub(crate) const CHECK_NUMBERS: &str = "lox_check_numbers";
+/// `lox_enter(line)`, counts a call starting, failing if calls already
+/// nest [`MAX_DEPTH`] deep. Clobbers only rax, rcx and rsi.
+pub(crate) const ENTER: &str = "lox_enter";
+/// `lox_leave()`, counts a call returning. Clobbers only rax and rcx.
+pub(crate) const LEAVE: &str = "lox_leave";
+
+const WRITE: &str = "lox_write";
+const WRITE_OBJECT: &str = "lox_write_object";
+const EXIT: &str = "lox_exit";
+const ALLOC: &str = "lox_alloc";
+const COPY: &str = "lox_copy";
+const FATAL: &str = "lox_fatal";
+const RUNTIME_ERROR: &str = "lox_runtime_error";
+/// `lox_to_string(value) -> string value`, what `print` writes for a value.
+pub(crate) const TO_STRING: &str = "lox_to_string";
+const CONCAT: &str = "lox_concat";
+const NUMBER_TO_STRING: &str = "lox_number_to_string";
+const WRITE_DIGITS: &str = "lox_write_digits";
+const HEAP: &str = "lox_heap";
+const HEAP_USED: &str = "lox_heap_used";
+const DEPTH: &str = "lox_depth";
+
+/// Strings the routines print.
+const STRINGS: [(&str, &str); 11] = [
+    ("lox_s_nil", "nil"),
+    ("lox_s_true", "true"),
+    ("lox_s_false", "false"),
+    ("lox_s_newline", "\n"),
+    ("lox_s_line", "\n[line "),
+    ("lox_s_line_end", "]\n"),
+    ("lox_e_operand_number", "Operand must be a number."),
+    ("lox_e_operands_numbers", "Operands must be numbers."),
+    ("lox_e_add", "Operands must be two numbers or two strings."),
+    ("lox_e_memory", "Out of memory."),
+    ("lox_e_stack", "Stack overflow."),
+];
+
+pub(crate) struct Runtime {
+    pub(crate) text: Vec<Section>,
+    pub(crate) rodata: Vec<Blob>,
+    pub(crate) bss: Vec<Blob>,
+}
+
+/// The runtime for programs running on `target`.
+pub(crate) fn runtime(target: Target) -> Runtime {
+    let text = vec![
+        print(),
+        to_string(),
+        number_to_string(),
+        write_digits(),
+        add(),
+        concat(),
+        equal(),
+        check_number(),
+        check_numbers(),
+        modulo(),
+        enter(),
+        leave(),
+        runtime_error(),
+        fatal(),
+        copy(),
+        write_object(),
+        write(target),
+        exit(target),
+        alloc(target),
+    ];
+    let rodata = STRINGS
+        .iter()
+        .map(|(name, text)| string_object(name, text.as_bytes()))
+        .collect();
+    let mut bss = vec![Blob::from(vec![label(DEPTH), OpCode::Resb(8)])];
+    if target == Target::Freestanding {
+        bss.push(Blob::from(vec![
+            label(HEAP_USED),
+            OpCode::Resb(8),
+            label(HEAP),
+            OpCode::Resb(HEAP_SIZE as usize),
+        ]));
+    }
+    Runtime { text, rodata, bss }
+}
+
+/// A string laid out the way string values point at it, padded to 8 bytes.
+pub(crate) fn string_object(name: &str, bytes: &[u8]) -> Blob {
+    let mut code = vec![label(name), OpCode::Dq(bytes.len() as u64)];
+    if !bytes.is_empty() {
+        let mut bytes = bytes.to_vec();
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        code.push(OpCode::Db(bytes));
+    }
+    Blob::from(code)
+}
+
+fn print() -> Section {
+    Section::new(
+        PRINT,
+        vec![Blob::from(vec![
+            call(TO_STRING),
+            mov(Reg::Rsi, POINTER),
+            OpCode::And(Reg::Rsi.into(), Reg::Rax.into()),
+            mov(Reg::Rdi, 1u32),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 1u32),
+            OpCode::Lea(Reg::Rsi, "lox_s_newline".to_string()),
+            jump(WRITE_OBJECT),
+        ])],
+    )
+}
+
+/// `lox_to_string(value) -> string value`
+fn to_string() -> Section {
+    let mut code = is(Reg::Rdi, QNAN);
+    code.push(jump_if(Cond::Ne, NUMBER_TO_STRING));
+    code.extend(is(Reg::Rdi, STRING));
+    code.extend([
+        jump_if(Cond::Ne, "lox_to_string_constant"),
+        mov(Reg::Rax, Reg::Rdi),
+        OpCode::Ret,
+        label("lox_to_string_constant"),
+        OpCode::Lea(Reg::Rax, "lox_s_nil".to_string()),
+        mov(Reg::R11, NIL),
+        OpCode::Cmp(Reg::Rdi.into(), Reg::R11.into()),
+        jump_if(Cond::E, "lox_to_string_box"),
+        OpCode::Lea(Reg::Rax, "lox_s_true".to_string()),
+        mov(Reg::R11, TRUE),
+        OpCode::Cmp(Reg::Rdi.into(), Reg::R11.into()),
+        jump_if(Cond::E, "lox_to_string_box"),
+        OpCode::Lea(Reg::Rax, "lox_s_false".to_string()),
+        label("lox_to_string_box"),
+        mov(Reg::R11, STRING),
+        OpCode::Add(Reg::Rax.into(), Reg::R11.into()),
+        OpCode::Ret,
+    ]);
+    Section::new(TO_STRING, vec![Blob::from(code)])
+}
+
+/// `lox_number_to_string(number) -> string value`
+///
+/// Numbers below 10^12 are written with up to six decimals and no trailing
+/// zeros, larger and tiny ones as a mantissa and a power of ten, e.g. `1e15`.
+fn number_to_string() -> Section {
+    // The characters are collected in a 48 byte buffer at [rbp-64], r8 is
+    // the cursor, xmm0 the magnitude and r9 the exponent.
+    let buffer = -64;
+    let mut code = vec![
+        OpCode::Push(Reg::Rbp.into()),
+        mov(Reg::Rbp, Reg::Rsp),
+        OpCode::Sub(Reg::Rsp.into(), 64u32.into()),
+        mov(Reg::R8, Reg::Rbp),
+        OpCode::Sub(Reg::R8.into(), 64u32.into()),
+        mov(Reg::Rax, i64::MAX as u64),
+        OpCode::And(Reg::Rax.into(), Reg::Rdi.into()),
+        mov(Reg::R11, f64::INFINITY.to_bits()),
+        OpCode::Cmp(Reg::Rax.into(), Reg::R11.into()),
+        jump_if(Cond::A, "lox_number_nan"),
+        OpCode::Cmp(Reg::Rdi.into(), 0u32.into()),
+        jump_if(Cond::Ge, "lox_number_positive"),
+    ];
+    code.extend(put("-"));
+    code.extend([
+        label("lox_number_positive"),
+        OpCode::Cmp(Reg::Rax.into(), Reg::R11.into()),
+        jump_if(Cond::E, "lox_number_infinite"),
+        OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rax),
+        OpCode::Xor(Reg::R9.into(), Reg::R9.into()),
+        mov(Reg::R11, 1e12f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::R11),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+        jump_if(Cond::B, "lox_number_small"),
+        mov(Reg::R11, 10f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::R11),
+        label("lox_number_shrink"),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+        jump_if(Cond::B, "lox_number_digits"),
+        OpCode::Divsd(Xmm::Xmm0, Xmm::Xmm1),
+        OpCode::Add(Reg::R9.into(), 1u32.into()),
+        jump("lox_number_shrink"),
+        label("lox_number_small"),
+        mov(Reg::R11, 1e-4f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::R11),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+        jump_if(Cond::Ae, "lox_number_digits"),
+        OpCode::Cmp(Reg::Rax.into(), 0u32.into()),
+        jump_if(Cond::E, "lox_number_digits"),
+        mov(Reg::R11, 1f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::R11),
+        mov(Reg::R11, 10f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm2, Reg::R11),
+        label("lox_number_grow"),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+        jump_if(Cond::Ae, "lox_number_digits"),
+        OpCode::Mulsd(Xmm::Xmm0, Xmm::Xmm2),
+        OpCode::Sub(Reg::R9.into(), 1u32.into()),
+        jump("lox_number_grow"),
+        // Six decimals as an integer, rounded to nearest even.
+        label("lox_number_digits"),
+        mov(Reg::R11, 1e6f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::R11),
+        OpCode::Mulsd(Xmm::Xmm0, Xmm::Xmm1),
+        OpCode::Cvtsd2si(Reg::Rax, Xmm::Xmm0),
+        OpCode::Xor(Reg::Rdx.into(), Reg::Rdx.into()),
+        mov(Reg::R11, 1_000_000u32),
+        OpCode::Div(Reg::R11),
+        mov(Reg::R10, Reg::Rdx),
+        mov(Reg::Rcx, 1u32),
+        call(WRITE_DIGITS),
+        OpCode::Cmp(Reg::R10.into(), 0u32.into()),
+        jump_if(Cond::E, "lox_number_exponent"),
+    ]);
+    code.extend(put("."));
+    code.extend([
+        mov(Reg::Rax, Reg::R10),
+        mov(Reg::Rcx, 6u32),
+        call(WRITE_DIGITS),
+        label("lox_number_trim"),
+        OpCode::Cmp(
+            Directive::byte(Reg::R8, true, Some(-1)).into(),
+            u32::from(b'0').into(),
+        ),
+        jump_if(Cond::Ne, "lox_number_exponent"),
+        OpCode::Sub(Reg::R8.into(), 1u32.into()),
+        jump("lox_number_trim"),
+        label("lox_number_exponent"),
+        OpCode::Cmp(Reg::R9.into(), 0u32.into()),
+        jump_if(Cond::E, "lox_number_done"),
+    ]);
+    code.extend(put("e"));
+    code.extend([
+        mov(Reg::Rax, Reg::R9),
+        OpCode::Cmp(Reg::R9.into(), 0u32.into()),
+        jump_if(Cond::G, "lox_number_exponent_digits"),
+    ]);
+    code.extend(put("-"));
+    code.extend([
+        OpCode::Xor(Reg::Rax.into(), Reg::Rax.into()),
+        OpCode::Sub(Reg::Rax.into(), Reg::R9.into()),
+        label("lox_number_exponent_digits"),
+        mov(Reg::Rcx, 1u32),
+        call(WRITE_DIGITS),
+        jump("lox_number_done"),
+        label("lox_number_nan"),
+    ]);
+    code.extend(put("nan"));
+    code.extend([jump("lox_number_done"), label("lox_number_infinite")]);
+    code.extend(put("inf"));
+    code.extend([
+        // Copy the buffer into a new string.
+        label("lox_number_done"),
+        mov(Reg::Rax, Reg::R8),
+        OpCode::Sub(Reg::Rax.into(), Reg::Rbp.into()),
+        OpCode::Add(Reg::Rax.into(), ((-buffer) as u32).into()),
+        mov(local(-8), Reg::Rax),
+        mov(Reg::Rdi, Reg::Rax),
+        OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+        call(ALLOC),
+        mov(Reg::Rdx, local(-8)),
+        mov(Regester::Deref(Reg::Rax), Reg::Rdx),
+        mov(Reg::Rdi, Reg::Rax),
+        OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+        mov(Reg::Rsi, Reg::Rbp),
+        OpCode::Sub(Reg::Rsi.into(), ((-buffer) as u32).into()),
+        call(COPY),
+        mov(Reg::R11, STRING),
+        OpCode::Add(Reg::Rax.into(), Reg::R11.into()),
+        mov(Reg::Rsp, Reg::Rbp),
+        OpCode::Pop(Reg::Rbp.into()),
+        OpCode::Ret,
+    ]);
+    Section::new(NUMBER_TO_STRING, vec![Blob::from(code)])
+}
+
+/// Writes the decimal digits of rax at r8, at least rcx of them, and moves
+/// r8 past them. Clobbers rax, rdx, rsi and r11.
+fn write_digits() -> Section {
+    Section::new(
+        WRITE_DIGITS,
+        vec![Blob::from(vec![
+            OpCode::Xor(Reg::Rsi.into(), Reg::Rsi.into()),
+            mov(Reg::R11, 10u32),
+            label("lox_write_digits_divide"),
+            OpCode::Xor(Reg::Rdx.into(), Reg::Rdx.into()),
+            OpCode::Div(Reg::R11),
+            OpCode::Add(Reg::Rdx.into(), u32::from(b'0').into()),
+            OpCode::Push(Reg::Rdx.into()),
+            OpCode::Add(Reg::Rsi.into(), 1u32.into()),
+            OpCode::Cmp(Reg::Rax.into(), 0u32.into()),
+            jump_if(Cond::Ne, "lox_write_digits_divide"),
+            OpCode::Cmp(Reg::Rsi.into(), Reg::Rcx.into()),
+            jump_if(Cond::B, "lox_write_digits_divide"),
+            label("lox_write_digits_store"),
+            OpCode::Pop(Reg::Rdx.into()),
+            mov(Directive::byte(Reg::R8, true, None), Reg::Dl),
+            OpCode::Add(Reg::R8.into(), 1u32.into()),
+            OpCode::Sub(Reg::Rsi.into(), 1u32.into()),
+            jump_if(Cond::Ne, "lox_write_digits_store"),
+            OpCode::Ret,
+        ])],
+    )
+}
+
+fn add() -> Section {
+    let mut code = is(Reg::Rdi, QNAN);
+    code.push(jump_if(Cond::E, "lox_add_strings"));
+    code.extend(is(Reg::Rsi, QNAN));
+    code.extend([
+        jump_if(Cond::E, "lox_add_error"),
+        OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rdi),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::Rsi),
+        OpCode::Addsd(Xmm::Xmm0, Xmm::Xmm1),
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm0),
+        OpCode::Ret,
+        label("lox_add_strings"),
+    ]);
+    code.extend(is(Reg::Rdi, STRING));
+    code.push(jump_if(Cond::Ne, "lox_add_error"));
+    code.extend(is(Reg::Rsi, STRING));
+    code.extend([
+        jump_if(Cond::E, CONCAT),
+        label("lox_add_error"),
+        OpCode::Lea(Reg::Rdi, "lox_e_add".to_string()),
+        mov(Reg::Rsi, Reg::Rdx),
+        jump(RUNTIME_ERROR),
+    ]);
+    Section::new(ADD, vec![Blob::from(code)])
+}
+
+/// `lox_concat(lhs, rhs) -> string value`, both have to be strings.
+fn concat() -> Section {
+    Section::new(
+        CONCAT,
+        vec![Blob::from(vec![
+            OpCode::Push(Reg::R12.into()),
+            OpCode::Push(Reg::R13.into()),
+            OpCode::Push(Reg::R14.into()),
+            mov(Reg::R11, POINTER),
+            mov(Reg::R12, Reg::Rdi),
+            OpCode::And(Reg::R12.into(), Reg::R11.into()),
+            mov(Reg::R13, Reg::Rsi),
+            OpCode::And(Reg::R13.into(), Reg::R11.into()),
+            mov(Reg::R14, Regester::Deref(Reg::R12)),
+            OpCode::Add(Reg::R14.into(), Regester::Deref(Reg::R13).into()),
+            mov(Reg::Rdi, Reg::R14),
+            OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+            call(ALLOC),
+            mov(Regester::Deref(Reg::Rax), Reg::R14),
+            mov(Reg::R14, Reg::Rax),
+            mov(Reg::Rdi, Reg::Rax),
+            OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+            mov(Reg::Rdx, Regester::Deref(Reg::R12)),
+            mov(Reg::Rsi, Reg::R12),
+            OpCode::Add(Reg::Rsi.into(), 8u32.into()),
+            call(COPY),
+            mov(Reg::Rdx, Regester::Deref(Reg::R13)),
+            mov(Reg::Rsi, Reg::R13),
+            OpCode::Add(Reg::Rsi.into(), 8u32.into()),
+            call(COPY),
+            mov(Reg::Rax, STRING),
+            OpCode::Add(Reg::Rax.into(), Reg::R14.into()),
+            OpCode::Pop(Reg::R14.into()),
+            OpCode::Pop(Reg::R13.into()),
+            OpCode::Pop(Reg::R12.into()),
+            OpCode::Ret,
+        ])],
+    )
+}
+
+/// Numbers compare by value (NaN isn't equal to itself), strings by their
+/// contents and everything else by identity.
+fn equal() -> Section {
+    let mut code = is(Reg::Rdi, QNAN);
+    code.push(jump_if(Cond::E, "lox_equal_other"));
+    code.extend(is(Reg::Rsi, QNAN));
+    code.extend([
+        jump_if(Cond::E, "lox_equal_false"),
+        OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rdi),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::Rsi),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm1),
+        jump_if(Cond::Ne, "lox_equal_false"),
+        jump_if(Cond::P, "lox_equal_false"),
+        jump("lox_equal_true"),
+        label("lox_equal_other"),
+        OpCode::Cmp(Reg::Rdi.into(), Reg::Rsi.into()),
+        jump_if(Cond::E, "lox_equal_true"),
+    ]);
+    code.extend(is(Reg::Rdi, STRING));
+    code.push(jump_if(Cond::Ne, "lox_equal_false"));
+    code.extend(is(Reg::Rsi, STRING));
+    code.extend([
+        jump_if(Cond::Ne, "lox_equal_false"),
+        mov(Reg::R11, POINTER),
+        OpCode::And(Reg::Rdi.into(), Reg::R11.into()),
+        OpCode::And(Reg::Rsi.into(), Reg::R11.into()),
+        mov(Reg::Rdx, Regester::Deref(Reg::Rdi)),
+        OpCode::Cmp(Reg::Rdx.into(), Regester::Deref(Reg::Rsi).into()),
+        jump_if(Cond::Ne, "lox_equal_false"),
+        OpCode::Add(Reg::Rdi.into(), 8u32.into()),
+        OpCode::Add(Reg::Rsi.into(), 8u32.into()),
+        label("lox_equal_bytes"),
+        OpCode::Cmp(Reg::Rdx.into(), 0u32.into()),
+        jump_if(Cond::E, "lox_equal_true"),
+        mov(Reg::Al, Regester::Deref(Reg::Rdi)),
+        OpCode::Cmp(Reg::Al.into(), Regester::Deref(Reg::Rsi).into()),
+        jump_if(Cond::Ne, "lox_equal_false"),
+        OpCode::Add(Reg::Rdi.into(), 1u32.into()),
+        OpCode::Add(Reg::Rsi.into(), 1u32.into()),
+        OpCode::Sub(Reg::Rdx.into(), 1u32.into()),
+        jump("lox_equal_bytes"),
+        label("lox_equal_true"),
+        mov(Reg::Rax, TRUE),
+        OpCode::Ret,
+        label("lox_equal_false"),
+        mov(Reg::Rax, FALSE),
+        OpCode::Ret,
+    ]);
+    Section::new(EQUAL, vec![Blob::from(code)])
+}
+
+fn check_number() -> Section {
+    let mut code = is(Reg::Rdi, QNAN);
+    code.extend([
+        jump_if(Cond::E, "lox_check_number_error"),
+        OpCode::Ret,
+        label("lox_check_number_error"),
+        OpCode::Lea(Reg::Rdi, "lox_e_operand_number".to_string()),
+        jump(RUNTIME_ERROR),
+    ]);
+    Section::new(CHECK_NUMBER, vec![Blob::from(code)])
+}
+
+fn check_numbers() -> Section {
+    let mut code = is(Reg::Rdi, QNAN);
+    code.push(jump_if(Cond::E, "lox_check_numbers_error"));
+    code.extend(is(Reg::Rsi, QNAN));
+    code.extend([
+        jump_if(Cond::E, "lox_check_numbers_error"),
+        OpCode::Ret,
+        label("lox_check_numbers_error"),
+        OpCode::Lea(Reg::Rdi, "lox_e_operands_numbers".to_string()),
+        mov(Reg::Rsi, Reg::Rdx),
+        jump(RUNTIME_ERROR),
+    ]);
+    Section::new(CHECK_NUMBERS, vec![Blob::from(code)])
+}
+
+/// Like C's `fmod`, subtracts the divisor doubled as often as fits from
+/// the dividend's magnitude, then halved again down to the divisor. Each
+/// subtraction is exact, the difference being at most what's subtracted.
+fn modulo() -> Section {
+    let code = vec![
+        // xmm0 = |lhs|, xmm1 = |rhs|
+        mov(Reg::R11, !SIGN),
+        mov(Reg::Rax, Reg::Rdi),
+        OpCode::And(Reg::Rax.into(), Reg::R11.into()),
+        OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rax),
+        mov(Reg::Rax, Reg::Rsi),
+        OpCode::And(Reg::Rax.into(), Reg::R11.into()),
+        OpCode::MovqToXmm(Xmm::Xmm1, Reg::Rax),
+        // NaN for a NaN or zero divisor and a NaN or infinite dividend.
+        OpCode::Ucomisd(Xmm::Xmm1, Xmm::Xmm1),
+        jump_if(Cond::P, "lox_modulo_nan"),
+        OpCode::Xor(Reg::Rax.into(), Reg::Rax.into()),
+        OpCode::MovqToXmm(Xmm::Xmm2, Reg::Rax),
+        OpCode::Ucomisd(Xmm::Xmm1, Xmm::Xmm2),
+        jump_if(Cond::E, "lox_modulo_nan"),
+        mov(Reg::Rax, f64::INFINITY.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm2, Reg::Rax),
+        // Unordered sets zf too.
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm2),
+        jump_if(Cond::E, "lox_modulo_nan"),
+        // xmm2 = the largest |rhs| * 2^n not above |lhs|
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm1),
+        OpCode::MovqToXmm(Xmm::Xmm2, Reg::Rax),
+        label("lox_modulo_double"),
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm2),
+        OpCode::MovqToXmm(Xmm::Xmm3, Reg::Rax),
+        OpCode::Addsd(Xmm::Xmm3, Xmm::Xmm2),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm3),
+        jump_if(Cond::B, "lox_modulo_subtract"),
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm3),
+        OpCode::MovqToXmm(Xmm::Xmm2, Reg::Rax),
+        jump("lox_modulo_double"),
+        label("lox_modulo_subtract"),
+        OpCode::Ucomisd(Xmm::Xmm0, Xmm::Xmm2),
+        jump_if(Cond::B, "lox_modulo_halve"),
+        OpCode::Subsd(Xmm::Xmm0, Xmm::Xmm2),
+        label("lox_modulo_halve"),
+        OpCode::Ucomisd(Xmm::Xmm2, Xmm::Xmm1),
+        jump_if(Cond::E, "lox_modulo_sign"),
+        mov(Reg::Rax, 0.5f64.to_bits()),
+        OpCode::MovqToXmm(Xmm::Xmm3, Reg::Rax),
+        OpCode::Mulsd(Xmm::Xmm2, Xmm::Xmm3),
+        jump("lox_modulo_subtract"),
+        // The remainder has the dividend's sign.
+        label("lox_modulo_sign"),
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm0),
+        OpCode::Cmp(Reg::Rdi.into(), 0u32.into()),
+        jump_if(Cond::Ge, "lox_modulo_done"),
+        mov(Reg::R11, SIGN),
+        OpCode::Xor(Reg::Rax.into(), Reg::R11.into()),
+        label("lox_modulo_done"),
+        OpCode::Ret,
+        label("lox_modulo_nan"),
+        OpCode::Xor(Reg::Rax.into(), Reg::Rax.into()),
+        OpCode::MovqToXmm(Xmm::Xmm0, Reg::Rax),
+        OpCode::Divsd(Xmm::Xmm0, Xmm::Xmm0),
+        OpCode::MovqFromXmm(Reg::Rax, Xmm::Xmm0),
+        OpCode::Ret,
+    ];
+    Section::new(MODULO, vec![Blob::from(code)])
+}
+
+fn enter() -> Section {
+    Section::new(
+        ENTER,
+        vec![Blob::from(vec![
+            OpCode::Lea(Reg::Rcx, DEPTH.to_string()),
+            mov(Reg::Rax, Regester::Deref(Reg::Rcx)),
+            OpCode::Cmp(Reg::Rax.into(), (MAX_DEPTH as u32).into()),
+            jump_if(Cond::Ae, "lox_enter_overflow"),
+            OpCode::Add(Reg::Rax.into(), 1u32.into()),
+            mov(Regester::Deref(Reg::Rcx), Reg::Rax),
+            OpCode::Ret,
+            label("lox_enter_overflow"),
+            mov(Reg::Rsi, Reg::Rdi),
+            OpCode::Lea(Reg::Rdi, "lox_e_stack".to_string()),
+            jump(RUNTIME_ERROR),
+        ])],
+    )
+}
+
+fn leave() -> Section {
+    Section::new(
+        LEAVE,
+        vec![Blob::from(vec![
+            OpCode::Lea(Reg::Rcx, DEPTH.to_string()),
+            mov(Reg::Rax, Regester::Deref(Reg::Rcx)),
+            OpCode::Sub(Reg::Rax.into(), 1u32.into()),
+            mov(Regester::Deref(Reg::Rcx), Reg::Rax),
+            OpCode::Ret,
+        ])],
+    )
+}
+
+/// `lox_runtime_error(message, line)`, reports the error on stderr and exits
+/// with 70. `message` points at a string, it isn't a value.
+fn runtime_error() -> Section {
+    Section::new(
+        RUNTIME_ERROR,
+        vec![Blob::from(vec![
+            OpCode::Push(Reg::R12.into()),
+            mov(Reg::R12, Reg::Rsi),
+            mov(Reg::Rsi, Reg::Rdi),
+            mov(Reg::Rdi, 2u32),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 2u32),
+            OpCode::Lea(Reg::Rsi, "lox_s_line".to_string()),
+            call(WRITE_OBJECT),
+            OpCode::Cvtsi2sd(Xmm::Xmm0, Reg::R12),
+            OpCode::MovqFromXmm(Reg::Rdi, Xmm::Xmm0),
+            call(NUMBER_TO_STRING),
+            mov(Reg::Rsi, POINTER),
+            OpCode::And(Reg::Rsi.into(), Reg::Rax.into()),
+            mov(Reg::Rdi, 2u32),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 2u32),
+            OpCode::Lea(Reg::Rsi, "lox_s_line_end".to_string()),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 70u32),
+            jump(EXIT),
+        ])],
+    )
+}
+
+/// `lox_fatal(message)`, like [`runtime_error`] for errors without a line.
+fn fatal() -> Section {
+    Section::new(
+        FATAL,
+        vec![Blob::from(vec![
+            mov(Reg::Rsi, Reg::Rdi),
+            mov(Reg::Rdi, 2u32),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 2u32),
+            OpCode::Lea(Reg::Rsi, "lox_s_newline".to_string()),
+            call(WRITE_OBJECT),
+            mov(Reg::Rdi, 70u32),
+            jump(EXIT),
+        ])],
+    )
+}
+
+/// `lox_copy(destination, source, count)`, leaves rdi just past the copied
+/// bytes and rax untouched.
+fn copy() -> Section {
+    Section::new(
+        COPY,
+        vec![Blob::from(vec![
+            OpCode::Cmp(Reg::Rdx.into(), 0u32.into()),
+            jump_if(Cond::E, "lox_copy_done"),
+            label("lox_copy_byte"),
+            mov(Reg::Cl, Regester::Deref(Reg::Rsi)),
+            mov(Regester::Deref(Reg::Rdi), Reg::Cl),
+            OpCode::Add(Reg::Rsi.into(), 1u32.into()),
+            OpCode::Add(Reg::Rdi.into(), 1u32.into()),
+            OpCode::Sub(Reg::Rdx.into(), 1u32.into()),
+            jump_if(Cond::Ne, "lox_copy_byte"),
+            label("lox_copy_done"),
+            OpCode::Ret,
+        ])],
+    )
+}
+
+/// `lox_write_object(fd, string)`, `string` is a pointer, not a value.
+fn write_object() -> Section {
+    Section::new(
+        WRITE_OBJECT,
+        vec![Blob::from(vec![
+            mov(Reg::Rdx, Regester::Deref(Reg::Rsi)),
+            OpCode::Add(Reg::Rsi.into(), 8u32.into()),
+            jump(WRITE),
+        ])],
+    )
+}
+
+/// `lox_write(fd, buffer, count)`
+fn write(target: Target) -> Section {
+    let code = match target {
+        Target::Freestanding => Blob::from(vec![
+            mov(Reg::Rax, Syscall::Write),
+            OpCode::Syscall,
+            OpCode::Ret,
+        ]),
+        Target::Libc { .. } => Blob::from(
+            abi::call_c("write")
+                .into_iter()
+                .chain([OpCode::Ret])
+                .collect::<Vec<_>>(),
+        ),
+    };
+    Section::new(WRITE, vec![code])
+}
+
+/// `lox_exit(code)`
+fn exit(target: Target) -> Section {
+    let code = match target {
+        Target::Freestanding => Blob::from(vec![mov(Reg::Rax, Syscall::Exit), OpCode::Syscall]),
+        Target::Libc { .. } => Blob::from(
+            abi::call_c("exit")
+                .into_iter()
+                .chain([OpCode::Ud2])
+                .collect::<Vec<_>>(),
+        ),
+    };
+    Section::new(EXIT, vec![code])
+}
+
+/// `lox_alloc(size) -> pointer`, 8 byte aligned. Memory is never freed.
+fn alloc(target: Target) -> Section {
+    let code = match target {
+        // Bumps a pointer through a fixed heap in .bss.
+        Target::Freestanding => vec![
+            OpCode::Lea(Reg::Rcx, HEAP_USED.to_string()),
+            mov(Reg::Rax, Regester::Deref(Reg::Rcx)),
+            OpCode::Add(Reg::Rdi.into(), 7u32.into()),
+            OpCode::And(Reg::Rdi.into(), (-8i64 as u64).into()),
+            mov(Reg::Rdx, Reg::Rax),
+            OpCode::Add(Reg::Rdx.into(), Reg::Rdi.into()),
+            OpCode::Cmp(Reg::Rdx.into(), HEAP_SIZE.into()),
+            jump_if(Cond::A, "lox_alloc_failed"),
+            mov(Regester::Deref(Reg::Rcx), Reg::Rdx),
+            OpCode::Lea(Reg::Rdx, HEAP.to_string()),
+            OpCode::Add(Reg::Rax.into(), Reg::Rdx.into()),
+            OpCode::Ret,
+        ],
+        Target::Libc { .. } => abi::call_c("malloc")
+            .into_iter()
+            .chain([
+                OpCode::Cmp(Reg::Rax.into(), 0u32.into()),
+                jump_if(Cond::E, "lox_alloc_failed"),
+                OpCode::Ret,
+            ])
+            .collect(),
+    };
+    let failed = vec![
+        label("lox_alloc_failed"),
+        OpCode::Lea(Reg::Rdi, "lox_e_memory".to_string()),
+        jump(FATAL),
+    ];
+    Section::new(ALLOC, vec![Blob::from(code), Blob::from(failed)])
+}
+
+/// Sets zf if every bit of `tag` is set in `value`. Clobbers rax and r11.
+///
+/// With [`QNAN`] that means `value` isn't a number.
+fn is(value: Reg, tag: u64) -> Vec<OpCode> {
+    vec![
+        mov(Reg::Rax, value),
+        mov(Reg::R11, tag),
+        OpCode::And(Reg::Rax.into(), Reg::R11.into()),
+        OpCode::Cmp(Reg::Rax.into(), Reg::R11.into()),
+    ]
+}
+/// Stores `text` at r8 and moves r8 past it.
+fn put(text: &str) -> Vec<OpCode> {
+    let mut code = text
+        .bytes()
+        .enumerate()
+        .map(|(i, byte)| {
+            let offset = (i > 0).then_some(i as i32);
+            mov(Directive::byte(Reg::R8, true, offset), u32::from(byte))
+        })
+        .collect::<Vec<_>>();
+    code.push(OpCode::Add(Reg::R8.into(), (text.len() as u32).into()));
+    code
+}
+fn local(offset: i32) -> Directive {
+    Directive::qword(Reg::Rbp, true, Some(offset))
+}
+fn mov<D: Into<Regester>, S: Into<Value>>(dst: D, src: S) -> OpCode {
+    OpCode::Mov(dst.into(), src.into())
+}
+fn label(name: &str) -> OpCode {
+    OpCode::Label(name.to_string())
+}
+fn jump(name: &str) -> OpCode {
+    OpCode::Jmp(name.to_string())
+}
+fn jump_if(cond: Cond, name: &str) -> OpCode {
+    OpCode::Jcc(cond, name.to_string())
+}
+fn call(name: &str) -> OpCode {
+    OpCode::Call(name.to_string())
+}
@@ -0,0 +1,137 @@
+//! Runs every script in `tests/lox` compiled, with and without optimizations,
+//! through the IR interpreter and through the tree-walking interpreter, which
+//! all have to agree on stdout, stderr and the exit code. GNU as and linking
+//! against libc are checked too where `as`, `ld` and `cc` are installed.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn lox(args: &[&str], file: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("run")
+        .args(args)
+        .arg(file)
+        .output()
+        .expect("lox runs")
+}
+
+/// Whether `tool` can be run.
+fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
+
+/// Compiles `file` with `--libc --no-main` and links it into a C program
+/// calling the script, then runs that.
+fn linked_into_c(file: &Path, dir: &Path) -> Output {
+    let object = dir.join("script.o");
+    let compiled = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--libc", "--no-main", "--assembler=builtin", "-c", "-o"])
+        .arg(&object)
+        .arg(file)
+        .output()
+        .expect("lox runs");
+    if !compiled.status.success() {
+        return compiled;
+    }
+    let driver = dir.join("driver.c");
+    fs::write(
+        &driver,
+        "int lox_main(void);\nint main(void) { return lox_main(); }\n",
+    )
+    .unwrap();
+    let executable = dir.join("driver");
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&driver)
+        .arg(&object)
+        .status()
+        .expect("cc runs");
+    assert!(status.success(), "linking {} failed", file.display());
+    let mut run = Command::new(&executable)
+        .output()
+        .expect("the program runs");
+    // The warnings come from compiling.
+    run.stderr.splice(0..0, compiled.stderr);
+    run
+}
+
+/// A directory of our own under the system's temporary one, removed when
+/// dropped.
+struct TempDir(PathBuf);
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn compiled_matches_interpreter() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+
+    // The builtin assembler needs no external tools.
+    let mut legs = vec![
+        ("compiled", &["--assembler=builtin"][..]),
+        ("-O0", &["--assembler=builtin", "-O0"]),
+        ("tree-walk", &["--tree-walk"]),
+    ];
+    if installed("as") && installed("ld") {
+        legs.push(("gas", &["--assembler=gas"]));
+        legs.push(("gas intel", &["--assembler=gas", "--syntax=intel"]));
+    } else {
+        eprintln!("as or ld isn't installed, skipping the GNU as legs");
+    }
+    let cc = installed("cc");
+    if cc {
+        legs.push(("libc", &["--assembler=builtin", "--libc"]));
+    } else {
+        eprintln!("cc isn't installed, skipping the libc legs");
+    }
+    let dir = TempDir::new("complox-differential");
+
+    let mut failures = Vec::new();
+    for file in &files {
+        let expected = lox(&["--interp"], file);
+        let mut runs: Vec<_> = legs
+            .iter()
+            .map(|(name, args)| (*name, lox(args, file)))
+            .collect();
+        if cc {
+            runs.push(("no-main", linked_into_c(file, &dir.0)));
+        }
+        for (name, actual) in runs {
+            if actual.status.code() != expected.status.code()
+                || actual.stdout != expected.stdout
+                || actual.stderr != expected.stderr
+            {
+                failures.push(format!(
+                    "{}:\n  interpreter: {:?} {:?} {:?}\n  {name:<12} {:?} {:?} {:?}",
+                    file.display(),
+                    expected.status.code(),
+                    String::from_utf8_lossy(&expected.stdout),
+                    String::from_utf8_lossy(&expected.stderr),
+                    actual.status.code(),
+                    String::from_utf8_lossy(&actual.stdout),
+                    String::from_utf8_lossy(&actual.stderr),
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}